@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS items_idx_namespace_key_version;
+
+ALTER TABLE items DROP COLUMN IF EXISTS "version";
+
+CREATE TRIGGER set_timestamp
+BEFORE UPDATE ON items
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_timestamp();
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- Rows are append-only from here on: a write adds a version instead of updating a row.
+-- The trigger would also stamp the backfill below, and every later maintenance update,
+-- with the time of the update instead of the time of the write.
+DROP TRIGGER IF EXISTS set_timestamp ON items;
+
+ALTER TABLE items ADD COLUMN "version" BIGINT;
+
+UPDATE items SET "version" = numbered.version
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY namespace, key ORDER BY updated_at, id) AS version
+    FROM items
+) AS numbered
+WHERE items.id = numbered.id;
+
+ALTER TABLE items ALTER COLUMN "version" SET NOT NULL;
+
+CREATE UNIQUE INDEX items_idx_namespace_key_version
+ON items(namespace, key, "version");
@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+-- Without tombstones a deleted key would come back with its last value, so keys that are
+-- deleted now lose their whole history
+DELETE FROM items
+USING (
+    SELECT DISTINCT ON (namespace, key) namespace, key, deleted
+    FROM items
+    ORDER BY namespace, key, "version" DESC
+) AS latest
+WHERE latest.deleted
+  AND items.namespace = latest.namespace
+  AND items.key = latest.key;
+
+-- Earlier deletes of keys written again since only leave gaps in the history
+DELETE FROM items WHERE deleted;
+
+ALTER TABLE items DROP COLUMN IF EXISTS deleted;
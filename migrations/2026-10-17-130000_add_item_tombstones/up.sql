@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE items ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE items ADD COLUMN written_by TEXT;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+CREATE TABLE namespace_parents (
+    namespace TEXT PRIMARY KEY,
+    parent TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (namespace <> parent)
+);
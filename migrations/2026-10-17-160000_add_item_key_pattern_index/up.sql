@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Lets prefix scans (key LIKE 'db.%') use an index regardless of the database collation
+CREATE INDEX items_idx_namespace_key_pattern
+ON items(namespace, key text_pattern_ops);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS items_idx_expires_at;
+
+ALTER TABLE items DROP COLUMN IF EXISTS expires_at;
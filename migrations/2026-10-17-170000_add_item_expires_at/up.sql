@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+ALTER TABLE items ADD COLUMN expires_at TIMESTAMPTZ;
+
+-- Lets the reaper find expiring rows without scanning the table. It clears `expires_at`
+-- of the rows it is done with, so the index only holds values it may still act on.
+CREATE INDEX items_idx_expires_at
+ON items(expires_at)
+WHERE expires_at IS NOT NULL AND NOT deleted;
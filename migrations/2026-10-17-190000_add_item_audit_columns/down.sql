@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS items_idx_updated_at;
+
+ALTER TABLE items DROP COLUMN IF EXISTS comment;
+ALTER TABLE items DROP COLUMN IF EXISTS user_agent;
+ALTER TABLE items DROP COLUMN IF EXISTS source_ip;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+ALTER TABLE items ADD COLUMN source_ip TEXT;
+ALTER TABLE items ADD COLUMN user_agent TEXT;
+ALTER TABLE items ADD COLUMN comment TEXT;
+
+-- Audit queries filter by time across namespaces
+CREATE INDEX items_idx_updated_at
+ON items(updated_at);
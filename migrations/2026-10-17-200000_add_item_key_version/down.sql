@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+-- Without `key_version` sealed values would be read as plaintext, so reverting is refused
+-- while any are stored
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM items WHERE key_version IS NOT NULL) THEN
+    RAISE EXCEPTION 'items holds encrypted values, which would be read as plaintext without key_version';
+  END IF;
+END
+$$;
+
+ALTER TABLE items DROP COLUMN IF EXISTS key_version;
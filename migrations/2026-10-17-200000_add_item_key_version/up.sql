@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Version of the encryption key `val` is sealed with; NULL for plaintext values
+ALTER TABLE items ADD COLUMN key_version INTEGER;
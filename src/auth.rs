@@ -0,0 +1,422 @@
+// Authenticates requests with the PSKs from the environment or with tokens from the tokens
+// table. Token lookups are cached for LITTLE_LOOKUP_TOKEN_CACHE_SECS, so most requests do not
+// touch the table.
+
+use actix_web::{http::header, HttpRequest};
+use ahash::AHashMap;
+use chrono::Utc;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+use crate::db_connection::Pool;
+use crate::error::AppError;
+use crate::keys::compile_glob;
+use crate::models::token::{Scope, Token};
+use crate::util::{get_allow_query_psk, get_psk, get_token_cache_secs, PSKType};
+
+// Header carrying the PSK for clients that cannot send `Authorization`
+pub(crate) const PSK_HEADER: &str = "X-Little-Lookup-PSK";
+
+// Lookups kept before the cache starts over, so unknown secrets cannot grow it without bound
+const TOKEN_CACHE_CAPACITY: usize = 10_000;
+
+// A token with its namespace globs compiled
+struct CachedToken {
+    token: Token,
+    namespaces: Vec<Regex>,
+}
+
+#[derive(Default)]
+struct TokenCache {
+    // By SHA-256 of the secret; None remembers that no token has that secret
+    lookups: AHashMap<String, (Instant, Option<Arc<CachedToken>>)>,
+    any_issued: Option<(Instant, bool)>,
+}
+
+static TOKEN_CACHE: LazyLock<Mutex<TokenCache>> = LazyLock::new(Mutex::default);
+
+fn token_cache() -> MutexGuard<'static, TokenCache> {
+    TOKEN_CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Forgets every cached lookup, so token changes made by this server apply immediately
+pub fn clear_token_cache() {
+    *token_cache() = TokenCache::default();
+}
+
+fn is_fresh(fetched: Instant) -> bool {
+    fetched.elapsed() < Duration::from_secs(get_token_cache_secs())
+}
+
+fn lookup_token(secret: &str, pool: &Pool) -> Result<Option<Arc<CachedToken>>, AppError> {
+    let token_hash = Token::hash_secret(secret);
+    if let Some((fetched, cached)) = token_cache().lookups.get(&token_hash) {
+        if is_fresh(*fetched) {
+            return Ok(cached.clone());
+        }
+    }
+
+    let mut connection = pool.get()?;
+    let cached = Token::find_by_hash(&token_hash, &mut connection)?.map(|token| {
+        Arc::new(CachedToken {
+            // Patterns are checked when the token is created
+            namespaces: token
+                .namespaces
+                .iter()
+                .filter_map(|pattern| compile_glob(pattern).ok())
+                .collect(),
+            token,
+        })
+    });
+
+    let mut cache = token_cache();
+    if cache.lookups.len() >= TOKEN_CACHE_CAPACITY {
+        cache.lookups.clear();
+    }
+    cache
+        .lookups
+        .insert(token_hash, (Instant::now(), cached.clone()));
+    Ok(cached)
+}
+
+fn any_issued_tokens(pool: &Pool) -> Result<bool, AppError> {
+    if let Some((fetched, any_issued)) = token_cache().any_issued {
+        if is_fresh(fetched) {
+            return Ok(any_issued);
+        }
+    }
+
+    let mut connection = pool.get()?;
+    let any_issued = Token::any_issued(&mut connection)?;
+    token_cache().any_issued = Some((Instant::now(), any_issued));
+    Ok(any_issued)
+}
+
+// What a request's credential allows
+pub struct Access {
+    scope: Scope,
+    // The PSK or token secret the client sent; empty when it sent none
+    client_psk: String,
+    // Set when the credential is an active token with `scope`
+    token: Option<Arc<CachedToken>>,
+    tokens_issued: bool,
+}
+
+impl Access {
+    // Returns the caller's identity in `namespace`, or None when nothing there is configured to
+    // check. `namespace` None is for operations that are not about one namespace, such as
+    // managing tokens; they need the global PSK or a token covering `*`.
+    pub fn check(&self, namespace: Option<&str>) -> Result<Option<String>, AppError> {
+        if let Some(cached) = &self.token {
+            let covered = match namespace {
+                Some(namespace) => cached
+                    .namespaces
+                    .iter()
+                    .any(|pattern| pattern.is_match(namespace)),
+                None => cached.token.namespaces.iter().any(|pattern| pattern == "*"),
+            };
+            return match (covered, namespace) {
+                (true, _) => Ok(Some(format!("token:{}", cached.token.name))),
+                (false, Some(namespace)) => Err(AppError::Forbidden(format!(
+                    "The {} scope does not cover namespace '{}'",
+                    self.scope.name(),
+                    namespace
+                ))),
+                (false, None) => Err(AppError::Forbidden(format!(
+                    "The {} scope must cover every namespace",
+                    self.scope.name()
+                ))),
+            };
+        }
+
+        let psk_type = self.scope.psk_type();
+        let identity = match psk_type {
+            PSKType::READ => "psk-read",
+            PSKType::WRITE => "psk-write",
+            PSKType::ADMIN => "psk-admin",
+        };
+        let server_psk = get_psk(psk_type, namespace);
+
+        // Digests are compared in constant time, so the response time reveals neither how much
+        // of the PSK matched nor how long it is
+        if !server_psk.is_empty()
+            && bool::from(
+                Sha256::digest(self.client_psk.as_bytes())
+                    .ct_eq(&Sha256::digest(server_psk.as_bytes())),
+            )
+        {
+            return Ok(Some(String::from(identity)));
+        }
+
+        if server_psk.is_empty() && !self.tokens_issued {
+            // Unlike read and write, admin operations are disabled rather than open
+            if self.scope == Scope::Admin {
+                return Err(AppError::Forbidden(String::from(
+                    "Admin operations are disabled; set LITTLE_LOOKUP_PSK_ADMIN or issue an admin token",
+                )));
+            }
+            return Ok(None);
+        }
+
+        match self.client_psk.as_str() {
+            "" => Err(AppError::Unauthorized(String::from("PSK required"))),
+            _ => Err(AppError::Unauthorized(String::from("Incorrect PSK"))),
+        }
+    }
+
+    pub fn allows(&self, namespace: &str) -> bool {
+        self.check(Some(namespace)).is_ok()
+    }
+}
+
+// Reads the request's credential and looks it up in the tokens table. PSKs are compared per
+// namespace by `Access::check`. A credential is required once a namespace's PSK is set or any
+// token has been issued; until then read, write and delete are open and admin is disabled.
+pub fn authenticate(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+    pool: &Pool,
+    scope: Scope,
+) -> Result<Access, AppError> {
+    let client_psk = get_client_psk(req, query_options_map)?.unwrap_or_default();
+
+    let token = match client_psk.is_empty() {
+        true => None,
+        false => {
+            lookup_token(&client_psk, pool)?.filter(|cached| cached.token.is_active(Utc::now()))
+        }
+    };
+    if let Some(cached) = &token {
+        if !cached.token.has_scope(scope) {
+            return Err(AppError::Forbidden(format!(
+                "Token '{}' does not have the {} scope",
+                cached.token.name,
+                scope.name()
+            )));
+        }
+    }
+
+    let tokens_issued = match token {
+        Some(_) => true,
+        None => any_issued_tokens(pool)?,
+    };
+    Ok(Access {
+        scope,
+        client_psk,
+        token,
+        tokens_issued,
+    })
+}
+
+// Authenticates for `scope` in `namespace` and returns the caller's identity
+pub fn authorize(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+    pool: &Pool,
+    scope: Scope,
+    namespace: &str,
+) -> Result<Option<String>, AppError> {
+    authenticate(req, query_options_map, pool, scope)?.check(Some(namespace))
+}
+
+// Authorizes a read of `namespace` and keeps the `Access`, so the namespaces the read falls
+// through can be checked as well
+pub fn authorize_read(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+    pool: &Pool,
+    namespace: &str,
+) -> Result<Access, AppError> {
+    let access = authenticate(req, query_options_map, pool, Scope::Read)?;
+    access.check(Some(namespace))?;
+    Ok(access)
+}
+
+// The PSK from `Authorization: Bearer`, the `X-Little-Lookup-PSK` header or the `psk` query
+// parameter, in that order. The query parameter is refused when
+// LITTLE_LOOKUP_ALLOW_QUERY_PSK is off.
+pub(crate) fn get_client_psk(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+) -> Result<Option<String>, AppError> {
+    let query_psk = query_options_map.get("psk");
+    if query_psk.is_some() && !get_allow_query_psk() {
+        return Err(AppError::Unauthorized(String::from(
+            "PSKs in the query string are disabled; send an Authorization: Bearer header",
+        )));
+    }
+
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        let authorization = authorization.to_str().unwrap_or_default();
+        return match authorization.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                Ok(Some(String::from(token.trim())))
+            }
+            _ => Err(AppError::Unauthorized(String::from(
+                "Authorization must use the Bearer scheme",
+            ))),
+        };
+    }
+
+    if let Some(psk) = req.headers().get(PSK_HEADER) {
+        return match psk.to_str() {
+            Ok(psk) => Ok(Some(String::from(psk))),
+            Err(_) => Err(AppError::Unauthorized(format!(
+                "Invalid {} header",
+                PSK_HEADER
+            ))),
+        };
+    }
+
+    Ok(query_psk.cloned())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::db_connection::establish_connection;
+    use crate::diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+    use crate::schema::tokens;
+    use actix_web::test;
+    use serial_test::serial;
+
+    fn bearer(secret: &str) -> HttpRequest {
+        test::TestRequest::default()
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+            .to_http_request()
+    }
+
+    // Deletes the named token when dropped, so a failing test does not leave one behind and
+    // lock every other test out
+    pub(crate) struct TokenCleanup(pub Pool, pub &'static str);
+
+    impl Drop for TokenCleanup {
+        fn drop(&mut self) {
+            if let Ok(mut connection) = self.0.get() {
+                let _ = diesel::delete(tokens::table.filter(tokens::name.eq(self.1)))
+                    .execute(&mut connection);
+            }
+            clear_token_cache();
+        }
+    }
+
+    fn issue(
+        pool: &Pool,
+        name: &'static str,
+        scopes: &[&str],
+        namespaces: &[&str],
+    ) -> (TokenCleanup, Token, String) {
+        // Clear out a token left by an earlier run
+        drop(TokenCleanup(pool.clone(), name));
+        let cleanup = TokenCleanup(pool.clone(), name);
+
+        let mut connection = pool.get().expect("Failed to get connection");
+        let strings = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        let (token, secret) = Token::create(
+            name,
+            &strings(scopes),
+            &strings(namespaces),
+            None,
+            &mut connection,
+        )
+        .unwrap();
+        clear_token_cache();
+        (cleanup, token, secret)
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_token_access() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_ADMIN");
+        let pool = establish_connection().expect("Failed to establish connection");
+        let map = HashMap::new();
+        let anonymous = test::TestRequest::default().to_http_request();
+
+        clear_token_cache();
+        assert_eq!(
+            authorize(&anonymous, &map, &pool, Scope::Write, "team-b").unwrap(),
+            None
+        );
+        assert!(matches!(
+            authorize(&anonymous, &map, &pool, Scope::Admin, "team-b"),
+            Err(AppError::Forbidden(_))
+        ));
+
+        let (_cleanup, token, secret) =
+            issue(&pool, "test-team-a", &["read", "write"], &["team-a/*"]);
+
+        let access = authenticate(&bearer(&secret), &map, &pool, Scope::Write).unwrap();
+        assert_eq!(
+            access.check(Some("team-a/api")).unwrap().as_deref(),
+            Some("token:test-team-a")
+        );
+        assert!(!access.allows("team-b"));
+        assert!(access.check(None).is_err());
+        assert!(matches!(
+            authorize(&bearer(&secret), &map, &pool, Scope::Read, "team-b"),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            authenticate(&bearer(&secret), &map, &pool, Scope::Delete),
+            Err(AppError::Forbidden(_))
+        ));
+
+        // Once a token exists, anonymous callers are turned away
+        assert_eq!(
+            authorize(&anonymous, &map, &pool, Scope::Read, "team-b")
+                .unwrap_err()
+                .to_string(),
+            "PSK required"
+        );
+        assert_eq!(
+            authorize(
+                &bearer("llt_unknown"),
+                &map,
+                &pool,
+                Scope::Read,
+                "team-a/api"
+            )
+            .unwrap_err()
+            .to_string(),
+            "Incorrect PSK"
+        );
+
+        // Revocation shows once the cached lookup is dropped
+        let mut connection = pool.get().expect("Failed to get connection");
+        Token::revoke(token.id, &mut connection).unwrap();
+        assert!(authorize(&bearer(&secret), &map, &pool, Scope::Read, "team-a/api").is_ok());
+        clear_token_cache();
+        assert!(matches!(
+            authorize(&bearer(&secret), &map, &pool, Scope::Read, "team-a/api"),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(authorize(&anonymous, &map, &pool, Scope::Read, "team-b").is_err());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_admin_token() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_ADMIN");
+        let pool = establish_connection().expect("Failed to establish connection");
+        let map = HashMap::new();
+
+        let (_cleanup, _, secret) = issue(&pool, "test-admin", &["admin"], &["*"]);
+
+        let access = authenticate(&bearer(&secret), &map, &pool, Scope::Admin).unwrap();
+        assert!(access.check(None).is_ok());
+        assert_eq!(
+            authorize(&bearer(&secret), &map, &pool, Scope::Delete, "any").unwrap(),
+            Some(String::from("token:test-admin"))
+        );
+    }
+}
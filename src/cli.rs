@@ -0,0 +1,341 @@
+use crate::crypto::keyring;
+use crate::db_connection::Pool;
+use crate::diff::MapDiff;
+use crate::error::AppError;
+use crate::import::{parse_entries, ImportFormat};
+use crate::models::item::{Author, ItemList};
+use crate::models::namespace::Namespace;
+use std::io::Read;
+
+pub const USAGE: &str = "Usage:
+  little-lookup
+      Run the server
+  little-lookup import <file> [--ns <namespace>] [--format dotenv|json|yaml] [--replace] [--dry-run]
+      Write the entries of a dotenv file, flat JSON object or YAML map into a namespace.
+      <file> - reads from stdin. The format defaults to the file extension, else dotenv.
+  little-lookup rotate-key [--ns <namespace>] [--dry-run]
+      Re-encrypt stored values under the active encryption key, including values stored
+      before encryption was enabled. Safe to run while the server is up, once every
+      server has loaded the active key.";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Import {
+        path: String,
+        namespace: String,
+        format: ImportFormat,
+        replace: bool,
+        dry_run: bool,
+    },
+    RotateKey {
+        namespace: Option<String>,
+        dry_run: bool,
+    },
+}
+
+// Parses the arguments following the program name
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Err(String::from("Missing command")),
+    };
+
+    match command {
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        "import" => parse_import(args),
+        "rotate-key" => parse_rotate_key(args),
+        command => Err(format!("Unknown command '{}'", command)),
+    }
+}
+
+fn parse_import(args: &[String]) -> Result<Command, String> {
+    let mut path = None;
+    let mut namespace = String::from("default");
+    let mut format = None;
+    let mut replace = false;
+    let mut dry_run = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ns" | "--namespace" => {
+                namespace = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?
+                    .clone();
+            }
+            "--format" => {
+                let name = args.next().ok_or("--format needs a value")?;
+                format = Some(
+                    ImportFormat::parse(name)
+                        .ok_or_else(|| format!("Unknown format '{}'", name))?,
+                );
+            }
+            "--replace" => replace = true,
+            "--dry-run" => dry_run = true,
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown option '{}'", option))
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    let path = path.ok_or("import needs a file")?;
+    Ok(Command::Import {
+        format: format.unwrap_or_else(|| ImportFormat::from_path(&path)),
+        path,
+        namespace,
+        replace,
+        dry_run,
+    })
+}
+
+fn parse_rotate_key(args: &[String]) -> Result<Command, String> {
+    let mut namespace = None;
+    let mut dry_run = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ns" | "--namespace" => {
+                namespace = Some(
+                    args.next()
+                        .ok_or_else(|| format!("{} needs a value", arg))?
+                        .clone(),
+                );
+            }
+            "--dry-run" => dry_run = true,
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown option '{}'", option))
+            }
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(Command::RotateKey { namespace, dry_run })
+}
+
+// Runs a command other than `Help` and returns the process exit code
+pub fn run(command: Command, pool: &Pool) -> i32 {
+    let result = match command {
+        Command::Help => Ok(()),
+        Command::Import {
+            path,
+            namespace,
+            format,
+            replace,
+            dry_run,
+        } => import(&path, &namespace, format, replace, dry_run, pool),
+        Command::RotateKey { namespace, dry_run } => {
+            rotate_key(namespace.as_deref(), dry_run, pool)
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn import(
+    path: &str,
+    namespace: &str,
+    format: ImportFormat,
+    replace: bool,
+    dry_run: bool,
+    pool: &Pool,
+) -> Result<(), AppError> {
+    let mut input = String::new();
+    let read = match path {
+        "-" => std::io::stdin().read_to_string(&mut input).map(|_| ()),
+        path => std::fs::read_to_string(path).map(|contents| input = contents),
+    };
+    read.map_err(|e| AppError::Validation(format!("Cannot read {}: {}", path, e)))?;
+
+    let entries = parse_entries(&input, format)?;
+    let mut connection = pool.get()?;
+    let diff = Namespace::import(
+        namespace,
+        &entries,
+        replace,
+        dry_run,
+        &Author::named(&cli_identity()),
+        &mut connection,
+    )?;
+
+    print!("{}", summarize(&diff));
+    println!(
+        "{} {} added, {} changed, {} removed in '{}'",
+        match dry_run {
+            true => "Would apply:",
+            false => "Applied:",
+        },
+        diff.added.len(),
+        diff.changed.len(),
+        diff.removed.len(),
+        namespace
+    );
+    Ok(())
+}
+
+// `written_by` of command line writes: `cli`, with the OS user when it is known
+fn cli_identity() -> String {
+    match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        Ok(user) if !user.is_empty() => format!("cli:{}", user),
+        _ => String::from("cli"),
+    }
+}
+
+fn rotate_key(namespace: Option<&str>, dry_run: bool, pool: &Pool) -> Result<(), AppError> {
+    let keyring = keyring().ok_or_else(|| {
+        AppError::Validation(String::from(
+            "Encryption is not enabled; set LITTLE_LOOKUP_ENCRYPTION_KEYS or LITTLE_LOOKUP_ENCRYPTION_KEY_FILE",
+        ))
+    })?;
+
+    let mut connection = pool.get()?;
+    let rewritten = ItemList::reencrypt(&mut connection, keyring, namespace, dry_run)?;
+    println!(
+        "{} {} versions under encryption key {}",
+        match dry_run {
+            true => "Would re-encrypt:",
+            false => "Re-encrypted:",
+        },
+        rewritten,
+        keyring.active_version()
+    );
+    Ok(())
+}
+
+// One line per changed key: `+` added, `~` changed, `-` removed. Values are left out, as
+// imports often carry secrets.
+fn summarize(diff: &MapDiff) -> String {
+    let mut lines = String::new();
+    for (marker, changes) in [
+        ("+", &diff.added),
+        ("~", &diff.changed),
+        ("-", &diff.removed),
+    ] {
+        for change in changes.iter() {
+            lines.push_str(&format!("{} {}\n", marker, change.key));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::KeyChange;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_import_args() {
+        assert_eq!(
+            parse_args(&args(&[
+                "import",
+                "prod.yml",
+                "--ns",
+                "production",
+                "--dry-run"
+            ])),
+            Ok(Command::Import {
+                path: String::from("prod.yml"),
+                namespace: String::from("production"),
+                format: ImportFormat::Yaml,
+                replace: false,
+                dry_run: true,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["import", "--replace", "--format", "json", "-"])),
+            Ok(Command::Import {
+                path: String::from("-"),
+                namespace: String::from("default"),
+                format: ImportFormat::Json,
+                replace: true,
+                dry_run: false,
+            })
+        );
+        assert_eq!(parse_args(&args(&["--help"])), Ok(Command::Help));
+
+        for invalid in [
+            vec!["serve"],
+            vec!["import"],
+            vec!["import", "a.env", "b.env"],
+            vec!["import", "a.env", "--ns"],
+            vec!["import", "a.env", "--format", "toml"],
+            vec!["import", "a.env", "--force"],
+        ] {
+            assert!(parse_args(&args(&invalid)).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_rotate_key_args() {
+        assert_eq!(
+            parse_args(&args(&["rotate-key", "--ns", "production", "--dry-run"])),
+            Ok(Command::RotateKey {
+                namespace: Some(String::from("production")),
+                dry_run: true,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["rotate-key"])),
+            Ok(Command::RotateKey {
+                namespace: None,
+                dry_run: false,
+            })
+        );
+
+        for invalid in [
+            vec!["rotate-key", "--ns"],
+            vec!["rotate-key", "2"],
+            vec!["rotate-key", "--force"],
+        ] {
+            assert!(parse_args(&args(&invalid)).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let change = |key: &str| KeyChange {
+            key: String::from(key),
+            from: None,
+            to: None,
+        };
+        let diff = MapDiff {
+            added: vec![change("NEW")],
+            removed: vec![change("OLD")],
+            changed: vec![change("DB_HOST")],
+        };
+        assert_eq!(summarize(&diff), "+ NEW\n~ DB_HOST\n- OLD\n");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_cli_identity() {
+        let saved = (std::env::var("USER"), std::env::var("USERNAME"));
+
+        std::env::set_var("USER", "alice");
+        assert_eq!(cli_identity(), "cli:alice");
+        std::env::remove_var("USER");
+        std::env::remove_var("USERNAME");
+        assert_eq!(cli_identity(), "cli");
+
+        if let Ok(user) = saved.0 {
+            std::env::set_var("USER", user);
+        }
+        if let Ok(user) = saved.1 {
+            std::env::set_var("USERNAME", user);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// Opt-in encryption of stored values with AES-256-GCM. Every sealed value records the
+// version of the key it was sealed with, so a new key can be introduced while rows sealed
+// with older ones still decrypt.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+const NONCE_BYTES: usize = 12;
+
+static KEYRING: OnceLock<Option<Keyring>> = OnceLock::new();
+
+pub struct Keyring {
+    keys: BTreeMap<i32, Aes256Gcm>,
+    // Version new values are sealed with
+    active: i32,
+}
+
+impl Keyring {
+    // `<version>:<key>` entries separated by commas or newlines, each key 32 bytes in
+    // base64. Lines starting with `#` are ignored. `active` defaults to the highest version.
+    pub fn parse(entries: &str, active: Option<i32>) -> Result<Keyring, String> {
+        let mut keys = BTreeMap::new();
+
+        for entry in entries
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+        {
+            let (version, key) = entry
+                .split_once(':')
+                .ok_or("Invalid encryption key, expected <version>:<base64 key>")?;
+            let version = version
+                .trim()
+                .parse::<i32>()
+                .ok()
+                .filter(|version| *version > 0)
+                .ok_or_else(|| format!("Invalid encryption key version '{}'", version))?;
+            let cipher = STANDARD
+                .decode(key.trim())
+                .ok()
+                .and_then(|key| Aes256Gcm::new_from_slice(&key).ok())
+                .ok_or_else(|| format!("Encryption key {} must be 32 bytes of base64", version))?;
+            if keys.insert(version, cipher).is_some() {
+                return Err(format!("Encryption key {} is defined twice", version));
+            }
+        }
+
+        let active = match active {
+            Some(active) if keys.contains_key(&active) => active,
+            Some(active) => {
+                return Err(format!(
+                    "Active encryption key {} is not configured",
+                    active
+                ))
+            }
+            None => *keys
+                .keys()
+                .next_back()
+                .ok_or("No encryption keys configured")?,
+        };
+        Ok(Keyring { keys, active })
+    }
+
+    // Keys from LITTLE_LOOKUP_ENCRYPTION_KEYS or the file named by
+    // LITTLE_LOOKUP_ENCRYPTION_KEY_FILE, the active one from
+    // LITTLE_LOOKUP_ENCRYPTION_KEY_VERSION. None when encryption is not configured.
+    pub fn from_env() -> Result<Option<Keyring>, String> {
+        let entries = match (
+            std::env::var("LITTLE_LOOKUP_ENCRYPTION_KEYS").ok(),
+            std::env::var("LITTLE_LOOKUP_ENCRYPTION_KEY_FILE").ok(),
+        ) {
+            (Some(_), Some(_)) => {
+                return Err(String::from(
+                    "Set only one of LITTLE_LOOKUP_ENCRYPTION_KEYS and LITTLE_LOOKUP_ENCRYPTION_KEY_FILE",
+                ))
+            }
+            (Some(entries), None) => entries,
+            (None, Some(path)) => std::fs::read_to_string(&path)
+                .map_err(|e| format!("Cannot read encryption keys from {}: {}", path, e))?,
+            (None, None) => return Ok(None),
+        };
+
+        let active = match std::env::var("LITTLE_LOOKUP_ENCRYPTION_KEY_VERSION") {
+            Ok(active) => Some(active.parse::<i32>().map_err(|_| {
+                format!("Invalid LITTLE_LOOKUP_ENCRYPTION_KEY_VERSION '{}'", active)
+            })?),
+            Err(_) => None,
+        };
+        Keyring::parse(&entries, active).map(Some)
+    }
+
+    pub fn active_version(&self) -> i32 {
+        self.active
+    }
+
+    // Encrypts under the active key. `aad` is authenticated but not stored; the same bytes
+    // must be passed to `open`. Returns the sealed value and the key version.
+    pub fn seal(&self, plaintext: &str, aad: &[u8]) -> Result<(String, i32), String> {
+        let nonce: [u8; NONCE_BYTES] = rand::random();
+        let ciphertext = self.keys[&self.active]
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad,
+                },
+            )
+            .map_err(|_| String::from("Encrypting the value failed"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok((STANDARD.encode(sealed), self.active))
+    }
+
+    pub fn open(&self, sealed: &str, version: i32, aad: &[u8]) -> Result<String, String> {
+        let cipher = self
+            .keys
+            .get(&version)
+            .ok_or_else(|| format!("Encryption key {} is not configured", version))?;
+        let sealed = STANDARD
+            .decode(sealed)
+            .ok()
+            .filter(|sealed| sealed.len() >= NONCE_BYTES)
+            .ok_or("Sealed value is malformed")?;
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| format!("Decrypting a value with key {} failed", version))?;
+        String::from_utf8(plaintext).map_err(|_| String::from("Decrypted value is not UTF-8"))
+    }
+}
+
+// Loads the keyring from the environment. Called once at startup, before the first query,
+// so a bad key stops the process instead of values being written in plaintext.
+pub fn init_keyring() -> Result<(), String> {
+    let keyring = Keyring::from_env()?;
+    let _ = KEYRING.set(keyring);
+    Ok(())
+}
+
+#[cfg(test)]
+thread_local! {
+    // Stands in for the loaded keyring on one test thread, so other tests stay in plaintext
+    static TEST_KEYRING: std::cell::Cell<Option<&'static Keyring>> =
+        const { std::cell::Cell::new(None) };
+}
+
+// The keyring loaded by `init_keyring`; None when encryption is off
+pub fn keyring() -> Option<&'static Keyring> {
+    #[cfg(test)]
+    if let Some(keyring) = TEST_KEYRING.get() {
+        return Some(keyring);
+    }
+    KEYRING.get().and_then(Option::as_ref)
+}
+
+// Runs `f` on this thread as if `keyring` had been loaded by `init_keyring`
+#[cfg(test)]
+pub fn with_keyring<T>(keyring: Keyring, f: impl FnOnce() -> T) -> T {
+    TEST_KEYRING.set(Some(Box::leak(Box::new(keyring))));
+    let result = f();
+    TEST_KEYRING.set(None);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_1: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+    const KEY_2: &str = "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=";
+
+    #[test]
+    fn test_seal_and_open() {
+        let keyring = Keyring::parse(&format!("1:{}", KEY_1), None).unwrap();
+        let (sealed, version) = keyring.seal("hunter2", b"db.password").unwrap();
+        assert_eq!(version, 1);
+        assert!(!sealed.contains("hunter2"));
+        assert_ne!(keyring.seal("hunter2", b"db.password").unwrap().0, sealed);
+
+        assert_eq!(keyring.open(&sealed, 1, b"db.password").unwrap(), "hunter2");
+        assert!(keyring.open(&sealed, 1, b"db.user").is_err());
+        assert!(keyring.open(&sealed, 2, b"db.password").is_err());
+        assert!(keyring.open("AAAA", 1, b"db.password").is_err());
+
+        let mut tampered = STANDARD.decode(&sealed).unwrap();
+        tampered[NONCE_BYTES] ^= 1;
+        assert!(keyring
+            .open(&STANDARD.encode(tampered), 1, b"db.password")
+            .is_err());
+    }
+
+    #[test]
+    fn test_rotated_keyring() {
+        let old = Keyring::parse(&format!("1:{}", KEY_1), None).unwrap();
+        let (sealed, _) = old.seal("v", b"k").unwrap();
+
+        let rotated = Keyring::parse(&format!("# keys\n1:{}\n2:{}\n", KEY_1, KEY_2), None).unwrap();
+        assert_eq!(rotated.active_version(), 2);
+        assert_eq!(rotated.open(&sealed, 1, b"k").unwrap(), "v");
+
+        let pinned = Keyring::parse(&format!("1:{},2:{}", KEY_1, KEY_2), Some(1)).unwrap();
+        assert_eq!(pinned.seal("v", b"k").unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_parse_keyring_errors() {
+        for entries in [
+            String::new(),
+            String::from("1"),
+            format!("0:{}", KEY_1),
+            String::from("1:c2hvcnQ="),
+            String::from("1:not base64!"),
+            format!("1:{},1:{}", KEY_1, KEY_2),
+        ] {
+            assert!(Keyring::parse(&entries, None).is_err(), "{}", entries);
+        }
+        assert!(Keyring::parse(&format!("1:{}", KEY_1), Some(2)).is_err());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_keyring_from_env() {
+        assert!(Keyring::from_env().unwrap().is_none());
+
+        std::env::set_var("LITTLE_LOOKUP_ENCRYPTION_KEYS", format!("1:{}", KEY_1));
+        assert_eq!(Keyring::from_env().unwrap().unwrap().active_version(), 1);
+
+        std::env::set_var("LITTLE_LOOKUP_ENCRYPTION_KEY_VERSION", "3");
+        assert!(Keyring::from_env().is_err());
+
+        std::env::remove_var("LITTLE_LOOKUP_ENCRYPTION_KEY_VERSION");
+
+        let path = std::env::temp_dir().join("little-lookup-test-keys");
+        std::fs::write(&path, format!("1:{}\n2:{}\n", KEY_1, KEY_2)).unwrap();
+        std::env::set_var("LITTLE_LOOKUP_ENCRYPTION_KEY_FILE", &path);
+        assert!(Keyring::from_env().is_err());
+
+        std::env::remove_var("LITTLE_LOOKUP_ENCRYPTION_KEYS");
+        assert_eq!(Keyring::from_env().unwrap().unwrap().active_version(), 2);
+
+        std::env::remove_var("LITTLE_LOOKUP_ENCRYPTION_KEY_FILE");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
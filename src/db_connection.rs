@@ -11,12 +11,14 @@ use crate::util::{get_database, get_pool_size_per_worker};
 // diesel_migrations::embed_migrations!();
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+#[allow(clippy::let_and_return)]
 pub fn establish_connection() -> Result<Pool, PoolError> {
     let database_url = get_database();
     let sql_pool = init_pool(&database_url);
     sql_pool
 }
 
+#[allow(clippy::unnecessary_mut_passed, clippy::print_with_newline)]
 pub fn run_sql_schema_migrations() {
     let mut sql_pool = match establish_connection() {
         Ok(pool) => pool,
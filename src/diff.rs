@@ -0,0 +1,172 @@
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LineChange {
+    pub op: &'static str,
+    pub line: String,
+}
+
+#[derive(Serialize)]
+pub struct ValueDiff {
+    pub changed: bool,
+    pub lines: Vec<LineChange>,
+    pub unified: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct KeyChange {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct MapDiff {
+    pub added: Vec<KeyChange>,
+    pub removed: Vec<KeyChange>,
+    pub changed: Vec<KeyChange>,
+}
+
+impl MapDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Line-level diff of two values; `from_label` and `to_label` head the unified diff
+pub fn diff_values(from: &str, to: &str, from_label: &str, to_label: &str) -> ValueDiff {
+    let text_diff = TextDiff::from_lines(from, to);
+
+    let lines = text_diff
+        .iter_all_changes()
+        .map(|change| LineChange {
+            op: match change.tag() {
+                ChangeTag::Equal => "equal",
+                ChangeTag::Delete => "delete",
+                ChangeTag::Insert => "insert",
+            },
+            line: change.value().trim_end_matches('\n').to_string(),
+        })
+        .collect();
+
+    let unified = text_diff
+        .unified_diff()
+        .header(from_label, to_label)
+        .to_string();
+
+    ValueDiff {
+        changed: from != to,
+        lines,
+        unified,
+    }
+}
+
+// Keys present only in `to` are added, only in `from` removed; all lists are sorted by key
+pub fn diff_maps(from: &HashMap<String, String>, to: &HashMap<String, String>) -> MapDiff {
+    let mut map_diff = MapDiff::default();
+
+    for (key, to_val) in to.iter() {
+        match from.get(key) {
+            None => map_diff.added.push(KeyChange {
+                key: key.clone(),
+                from: None,
+                to: Some(to_val.clone()),
+            }),
+            Some(from_val) if from_val != to_val => map_diff.changed.push(KeyChange {
+                key: key.clone(),
+                from: Some(from_val.clone()),
+                to: Some(to_val.clone()),
+            }),
+            Some(_) => (),
+        }
+    }
+
+    for (key, from_val) in from.iter() {
+        if !to.contains_key(key) {
+            map_diff.removed.push(KeyChange {
+                key: key.clone(),
+                from: Some(from_val.clone()),
+                to: None,
+            });
+        }
+    }
+
+    map_diff.added.sort_by(|a, b| a.key.cmp(&b.key));
+    map_diff.removed.sort_by(|a, b| a.key.cmp(&b.key));
+    map_diff.changed.sort_by(|a, b| a.key.cmp(&b.key));
+
+    map_diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_values_multiline() {
+        let value_diff = diff_values("a\nb\nc\n", "a\nB\nc\n", "v1", "v2");
+        assert!(value_diff.changed);
+        assert_eq!(
+            value_diff.lines,
+            vec![
+                LineChange {
+                    op: "equal",
+                    line: String::from("a")
+                },
+                LineChange {
+                    op: "delete",
+                    line: String::from("b")
+                },
+                LineChange {
+                    op: "insert",
+                    line: String::from("B")
+                },
+                LineChange {
+                    op: "equal",
+                    line: String::from("c")
+                },
+            ]
+        );
+        assert!(value_diff.unified.starts_with("--- v1\n+++ v2\n"));
+        assert!(value_diff.unified.contains("-b\n+B\n"));
+    }
+
+    #[test]
+    fn test_diff_values_unchanged() {
+        let value_diff = diff_values("same", "same", "v1", "v2");
+        assert!(!value_diff.changed);
+        assert!(value_diff.unified.is_empty());
+    }
+
+    #[test]
+    fn test_diff_maps() {
+        let from: HashMap<String, String> = [("kept", "1"), ("changed", "old"), ("removed", "x")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let to: HashMap<String, String> = [("kept", "1"), ("changed", "new"), ("added", "y")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let map_diff = diff_maps(&from, &to);
+        assert_eq!(map_diff.added.len(), 1);
+        assert_eq!(map_diff.added[0].key, "added");
+        assert_eq!(map_diff.removed[0].key, "removed");
+        assert_eq!(map_diff.removed[0].from.as_deref(), Some("x"));
+        assert_eq!(
+            map_diff.changed,
+            vec![KeyChange {
+                key: String::from("changed"),
+                from: Some(String::from("old")),
+                to: Some(String::from("new")),
+            }]
+        );
+        assert!(!map_diff.is_empty());
+        assert!(diff_maps(&from, &from).is_empty());
+    }
+}
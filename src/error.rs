@@ -0,0 +1,187 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use diesel::r2d2::PoolError;
+use log::error;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AppError {
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Validation(String),
+    Conflict(String),
+    PreconditionFailed(String),
+    Timeout(String),
+    PoolExhausted(PoolError),
+    Database(diesel::result::Error),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: String,
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::Timeout(_) => "timeout",
+            AppError::PoolExhausted(_) => "pool_exhausted",
+            AppError::Database(_) => "database_error",
+        }
+    }
+
+    // Legacy routes keep their plain-text bodies; the machine-readable code goes in a header
+    pub fn plain_text_response(&self) -> HttpResponse {
+        self.log();
+        HttpResponse::build(self.status_code())
+            .insert_header(("X-Little-Lookup-Error", self.code()))
+            .body(self.to_string())
+    }
+
+    fn log(&self) {
+        match self {
+            AppError::PoolExhausted(e) => error!("{}", e),
+            AppError::Database(e) => error!("{}", e),
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Validation(message)
+            | AppError::Conflict(message)
+            | AppError::PreconditionFailed(message)
+            | AppError::Timeout(message) => write!(f, "{}", message),
+            AppError::PoolExhausted(_) => write!(f, "Database connection failed"),
+            AppError::Database(_) => write!(f, "Database error"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::Timeout(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::PoolExhausted(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.log();
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+        })
+    }
+}
+
+impl From<diesel::result::Error> for AppError {
+    fn from(e: diesel::result::Error) -> AppError {
+        match e {
+            diesel::result::Error::NotFound => AppError::NotFound(String::from("Undefined")),
+            e => AppError::Database(e),
+        }
+    }
+}
+
+impl From<PoolError> for AppError {
+    fn from(e: PoolError) -> AppError {
+        AppError::PoolExhausted(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(
+            AppError::Unauthorized(String::from("PSK required")).status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            AppError::Forbidden(String::from("disabled")).status_code(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            AppError::NotFound(String::from("Undefined")).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            AppError::Validation(String::from("bad")).status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            AppError::Conflict(String::from("stale")).status_code(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            AppError::PreconditionFailed(String::from("stale")).status_code(),
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            AppError::Timeout(String::from("cut off")).status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            AppError::Database(diesel::result::Error::RollbackTransaction).status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_from_diesel_not_found() {
+        let e = AppError::from(diesel::result::Error::NotFound);
+        assert_eq!(e.code(), "not_found");
+        assert_eq!(e.to_string(), "Undefined");
+    }
+
+    #[actix_rt::test]
+    async fn test_error_response_body() {
+        let e = AppError::Unauthorized(String::from("Incorrect PSK"));
+        let resp = e.error_response();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"], "unauthorized");
+        assert_eq!(json["message"], "Incorrect PSK");
+    }
+
+    #[actix_rt::test]
+    async fn test_plain_text_response() {
+        let e = AppError::NotFound(String::from("Undefined"));
+        let resp = e.plain_text_response();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.headers().get("X-Little-Lookup-Error").unwrap(),
+            "not_found"
+        );
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "Undefined");
+    }
+}
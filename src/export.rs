@@ -0,0 +1,316 @@
+// Renders a namespace as a file other tools can load. Every format quotes values so that
+// any string, quotes and newlines included, comes back unchanged.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Bash,
+    Dotenv,
+    Json,
+    Yaml,
+    Fish,
+    Powershell,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "bash" | "sh" => Some(ExportFormat::Bash),
+            "dotenv" | "env" => Some(ExportFormat::Dotenv),
+            "json" => Some(ExportFormat::Json),
+            "yaml" | "yml" => Some(ExportFormat::Yaml),
+            "fish" => Some(ExportFormat::Fish),
+            "powershell" | "pwsh" => Some(ExportFormat::Powershell),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Yaml => "application/yaml",
+            _ => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn head(&self) -> &'static str {
+        match self {
+            ExportFormat::Bash => "#!/bin/bash\n",
+            ExportFormat::Json => "{",
+            _ => "",
+        }
+    }
+
+    pub fn tail(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "}",
+            _ => "",
+        }
+    }
+
+    // The whole export when there are no entries, if that differs from `head` and `tail`;
+    // an empty YAML document would read as null instead of an empty map
+    pub fn empty(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Yaml => Some("{}\n"),
+            _ => None,
+        }
+    }
+
+    // One entry; `first` is set for the first entry of the export. Shell and dotenv formats
+    // replace keys that are not variable names with a comment saying they were skipped.
+    pub fn render(&self, key: &str, value: &str, first: bool) -> String {
+        let needs_variable_name = !matches!(self, ExportFormat::Json | ExportFormat::Yaml);
+        if needs_variable_name && !is_variable_name(key) {
+            return format!(
+                "# Skipped {}: not a valid variable name\n",
+                json_string(key)
+            );
+        }
+
+        match self {
+            ExportFormat::Bash => format!("export {}={}\n", key, posix_quote(value)),
+            ExportFormat::Dotenv => format!("{}={}\n", key, dotenv_quote(value)),
+            ExportFormat::Json => format!(
+                "{}{}:{}",
+                if first { "" } else { "," },
+                json_string(key),
+                json_string(value)
+            ),
+            ExportFormat::Yaml => format!("{}: {}\n", yaml_string(key), yaml_string(value)),
+            ExportFormat::Fish => format!("set -gx {} {}\n", key, fish_quote(value)),
+            ExportFormat::Powershell => {
+                format!("$env:{} = {}\n", key, powershell_quote(value))
+            }
+        }
+    }
+}
+
+// ASCII letters, digits and `_`, not starting with a digit
+pub fn is_variable_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Single quotes keep everything literal; a quote inside closes the string, adds an escaped
+// quote and reopens it
+pub fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Single quoted when that is literal everywhere; python-dotenv unescapes `\\` and `\'` even
+// there. Otherwise double quoted with the escapes of the Ruby and Node dotenv loaders, where
+// `$` is escaped so they do not expand it; python-dotenv keeps that `\$` as written.
+fn dotenv_quote(value: &str) -> String {
+    if !value.contains(['\'', '\\', '\n', '\r']) {
+        return format!("'{}'", value);
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// In fish single quotes only `\` and `'` are special
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// PowerShell also closes single-quoted strings on the typographic single quotes, so those
+// are doubled too
+fn powershell_quote(value: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in value.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+// Double-quoted YAML scalar. Besides the C0 controls, YAML does not allow DEL, C1 controls
+// or a BOM as is, and reads U+0085, U+2028 and U+2029 as line breaks.
+fn yaml_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{85}' => quoted.push_str("\\N"),
+            '\u{2028}' => quoted.push_str("\\L"),
+            '\u{2029}' => quoted.push_str("\\P"),
+            '\u{0}'..='\u{1F}' | '\u{7F}' => quoted.push_str(&format!("\\x{:02X}", c as u32)),
+            '\u{80}'..='\u{9F}' | '\u{FEFF}' => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRICKY: &str = "it's $HOME\n\"quoted\" \\ `cmd`";
+
+    #[test]
+    fn test_is_variable_name() {
+        assert!(is_variable_name("DB_HOST"));
+        assert!(is_variable_name("_private1"));
+        assert!(!is_variable_name("1ST"));
+        assert!(!is_variable_name("db.host"));
+        assert!(!is_variable_name("a b"));
+        assert!(!is_variable_name(""));
+    }
+
+    #[test]
+    fn test_render_bash() {
+        let bash = ExportFormat::Bash;
+        assert_eq!(bash.render("A", "plain", true), "export A='plain'\n");
+        assert_eq!(
+            bash.render("A", "'; rm -rf / #", true),
+            "export A=''\\''; rm -rf / #'\n"
+        );
+        assert_eq!(
+            bash.render("A", TRICKY, true),
+            "export A='it'\\''s $HOME\n\"quoted\" \\ `cmd`'\n"
+        );
+        assert_eq!(
+            bash.render("a b\nrm -rf /", "x", true),
+            "# Skipped \"a b\\nrm -rf /\": not a valid variable name\n"
+        );
+    }
+
+    #[test]
+    fn test_render_dotenv() {
+        let dotenv = ExportFormat::Dotenv;
+        assert_eq!(dotenv.render("A", "$HOME \"x\"", true), "A='$HOME \"x\"'\n");
+        // python-dotenv unescapes `\\` in single quotes too
+        assert_eq!(dotenv.render("A", "C:\\dir", true), "A=\"C:\\\\dir\"\n");
+        assert_eq!(
+            dotenv.render("A", TRICKY, true),
+            "A=\"it's \\$HOME\\n\\\"quoted\\\" \\\\ `cmd`\"\n"
+        );
+    }
+
+    #[test]
+    fn test_dotenv_round_trips_through_import() {
+        use crate::import::{parse_entries, ImportFormat};
+
+        let values = [
+            TRICKY,
+            "plain",
+            "",
+            "a'b",
+            "line 1\r\nline 2",
+            "# not a comment",
+            "C:\\dir\\",
+        ];
+        let exported: String = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| ExportFormat::Dotenv.render(&format!("K{}", i), value, i == 0))
+            .collect();
+
+        let imported = parse_entries(&exported, ImportFormat::Dotenv).unwrap();
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(imported[&format!("K{}", i)], *value);
+        }
+    }
+
+    #[test]
+    fn test_render_json_and_yaml() {
+        let json = ExportFormat::Json;
+        let rendered = format!(
+            "{}{}{}{}",
+            json.head(),
+            json.render("db.host", TRICKY, true),
+            json.render("a b", "1", false),
+            json.tail()
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed["db.host"], TRICKY);
+        assert_eq!(parsed["a b"], "1");
+
+        let yaml = ExportFormat::Yaml;
+        let rendered = format!(
+            "{}{}",
+            yaml.render("db.host", TRICKY, true),
+            yaml.render("on", "yes", false)
+        );
+        let parsed: serde_yaml_ng::Value = serde_yaml_ng::from_str(&rendered).unwrap();
+        assert_eq!(parsed["db.host"].as_str(), Some(TRICKY));
+        assert_eq!(parsed["on"].as_str(), Some("yes"));
+    }
+
+    #[test]
+    fn test_yaml_round_trips() {
+        let values = [
+            TRICKY,
+            "tab\tbell\u{7}esc\u{1b}nul\u{0}",
+            "del\u{7F}",
+            "c1\u{80}\u{9F}",
+            "next line\u{85}line sep\u{2028}para sep\u{2029}end",
+            "\u{FEFF}bom",
+            "unicode é ✓ 🦀",
+            "",
+        ];
+        let yaml = ExportFormat::Yaml;
+        let rendered: String = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| yaml.render(&format!("k{}\u{2028}", i), value, i == 0))
+            .collect();
+
+        let parsed: std::collections::BTreeMap<String, String> =
+            serde_yaml_ng::from_str(&rendered).unwrap();
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(parsed[&format!("k{}\u{2028}", i)], *value);
+        }
+
+        let empty: serde_yaml_ng::Value = serde_yaml_ng::from_str(yaml.empty().unwrap()).unwrap();
+        assert_eq!(empty, serde_yaml_ng::Value::Mapping(Default::default()));
+    }
+
+    #[test]
+    fn test_render_fish_and_powershell() {
+        assert_eq!(
+            ExportFormat::Fish.render("A", "it's a \\ path", true),
+            "set -gx A 'it\\'s a \\\\ path'\n"
+        );
+        assert_eq!(
+            ExportFormat::Powershell.render("A", "it's \u{2019}$env:X", true),
+            "$env:A = 'it''s \u{2019}\u{2019}$env:X'\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(ExportFormat::parse("sh"), Some(ExportFormat::Bash));
+        assert_eq!(ExportFormat::parse("PWSH"), Some(ExportFormat::Powershell));
+        assert_eq!(ExportFormat::parse("toml"), None);
+        assert_eq!(ExportFormat::Json.content_type(), "application/json");
+    }
+}
@@ -1,66 +1,602 @@
-use actix_web::{web, HttpRequest, HttpResponse};
-use diesel::r2d2::PoolError;
-use log::error;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use diesel::pg::PgConnection;
+use futures_util::{stream, StreamExt};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
 
+use crate::auth::{authorize, authorize_read, Access};
 use crate::db_connection::{Pool, PooledConnection};
-use crate::models::item::{Item, ItemList};
-use crate::util::{get_namespace, get_psk, PSKType};
+use crate::error::AppError;
+use crate::export::ExportFormat;
+use crate::keys::{compile_glob, compile_regex, KeyFilter};
+use crate::models::item::{
+    Author, BatchConnection, HistoryQuery, Item, ItemList, ItemListIter, ListCursor, ListQuery,
+    ListSort, ListedItem,
+};
+use crate::models::namespace::Namespace;
+use crate::models::token::Scope;
+use crate::util::{get_namespace, get_stream_timeout, get_trust_forwarded_for};
 
 // Utility functions
 
-fn check_psk(query_options_map: &HashMap<String, String>, psk_type: PSKType) -> String {
-    let server_psk = get_psk(psk_type);
-    if server_psk != String::from("") {
-        let client_psk = match query_options_map.get("psk") {
-            Some(psk) => String::from(psk),
-            None => String::from(""),
-        };
+pub(crate) fn req_query_to_map(query_string: &str) -> Result<HashMap<String, String>, AppError> {
+    let mut query_map: HashMap<String, String> = HashMap::new();
+
+    for query_element in query_string.split('&').filter(|e| !e.is_empty()) {
+        // A bare `flag` is `flag=`, as in browsers
+        let (raw_key, raw_val) = query_element.split_once('=').unwrap_or((query_element, ""));
+
+        let key = percent_decode(raw_key)?;
+        let val = percent_decode(raw_val)?;
+        if key.is_empty() {
+            return Err(AppError::Validation(String::from(
+                "Malformed query parameter with empty name",
+            )));
+        }
 
-        if client_psk != server_psk {
-            match client_psk.as_str() {
-                "" => return String::from("PSK required"),
-                _ => return String::from("Incorrect PSK"),
+        // Repeated keys are accepted as long as they agree, anything else is ambiguous
+        match query_map.get(&key) {
+            Some(existing) if *existing != val => {
+                return Err(AppError::Validation(format!(
+                    "Conflicting values for query parameter '{}'",
+                    key
+                )))
+            }
+            Some(_) => (),
+            None => {
+                query_map.insert(key, val);
             }
         }
+    }
+
+    if let (Some(ns), Some(namespace)) = (query_map.get("ns"), query_map.get("namespace")) {
+        if ns != namespace {
+            return Err(AppError::Validation(String::from(
+                "Conflicting values for query parameters 'ns' and 'namespace'",
+            )));
+        }
+    }
+
+    Ok(query_map)
+}
+
+// Decodes application/x-www-form-urlencoded text, rejecting bad escapes and invalid UTF-8.
+// Errors leave out the input, which may be a credential.
+fn percent_decode(input: &str) -> Result<String, AppError> {
+    let bytes = input.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                match hex {
+                    Some(byte) => decoded.push(byte),
+                    None => {
+                        return Err(AppError::Validation(String::from(
+                            "Malformed percent-encoding in query string",
+                        )))
+                    }
+                }
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8(decoded)
+        .map_err(|_| AppError::Validation(String::from("Query string is not valid UTF-8")))
+}
+
+pub(crate) fn parse_timestamp(name: &str, value: &str) -> Result<DateTime<Utc>, AppError> {
+    // An unencoded `+` in a UTC offset arrives as a space after query decoding
+    DateTime::parse_from_rfc3339(value)
+        .or_else(|e| match value.contains(' ') {
+            true => DateTime::parse_from_rfc3339(&value.replace(' ', "+")),
+            false => Err(e),
+        })
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| {
+            AppError::Validation(format!(
+                "Invalid {} '{}', expected an RFC 3339 timestamp",
+                name, value
+            ))
+        })
+}
+
+pub(crate) fn get_as_of(
+    query_options_map: &HashMap<String, String>,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    match query_options_map.get("as_of") {
+        Some(as_of) => parse_timestamp("as_of", as_of).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn get_i64_param(
+    query_options_map: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<i64>, AppError> {
+    match query_options_map.get(name) {
+        Some(value) => value
+            .parse::<i64>()
+            .map(Some)
+            .map_err(|_| AppError::Validation(format!("Invalid {} '{}'", name, value))),
+        None => Ok(None),
+    }
+}
+
+// `step` (default 1, negative to decrement) and `initial` (default 0) of an increment
+pub(crate) fn get_increment(
+    query_options_map: &HashMap<String, String>,
+) -> Result<(i64, i64), AppError> {
+    let step = get_i64_param(query_options_map, "step")?.unwrap_or(1);
+    let initial = get_i64_param(query_options_map, "initial")?.unwrap_or(0);
+    Ok((step, initial))
+}
+
+// `format` of an export, bash by default
+pub(crate) fn get_export_format(
+    query_options_map: &HashMap<String, String>,
+) -> Result<ExportFormat, AppError> {
+    match query_options_map.get("format") {
+        Some(format) => ExportFormat::parse(format).ok_or_else(|| {
+            AppError::Validation(format!(
+                "Invalid format '{}', expected bash, dotenv, json, yaml, fish or powershell",
+                format
+            ))
+        }),
+        None => Ok(ExportFormat::Bash),
+    }
+}
+
+pub(crate) fn get_bool_param(
+    query_options_map: &HashMap<String, String>,
+    name: &str,
+    default: bool,
+) -> Result<bool, AppError> {
+    match query_options_map.get(name).map(String::as_str) {
+        None => Ok(default),
+        Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") | Some("") => Ok(true),
+        Some(value) => Err(AppError::Validation(format!(
+            "Invalid {} '{}', expected true or false",
+            name, value
+        ))),
+    }
+}
+
+// Namespaces a read falls through, nearest first; `inherit=false` reads only `namespace`.
+// The chain ends before the first ancestor `access` cannot read, so a parent never reveals
+// values to callers without access to it.
+pub(crate) fn get_lookup_chain(
+    query_options_map: &HashMap<String, String>,
+    namespace: &str,
+    access: &Access,
+    connection: &mut PgConnection,
+) -> Result<Vec<String>, AppError> {
+    if get_bool_param(query_options_map, "inherit", true)? {
+        Ok(Namespace::chain(namespace, connection)?
+            .into_iter()
+            .take_while(|namespace| access.allows(namespace))
+            .collect())
+    } else {
+        Ok(vec![String::from(namespace)])
+    }
+}
+
+// `prefix`, plus at most one of `glob` and `regex`
+pub(crate) fn get_key_filter(
+    query_options_map: &HashMap<String, String>,
+) -> Result<KeyFilter, AppError> {
+    let pattern = match (
+        query_options_map.get("glob"),
+        query_options_map.get("regex"),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::Validation(String::from(
+                "glob and regex cannot be combined",
+            )))
+        }
+        (Some(glob), None) => Some(
+            compile_glob(glob)
+                .map_err(|_| AppError::Validation(format!("Invalid glob '{}'", glob)))?,
+        ),
+        (None, Some(regex)) => Some(
+            compile_regex(regex)
+                .map_err(|e| AppError::Validation(format!("Invalid regex '{}': {}", regex, e)))?,
+        ),
+        (None, None) => None,
+    };
+
+    Ok(KeyFilter {
+        prefix: query_options_map.get("prefix").cloned(),
+        pattern,
+    })
+}
+
+pub(crate) const MAX_LIST_LIMIT: i64 = 1000;
+
+// Items rendered into each chunk of a streamed listing
+const STREAM_CHUNK_ITEMS: usize = 256;
+
+// `sort`, `order`, `cursor` and `as_of`
+pub(crate) fn get_list_query(
+    query_options_map: &HashMap<String, String>,
+) -> Result<ListQuery, AppError> {
+    let sort = match query_options_map.get("sort").map(String::as_str) {
+        None | Some("key") => ListSort::Key,
+        Some("updated_at") => ListSort::UpdatedAt,
+        Some(sort) => {
+            return Err(AppError::Validation(format!(
+                "Invalid sort '{}', expected key or updated_at",
+                sort
+            )))
+        }
+    };
+    let descending = match query_options_map.get("order").map(String::as_str) {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => {
+            return Err(AppError::Validation(format!(
+                "Invalid order '{}', expected asc or desc",
+                order
+            )))
+        }
     };
-    String::from("")
+    let after = match query_options_map.get("cursor") {
+        Some(cursor) => Some(decode_cursor(cursor, sort)?),
+        None => None,
+    };
+
+    Ok(ListQuery {
+        as_of: get_as_of(query_options_map)?,
+        sort,
+        descending,
+        after,
+    })
+}
+
+// `None` lists everything; a limit returns one page and a cursor for the next
+pub(crate) fn get_list_limit(
+    query_options_map: &HashMap<String, String>,
+) -> Result<Option<i64>, AppError> {
+    let limit = get_i64_param(query_options_map, "limit")?;
+    if limit.is_some_and(|limit| !(1..=MAX_LIST_LIMIT).contains(&limit)) {
+        return Err(AppError::Validation(format!(
+            "limit must be between 1 and {}",
+            MAX_LIST_LIMIT
+        )));
+    }
+    Ok(limit)
 }
 
-fn req_query_to_map(query_string: String) -> HashMap<String, String> {
-    let query_map: HashMap<String, String> = match query_string.as_str() {
-        "" => HashMap::new(),
-        _ => {
-            let query_vec: Vec<&str> = query_string.split('&').collect();
+// A key cursor is the key itself; an `updated_at` cursor is `<timestamp>|<key>`
+pub(crate) fn encode_cursor(cursor: &ListCursor) -> String {
+    match cursor.updated_at {
+        Some(updated_at) => format!(
+            "{}|{}",
+            updated_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            cursor.key
+        ),
+        None => cursor.key.clone(),
+    }
+}
 
-            query_vec
-                .iter()
-                .fold(HashMap::new(), |mut acc, query_element| {
-                    let query_element_vec: Vec<&str> = query_element.split('=').collect();
-                    // Only process valid query parameters with key and value
-                    if let (Some(key), Some(val)) =
-                        (query_element_vec.get(0), query_element_vec.get(1))
-                    {
-                        acc.insert(String::from(*key), String::from(*val));
-                    }
-                    acc
-                })
+fn decode_cursor(cursor: &str, sort: ListSort) -> Result<ListCursor, AppError> {
+    match sort {
+        ListSort::Key => Ok(ListCursor {
+            key: String::from(cursor),
+            updated_at: None,
+        }),
+        ListSort::UpdatedAt => match cursor.split_once('|') {
+            Some((updated_at, key)) => Ok(ListCursor {
+                key: String::from(key),
+                updated_at: Some(parse_timestamp("cursor", updated_at)?),
+            }),
+            None => Err(AppError::Validation(format!(
+                "Invalid cursor '{}' for sort=updated_at",
+                cursor
+            ))),
+        },
+    }
+}
+
+// Collects at most `limit` items, plus the cursor to continue from when more remain
+pub(crate) fn list_page<C: BatchConnection>(
+    listed: ItemListIter<C>,
+    sort: ListSort,
+    limit: i64,
+) -> Result<(Vec<ListedItem>, Option<String>), AppError> {
+    let mut page = listed
+        .take(limit as usize + 1)
+        .collect::<Result<Vec<ListedItem>, _>>()?;
+
+    let next_cursor = match page.len() as i64 > limit {
+        true => {
+            page.truncate(limit as usize);
+            page.last()
+                .map(|listed| encode_cursor(&ListCursor::after(&listed.item, sort)))
+        }
+        false => None,
+    };
+
+    Ok((page, next_cursor))
+}
+
+// Streams `head`, each item rendered by `render` and `tail`, without holding the listing in
+// memory, or only `empty` when it is given and nothing is listed. `render` is told whether
+// the item is the first. A failure to fetch the first batch is returned as a regular error;
+// later failures, including running past LITTLE_LOOKUP_STREAM_TIMEOUT_SECS, abort the
+// response.
+pub(crate) fn stream_items<C, F>(
+    listed: ItemListIter<C>,
+    head: String,
+    tail: &'static str,
+    empty: Option<&'static str>,
+    mut render: F,
+) -> Result<HttpResponse, AppError>
+where
+    C: BatchConnection + 'static,
+    F: FnMut(ListedItem, bool) -> String + 'static,
+{
+    let mut listed = match get_stream_timeout() {
+        0 => listed,
+        timeout => listed.with_deadline(Instant::now() + Duration::from_secs(timeout)),
+    };
+    let first = listed.next().transpose()?;
+    if let (None, Some(empty)) = (&first, empty) {
+        return Ok(HttpResponse::Ok().body(empty));
+    }
+
+    let items = first
+        .into_iter()
+        .map(Ok)
+        .chain(listed)
+        .enumerate()
+        .map(move |(i, listed)| listed.map(|listed| render(listed, i == 0)));
+
+    let body = stream::iter([Ok(head)])
+        .chain(
+            stream::iter(items)
+                .ready_chunks(STREAM_CHUNK_ITEMS)
+                .map(|chunk| chunk.into_iter().collect::<Result<String, AppError>>()),
+        )
+        .chain(stream::iter([Ok(String::from(tail))]))
+        .map(|chunk| chunk.map(web::Bytes::from));
+
+    Ok(HttpResponse::Ok().streaming(body))
+}
+
+pub(crate) const MAX_HISTORY_LIMIT: i64 = 1000;
+
+// `default_limit` applies when the client does not pass `limit`
+pub(crate) fn get_history_query(
+    query_options_map: &HashMap<String, String>,
+    default_limit: Option<i64>,
+) -> Result<HistoryQuery, AppError> {
+    let limit = get_i64_param(query_options_map, "limit")?.or(default_limit);
+    if let Some(limit) = limit {
+        if !(1..=MAX_HISTORY_LIMIT).contains(&limit) {
+            return Err(AppError::Validation(format!(
+                "limit must be between 1 and {}",
+                MAX_HISTORY_LIMIT
+            )));
+        }
+    }
+
+    let offset = get_i64_param(query_options_map, "offset")?;
+    if offset.is_some_and(|offset| offset < 0) {
+        return Err(AppError::Validation(String::from(
+            "offset must not be negative",
+        )));
+    }
+
+    let since = match query_options_map.get("since") {
+        Some(since) => Some(parse_timestamp("since", since)?),
+        None => None,
+    };
+    let until = match query_options_map.get("until") {
+        Some(until) => Some(parse_timestamp("until", until)?),
+        None => None,
+    };
+
+    Ok(HistoryQuery {
+        limit,
+        offset,
+        before_version: get_i64_param(query_options_map, "before")?,
+        since,
+        until,
+    })
+}
+
+// `ttl` in seconds or an absolute `expires_at`; either way the expiry must lie in the future
+pub(crate) fn get_expires_at(
+    query_options_map: &HashMap<String, String>,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    let expires_at = match query_options_map.get("expires_at") {
+        Some(expires_at) => Some(parse_timestamp("expires_at", expires_at)?),
+        None => None,
+    };
+    expiry(get_i64_param(query_options_map, "ttl")?, expires_at)
+}
+
+pub(crate) fn expiry(
+    ttl: Option<i64>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    let now = Utc::now();
+    let expires_at = match (ttl, expires_at) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::Validation(String::from(
+                "ttl and expires_at cannot be combined",
+            )))
+        }
+        (Some(ttl), None) => Some(
+            TimeDelta::try_seconds(ttl)
+                .and_then(|ttl| now.checked_add_signed(ttl))
+                .ok_or_else(|| AppError::Validation(format!("Invalid ttl '{}'", ttl)))?,
+        ),
+        (None, expires_at) => expires_at,
+    };
+
+    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AppError::Validation(String::from(
+            "ttl and expires_at must lie in the future",
+        )));
+    }
+    Ok(expires_at)
+}
+
+pub(crate) enum ExpectedVersion {
+    // `None` for `If-Match: *`, which only requires the key to exist
+    IfMatch(Option<i64>),
+    Param(i64),
+}
+
+pub(crate) fn get_expected_version(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+) -> Result<Option<ExpectedVersion>, AppError> {
+    let param_version = get_i64_param(query_options_map, "expected_version")?;
+
+    let if_match_version = match req.headers().get(header::IF_MATCH) {
+        Some(if_match) => {
+            let if_match = if_match
+                .to_str()
+                .map_err(|_| AppError::Validation(String::from("Invalid If-Match header")))?
+                .trim();
+            match if_match {
+                "*" => Some(None),
+                _ => Some(Some(if_match.trim_matches('"').parse::<i64>().map_err(
+                    |_| AppError::Validation(format!("Invalid If-Match '{}'", if_match)),
+                )?)),
+            }
         }
+        None => None,
     };
 
-    query_map
+    match (if_match_version, param_version) {
+        (Some(Some(if_match)), Some(param)) if if_match != param => Err(AppError::Validation(
+            String::from("If-Match and expected_version disagree"),
+        )),
+        (Some(if_match), _) => Ok(Some(ExpectedVersion::IfMatch(if_match))),
+        (None, Some(param)) => Ok(Some(ExpectedVersion::Param(param))),
+        (None, None) => Ok(None),
+    }
+}
+
+// Longest change comment a client may attach to a write
+pub(crate) const MAX_COMMENT_CHARS: usize = 1024;
+
+// Longer user agents are cut off before they are stored
+const MAX_USER_AGENT_CHARS: usize = 512;
+
+// Who is making a change, for the audit trail stored on every version it writes.
+// `written_by` is the identity returned by `authorize`.
+pub(crate) fn get_author(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+    written_by: Option<String>,
+) -> Result<Author, AppError> {
+    let comment = query_options_map
+        .get("comment")
+        .filter(|comment| !comment.is_empty());
+    if comment.is_some_and(|comment| comment.chars().count() > MAX_COMMENT_CHARS) {
+        return Err(AppError::Validation(format!(
+            "comment must be at most {} characters",
+            MAX_COMMENT_CHARS
+        )));
+    }
+
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .filter(|user_agent| !user_agent.is_empty())
+        .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_CHARS).collect());
+
+    Ok(Author {
+        written_by,
+        source_ip: client_ip(req),
+        user_agent,
+        comment: comment.cloned(),
+    })
+}
+
+// The peer address, or the first X-Forwarded-For entry when the proxy in front is trusted
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    if get_trust_forwarded_for() {
+        let forwarded_for = req
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|forwarded_for| forwarded_for.to_str().ok())
+            .and_then(|forwarded_for| forwarded_for.split(',').next())
+            .and_then(|client| client.trim().parse::<IpAddr>().ok());
+        if let Some(client) = forwarded_for {
+            return Some(client.to_string());
+        }
+    }
+    req.peer_addr().map(|peer| peer.ip().to_string())
 }
 
-fn sql_pool_handler(pool: web::Data<Pool>) -> Result<PooledConnection, PoolError> {
-    match pool.get() {
-        Ok(sql_pooled_connection) => Ok(sql_pooled_connection),
-        Err(e) => {
-            error!("{}", e);
-            Err(e)
+// A stale `expected_version` is a 409 Conflict, a stale `If-Match` a 412 Precondition Failed
+pub(crate) fn write_item(
+    key_id: &str,
+    value: &str,
+    namespace: &str,
+    expected_version: Option<ExpectedVersion>,
+    expires_at: Option<DateTime<Utc>>,
+    author: &Author,
+    connection: &mut PgConnection,
+) -> Result<Item, AppError> {
+    match expected_version {
+        None => Ok(Item::replace_into(
+            key_id, value, namespace, expires_at, author, connection,
+        )?),
+        Some(ExpectedVersion::Param(version)) => Item::replace_into_if(
+            key_id, value, namespace, version, expires_at, author, connection,
+        ),
+        Some(ExpectedVersion::IfMatch(version)) => {
+            let version = match version {
+                Some(version) => version,
+                None => match Item::live_version(key_id, namespace, connection)? {
+                    0 => {
+                        return Err(AppError::PreconditionFailed(String::from(
+                            "If-Match: * requires an existing key",
+                        )))
+                    }
+                    current_version => current_version,
+                },
+            };
+            Item::replace_into_if(
+                key_id, value, namespace, version, expires_at, author, connection,
+            )
+            .map_err(|e| match e {
+                AppError::Conflict(message) => AppError::PreconditionFailed(message),
+                e => e,
+            })
         }
     }
 }
 
+pub(crate) fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+pub(crate) fn sql_pool_handler(pool: web::Data<Pool>) -> Result<PooledConnection, AppError> {
+    pool.get().map_err(AppError::from)
+}
+
 // Route handler functions
 
 pub async fn index() -> HttpResponse {
@@ -72,7 +608,7 @@ pub async fn index() -> HttpResponse {
 <li>/history/$KEY : Get history for $KEY</li>
 <li>/update/$KEY/$VAL : Update $VAL for $KEY</li>
 <li>/list?delim=$FOO : List all keys, optional custom delimiter $BAR (defaults to space)</li>
-<li>/script : Get bash script to export all keys</li>
+<li>/script?format=$FORMAT : Export all keys as a bash (default), dotenv, json, yaml, fish or powershell script</li>
 <li>/delete/$KEY : Delete $VAL for $KEY</li>
 </ul>";
     HttpResponse::Ok().body(body)
@@ -83,24 +619,29 @@ pub async fn delete_item(
     req: HttpRequest,
     pool: web::Data<Pool>,
 ) -> HttpResponse {
-    let query_options_map = req_query_to_map(req.query_string().to_string());
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
     let namespace: &str = get_namespace(&query_options_map);
-    let psk_result = check_psk(&query_options_map, PSKType::WRITE);
-    if psk_result.len() > 0 {
-        return HttpResponse::Unauthorized().body(psk_result);
+    let author = match authorize(&req, &query_options_map, &pool, Scope::Delete, namespace)
+        .and_then(|written_by| get_author(&req, &query_options_map, written_by))
+    {
+        Ok(author) => author,
+        Err(e) => return e.plain_text_response(),
     };
 
     let mut sql_pooled_connection = match sql_pool_handler(pool) {
         Ok(sql_pooled_connection) => sql_pooled_connection,
-        Err(_) => return HttpResponse::InternalServerError().body("Database connection failed"),
+        Err(e) => return e.plain_text_response(),
     };
 
-    match Item::destroy(id.as_str(), namespace, &mut sql_pooled_connection) {
-        Ok(delete_count) => HttpResponse::Ok().body(format!("{} items deleted", delete_count)),
-        Err(e) => {
-            error!("Failed to delete item '{}': {}", id, e);
-            HttpResponse::InternalServerError().body("Failed to delete item")
-        }
+    match Item::destroy(id.as_str(), namespace, &author, &mut sql_pooled_connection) {
+        Ok(tombstone) => HttpResponse::Ok().body(format!(
+            "{} items deleted",
+            usize::from(tombstone.is_some())
+        )),
+        Err(e) => AppError::from(e).plain_text_response(),
     }
 }
 
@@ -109,21 +650,46 @@ pub async fn get_item(
     req: HttpRequest,
     pool: web::Data<Pool>,
 ) -> HttpResponse {
-    let query_options_map = req_query_to_map(req.query_string().to_string());
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
     let namespace: &str = get_namespace(&query_options_map);
-    let psk_result = check_psk(&query_options_map, PSKType::READ);
-    if psk_result.len() > 0 {
-        return HttpResponse::Unauthorized().body(psk_result);
+    let access = match authorize_read(&req, &query_options_map, &pool, namespace) {
+        Ok(access) => access,
+        Err(e) => return e.plain_text_response(),
+    };
+    let as_of = match get_as_of(&query_options_map) {
+        Ok(as_of) => as_of,
+        Err(e) => return e.plain_text_response(),
     };
 
     let mut sql_pooled_connection = match sql_pool_handler(pool) {
         Ok(sql_pooled_connection) => sql_pooled_connection,
-        Err(_) => return HttpResponse::Unauthorized().body("SQL Error"),
+        Err(e) => return e.plain_text_response(),
+    };
+
+    let chain = match get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    ) {
+        Ok(chain) => chain,
+        Err(e) => return e.plain_text_response(),
     };
 
-    match Item::find(id.as_str(), namespace, &mut sql_pooled_connection) {
-        Ok(item) => HttpResponse::Ok().body(item.val),
-        _ => HttpResponse::NotFound().body("Undefined"),
+    match Item::find_layered(id.as_str(), &chain, as_of, &mut sql_pooled_connection) {
+        Ok(item) => {
+            let mut response = HttpResponse::Ok();
+            response.insert_header(("X-Little-Lookup-Layer", item.namespace.as_str()));
+            // An inherited value has no version in this namespace to match against
+            if item.namespace == namespace {
+                response.insert_header((header::ETAG, etag(item.version)));
+            }
+            response.body(item.val)
+        }
+        Err(e) => AppError::from(e).plain_text_response(),
     }
 }
 
@@ -132,22 +698,33 @@ pub async fn history_item(
     req: HttpRequest,
     pool: web::Data<Pool>,
 ) -> HttpResponse {
-    let query_options_map = req_query_to_map(req.query_string().to_string());
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
     let namespace: &str = get_namespace(&query_options_map);
-    let psk_result = check_psk(&query_options_map, PSKType::READ);
-    if psk_result.len() > 0 {
-        return HttpResponse::Unauthorized().body(psk_result);
+    if let Err(e) = authorize(&req, &query_options_map, &pool, Scope::Read, namespace) {
+        return e.plain_text_response();
+    };
+    let history_query = match get_history_query(&query_options_map, None) {
+        Ok(history_query) => history_query,
+        Err(e) => return e.plain_text_response(),
     };
 
     let mut sql_pooled_connection = match sql_pool_handler(pool) {
         Ok(sql_pooled_connection) => sql_pooled_connection,
-        Err(_) => return HttpResponse::Unauthorized().body("SQL Error"),
+        Err(e) => return e.plain_text_response(),
     };
 
-    match Item::history(id.as_str(), namespace, &mut sql_pooled_connection) {
+    match Item::history_page(
+        id.as_str(),
+        namespace,
+        &history_query,
+        &mut sql_pooled_connection,
+    ) {
         Ok(item_list) => {
             let mut val_list: Vec<String> = Vec::new();
-            for item in item_list.iter() {
+            for item in item_list.iter().filter(|item| !item.deleted) {
                 val_list.push(item.val.clone())
             }
 
@@ -155,86 +732,148 @@ pub async fn history_item(
 
             HttpResponse::Ok().body(body_string)
         }
-        _ => HttpResponse::NotFound().body("Undefined"),
+        Err(e) => AppError::from(e).plain_text_response(),
     }
 }
 
 pub async fn list_items(req: HttpRequest, pool: web::Data<Pool>) -> HttpResponse {
-    let query_options_map = req_query_to_map(req.query_string().to_string());
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
     let namespace: &str = get_namespace(&query_options_map);
-    let psk_result = check_psk(&query_options_map, PSKType::READ);
-    if psk_result.len() > 0 {
-        return HttpResponse::Unauthorized().body(psk_result);
+    let access = match authorize_read(&req, &query_options_map, &pool, namespace) {
+        Ok(access) => access,
+        Err(e) => return e.plain_text_response(),
+    };
+    let list_query = match get_list_query(&query_options_map) {
+        Ok(list_query) => list_query,
+        Err(e) => return e.plain_text_response(),
+    };
+    let limit = match get_list_limit(&query_options_map) {
+        Ok(limit) => limit,
+        Err(e) => return e.plain_text_response(),
+    };
+    let key_filter = match get_key_filter(&query_options_map) {
+        Ok(key_filter) => key_filter,
+        Err(e) => return e.plain_text_response(),
     };
 
-    let mut sql_pooled_connection = match sql_pool_handler(pool) {
+    let mut sql_pooled_connection = match sql_pool_handler(pool.clone()) {
         Ok(sql_pooled_connection) => sql_pooled_connection,
-        Err(_) => return HttpResponse::InternalServerError().body("Database connection failed"),
+        Err(e) => return e.plain_text_response(),
+    };
+
+    let chain = match get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    ) {
+        Ok(chain) => chain,
+        Err(e) => return e.plain_text_response(),
+    };
+
+    let delimiter: String = match query_options_map.get("delim") {
+        Some(d) => d.clone(),
+        None => String::from(" "),
+    };
+    let render = move |listed: ListedItem, _first: bool| {
+        format!("{}{}{}\n", listed.item.key, delimiter, listed.item.val)
     };
 
-    let results = match ItemList::list(&mut sql_pooled_connection, namespace) {
-        Ok(items) => items,
-        Err(e) => {
-            error!("Failed to list items: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to retrieve items");
+    let sort = list_query.sort;
+    drop(sql_pooled_connection);
+    let listed = ItemList::iter(pool.get_ref().clone(), &chain, key_filter, list_query);
+
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            return stream_items(listed, String::from("<pre>\n"), "</pre>", None, render)
+                .unwrap_or_else(|e| e.plain_text_response())
         }
     };
 
-    let delimiter: &str = match query_options_map.get("delim") {
-        Some(d) => d.as_str(),
-        None => " ",
+    let (page, next_cursor) = match list_page(listed, sort, limit) {
+        Ok(page) => page,
+        Err(e) => return e.plain_text_response(),
     };
 
-    let result_collection: String = results.iter().fold(String::from(""), |mut acc, result| {
-        let _ = &acc.push_str(&result.key);
-        let _ = &acc.push_str(delimiter);
-        let _ = &acc.push_str(&result.val);
-        let _ = &acc.push_str("\n");
-        acc
-    });
+    let result_collection: String = page
+        .into_iter()
+        .map(|listed| render(listed, false))
+        .collect();
 
     let body_string: String = format!("<pre>\n{}</pre>", result_collection);
 
-    HttpResponse::Ok().body(body_string)
+    let mut response = HttpResponse::Ok();
+    if let Some(next_cursor) = next_cursor {
+        response.insert_header(("X-Little-Lookup-Next-Cursor", next_cursor));
+    }
+    response.body(body_string)
 }
 
 pub async fn script(req: HttpRequest, pool: web::Data<Pool>) -> HttpResponse {
-    let query_options_map = req_query_to_map(req.query_string().to_string());
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
     let namespace: &str = get_namespace(&query_options_map);
-    let psk_result = check_psk(&query_options_map, PSKType::READ);
-    if psk_result.len() > 0 {
-        return HttpResponse::Unauthorized().body(psk_result);
+    let access = match authorize_read(&req, &query_options_map, &pool, namespace) {
+        Ok(access) => access,
+        Err(e) => return e.plain_text_response(),
+    };
+    let as_of = match get_as_of(&query_options_map) {
+        Ok(as_of) => as_of,
+        Err(e) => return e.plain_text_response(),
+    };
+    let key_filter = match get_key_filter(&query_options_map) {
+        Ok(key_filter) => key_filter,
+        Err(e) => return e.plain_text_response(),
+    };
+    let format = match get_export_format(&query_options_map) {
+        Ok(format) => format,
+        Err(e) => return e.plain_text_response(),
     };
 
-    let mut sql_pooled_connection = match sql_pool_handler(pool) {
+    let mut sql_pooled_connection = match sql_pool_handler(pool.clone()) {
         Ok(sql_pooled_connection) => sql_pooled_connection,
-        Err(_) => return HttpResponse::InternalServerError().body("Database connection failed"),
+        Err(e) => return e.plain_text_response(),
     };
 
-    let results = match ItemList::list(&mut sql_pooled_connection, namespace) {
-        Ok(items) => items,
-        Err(e) => {
-            error!("Failed to generate script: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to generate script");
-        }
+    let chain = match get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    ) {
+        Ok(chain) => chain,
+        Err(e) => return e.plain_text_response(),
     };
 
-    let result_collection: String =
-        results
-            .iter()
-            .fold(String::from("#!/bin/bash\n"), |mut acc, result| {
-                let _ = &acc.push_str("export ");
-                let _ = &acc.push_str(&result.key);
-                let _ = &acc.push_str("='");
-                let _ = &acc.push_str(&result.val);
-                let _ = &acc.push_str("'");
-                let _ = &acc.push_str("\n");
-                acc
-            });
-
-    let body_string: String = format!("<pre>\n{}</pre>", result_collection);
-
-    HttpResponse::Ok().body(body_string)
+    let list_query = ListQuery {
+        as_of,
+        ..ListQuery::default()
+    };
+    drop(sql_pooled_connection);
+    let listed = ItemList::iter(pool.get_ref().clone(), &chain, key_filter, list_query);
+
+    match stream_items(
+        listed,
+        String::from(format.head()),
+        format.tail(),
+        format.empty(),
+        move |listed, first| format.render(&listed.item.key, &listed.item.val, first),
+    ) {
+        Ok(mut response) => {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static(format.content_type()),
+            );
+            response
+        }
+        Err(e) => e.plain_text_response(),
+    }
 }
 
 pub async fn update_item(
@@ -243,29 +882,92 @@ pub async fn update_item(
     pool: web::Data<Pool>,
 ) -> HttpResponse {
     let (id, val) = params.into_inner();
-    let query_options_map = req_query_to_map(req.query_string().to_string());
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
     let namespace: &str = get_namespace(&query_options_map);
-    let psk_result = check_psk(&query_options_map, PSKType::WRITE);
-    if psk_result.len() > 0 {
-        return HttpResponse::Unauthorized().body(psk_result);
+    let author = match authorize(&req, &query_options_map, &pool, Scope::Write, namespace)
+        .and_then(|written_by| get_author(&req, &query_options_map, written_by))
+    {
+        Ok(author) => author,
+        Err(e) => return e.plain_text_response(),
+    };
+
+    let expected_version = match get_expected_version(&req, &query_options_map) {
+        Ok(expected_version) => expected_version,
+        Err(e) => return e.plain_text_response(),
+    };
+    let expires_at = match get_expires_at(&query_options_map) {
+        Ok(expires_at) => expires_at,
+        Err(e) => return e.plain_text_response(),
     };
 
     let mut sql_pooled_connection = match sql_pool_handler(pool) {
         Ok(sql_pooled_connection) => sql_pooled_connection,
-        Err(_) => return HttpResponse::InternalServerError().body("Database connection failed"),
+        Err(e) => return e.plain_text_response(),
     };
 
-    match Item::replace_into(
+    match write_item(
         id.as_str(),
         val.as_str(),
         namespace,
+        expected_version,
+        expires_at,
+        &author,
         &mut sql_pooled_connection,
     ) {
-        Ok(_) => HttpResponse::Ok().body(val),
-        Err(e) => {
-            error!("Failed to update item '{}': {}", id, e);
-            HttpResponse::InternalServerError().body("Failed to update item")
-        }
+        Ok(item) => HttpResponse::Ok()
+            .insert_header((header::ETAG, etag(item.version)))
+            .body(val),
+        Err(e) => e.plain_text_response(),
+    }
+}
+
+pub async fn increment_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> HttpResponse {
+    let query_options_map = match req_query_to_map(req.query_string()) {
+        Ok(query_options_map) => query_options_map,
+        Err(e) => return e.plain_text_response(),
+    };
+    let namespace: &str = get_namespace(&query_options_map);
+    let author = match authorize(&req, &query_options_map, &pool, Scope::Write, namespace)
+        .and_then(|written_by| get_author(&req, &query_options_map, written_by))
+    {
+        Ok(author) => author,
+        Err(e) => return e.plain_text_response(),
+    };
+
+    let (step, initial) = match get_increment(&query_options_map) {
+        Ok(increment) => increment,
+        Err(e) => return e.plain_text_response(),
+    };
+    let expires_at = match get_expires_at(&query_options_map) {
+        Ok(expires_at) => expires_at,
+        Err(e) => return e.plain_text_response(),
+    };
+
+    let mut sql_pooled_connection = match sql_pool_handler(pool) {
+        Ok(sql_pooled_connection) => sql_pooled_connection,
+        Err(e) => return e.plain_text_response(),
+    };
+
+    match Item::increment(
+        id.as_str(),
+        namespace,
+        step,
+        initial,
+        expires_at,
+        &author,
+        &mut sql_pooled_connection,
+    ) {
+        Ok(item) => HttpResponse::Ok()
+            .insert_header((header::ETAG, etag(item.version)))
+            .body(item.val),
+        Err(e) => e.plain_text_response(),
     }
 }
 
@@ -278,15 +980,21 @@ mod tests {
     };
     use serial_test::serial;
 
+    use crate::auth::PSK_HEADER;
     use crate::db_connection::establish_connection;
 
     use super::*;
 
+    fn purge(pool: &Pool, ns: &str, key: &str) {
+        let mut connection = pool.get().expect("Failed to get connection");
+        Item::purge(key, ns, &mut connection).expect("Failed to purge");
+    }
+
     #[actix_rt::test]
     #[serial]
     async fn test_index() {
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/", web::get().to(index)),
@@ -294,7 +1002,7 @@ mod tests {
         .await;
 
         let req = test::TestRequest::get().uri("/").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::OK);
 
         let body: web::Bytes = test::read_body(resp).await;
@@ -308,7 +1016,7 @@ mod tests {
     #[serial]
     async fn test_script() {
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/script", web::get().to(script)),
@@ -316,11 +1024,85 @@ mod tests {
         .await;
 
         let req = test::TestRequest::get().uri("/script?ns=test").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::OK);
 
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+
         let body: web::Bytes = test::read_body(resp).await;
-        assert_eq!(body, "<pre>\n#!/bin/bash\n</pre>");
+        assert_eq!(body, "#!/bin/bash\n");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_script_quotes_values_and_formats() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/script", web::get().to(script)),
+        )
+        .await;
+
+        let ns = "test_script_quoting_ns";
+        {
+            let mut connection = pool.get().expect("Failed to get connection");
+            for (key, value) in [("GREETING", "it's"), ("db.host", "x")] {
+                Item::purge(key, ns, &mut connection).expect("Failed to purge");
+                Item::replace_into(key, value, ns, None, &Author::default(), &mut connection)
+                    .expect("Failed to write");
+            }
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/script?ns={}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body = test::read_body(resp).await;
+        assert_eq!(
+            body,
+            "#!/bin/bash\nexport GREETING='it'\\''s'\n# Skipped \"db.host\": not a valid variable name\n"
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/script?ns={}&format=json", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body,
+            serde_json::json!({"GREETING": "it's", "db.host": "x"})
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/script?ns={}&format=toml", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+        // An empty namespace is an empty map, not a null document
+        let req = test::TestRequest::get()
+            .uri("/script?ns=test_script_empty_ns&format=yaml")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/yaml"
+        );
+        assert_eq!(test::read_body(resp).await, "{}\n");
+
+        purge(&pool, ns, "GREETING");
+        purge(&pool, ns, "db.host");
     }
 
     #[actix_rt::test]
@@ -330,155 +1112,510 @@ mod tests {
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
-        let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
-            App::new()
-                .app_data(Data::new(pool))
-                .route("/update/{id}/{val}", web::put().to(update_item)),
-        )
-        .await;
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/update/{id}/{val}", web::put().to(update_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/update/testkey/testvalue")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "testvalue");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_increment_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        purge(&pool, "test_incr_ns", "build_number");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/incr/{id}", web::post().to(increment_item))
+                .route("/update/{id}/{val}", web::get().to(update_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/incr/build_number?ns=test_incr_ns&initial=100")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "101");
+
+        let req = test::TestRequest::post()
+            .uri("/incr/build_number?ns=test_incr_ns&step=-2")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"2\"");
+        assert_eq!(test::read_body(resp).await, "99");
+
+        let req = test::TestRequest::post()
+            .uri("/incr/build_number?ns=test_incr_ns&step=one")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get()
+            .uri("/update/build_number/abc?ns=test_incr_ns")
+            .to_request();
+        let _ = test::call_service(&app, req).await;
+        let req = test::TestRequest::post()
+            .uri("/incr/build_number?ns=test_incr_ns")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+
+        // Only POST increments, so a prefetched link cannot
+        let req = test::TestRequest::get()
+            .uri("/incr/build_number?ns=test_incr_ns")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        purge(&pool, "test_incr_ns", "build_number");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_get_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/get/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let update_req = test::TestRequest::get()
+            .uri("/update/test_get_key/test_get_value?ns=test_get_ns")
+            .to_request();
+        let _ = test::call_service(&app, update_req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/get/test_get_key?ns=test_get_ns")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "test_get_value");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_get_item_not_found() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/get/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/get/nonexistent_key_xyz?ns=nonexistent_ns")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "Undefined");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_history_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/history/{id}", web::get().to(history_item)),
+        )
+        .await;
+
+        let ns = "test_history_ns";
+        let key = "test_history_key";
+
+        let update1 = test::TestRequest::get()
+            .uri(&format!("/update/{}/value1?ns={}", key, ns))
+            .to_request();
+        let _ = test::call_service(&app, update1).await;
+
+        let update2 = test::TestRequest::get()
+            .uri(&format!("/update/{}/value2?ns={}", key, ns))
+            .to_request();
+        let _ = test::call_service(&app, update2).await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/history/{}?ns={}", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body = test::read_body(resp).await;
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body_str.contains("value1"));
+        assert!(body_str.contains("value2"));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_get_item_etag_and_conditional_update() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/delete/{id}", web::get().to(delete_item))
+                .route("/get/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let ns = "test_etag_ns";
+        let key = "test_etag_key";
+
+        purge(&pool, ns, key);
+
+        // Create-only write
+        let create_req = test::TestRequest::get()
+            .uri(&format!("/update/{}/v1?ns={}&expected_version=0", key, ns))
+            .to_request();
+        let create_resp = test::call_service(&app, create_req).await;
+        assert_eq!(create_resp.status(), http::StatusCode::OK);
+        assert_eq!(create_resp.headers().get(header::ETAG).unwrap(), "\"1\"");
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/get/{}?ns={}", key, ns))
+            .to_request();
+        let get_resp = test::call_service(&app, get_req).await;
+        let etag_value = get_resp.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(etag_value, "\"1\"");
+
+        let update_req = test::TestRequest::get()
+            .uri(&format!("/update/{}/v2?ns={}", key, ns))
+            .insert_header((header::IF_MATCH, etag_value.clone()))
+            .to_request();
+        let update_resp = test::call_service(&app, update_req).await;
+        assert_eq!(update_resp.status(), http::StatusCode::OK);
+        assert_eq!(update_resp.headers().get(header::ETAG).unwrap(), "\"2\"");
+
+        // Stale If-Match
+        let stale_req = test::TestRequest::get()
+            .uri(&format!("/update/{}/v3?ns={}", key, ns))
+            .insert_header((header::IF_MATCH, etag_value))
+            .to_request();
+        let stale_resp = test::call_service(&app, stale_req).await;
+        assert_eq!(stale_resp.status(), http::StatusCode::PRECONDITION_FAILED);
 
-        let req = test::TestRequest::put()
-            .uri("/update/testkey/testvalue")
+        // Stale expected_version
+        let stale_req = test::TestRequest::get()
+            .uri(&format!("/update/{}/v3?ns={}&expected_version=1", key, ns))
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        let stale_resp = test::call_service(&app, stale_req).await;
+        assert_eq!(stale_resp.status(), http::StatusCode::CONFLICT);
 
-        let body = test::read_body(resp).await;
-        assert_eq!(body, "testvalue");
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/get/{}?ns={}", key, ns))
+            .to_request();
+        let get_resp = test::call_service(&app, get_req).await;
+        let body = test::read_body(get_resp).await;
+        assert_eq!(body, "v2");
     }
 
     #[actix_rt::test]
     #[serial]
-    async fn test_get_item() {
+    async fn test_get_list_and_script_as_of() {
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item))
-                .route("/get/{id}", web::get().to(get_item)),
+                .route("/delete/{id}", web::get().to(delete_item))
+                .route("/get/{id}", web::get().to(get_item))
+                .route("/list", web::get().to(list_items))
+                .route("/script", web::get().to(script)),
         )
         .await;
 
+        let ns = "test_as_of_ns";
+
+        for key in ["key1", "key2"] {
+            let delete_req = test::TestRequest::get()
+                .uri(&format!("/delete/{}?ns={}", key, ns))
+                .to_request();
+            let _ = test::call_service(&app, delete_req).await;
+        }
+
         let update_req = test::TestRequest::get()
-            .uri("/update/test_get_key/test_get_value?ns=test_get_ns")
+            .uri(&format!("/update/key1/old?ns={}", ns))
             .to_request();
-        let _ = test::call_service(&mut app, update_req).await;
+        let _ = test::call_service(&app, update_req).await;
 
-        let req = test::TestRequest::get()
-            .uri("/get/test_get_key?ns=test_get_ns")
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let as_of = Utc::now().to_rfc3339();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        for (key, val) in [("key1", "new"), ("key2", "added")] {
+            let update_req = test::TestRequest::get()
+                .uri(&format!("/update/{}/{}?ns={}", key, val, ns))
+                .to_request();
+            let _ = test::call_service(&app, update_req).await;
+        }
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!(
+                "/get/key1?ns={}&as_of={}",
+                ns,
+                urlencoding::encode(&as_of)
+            ))
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        let get_resp = test::call_service(&app, get_req).await;
+        assert_eq!(get_resp.status(), http::StatusCode::OK);
+        assert_eq!(test::read_body(get_resp).await, "old");
 
-        let body = test::read_body(resp).await;
-        assert_eq!(body, "test_get_value");
+        let get_req = test::TestRequest::get()
+            .uri(&format!(
+                "/get/key2?ns={}&as_of={}",
+                ns,
+                urlencoding::encode(&as_of)
+            ))
+            .to_request();
+        let get_resp = test::call_service(&app, get_req).await;
+        assert_eq!(get_resp.status(), http::StatusCode::NOT_FOUND);
+
+        let list_req = test::TestRequest::get()
+            .uri(&format!(
+                "/list?ns={}&as_of={}",
+                ns,
+                urlencoding::encode(&as_of)
+            ))
+            .to_request();
+        let list_resp = test::call_service(&app, list_req).await;
+        assert_eq!(test::read_body(list_resp).await, "<pre>\nkey1 old\n</pre>");
+
+        let script_req = test::TestRequest::get()
+            .uri(&format!(
+                "/script?ns={}&as_of={}",
+                ns,
+                urlencoding::encode(&as_of)
+            ))
+            .to_request();
+        let script_resp = test::call_service(&app, script_req).await;
+        assert_eq!(
+            test::read_body(script_resp).await,
+            "#!/bin/bash\nexport key1='old'\n"
+        );
+
+        let bad_req = test::TestRequest::get()
+            .uri(&format!("/list?ns={}&as_of=yesterday", ns))
+            .to_request();
+        let bad_resp = test::call_service(&app, bad_req).await;
+        assert_eq!(bad_resp.status(), http::StatusCode::BAD_REQUEST);
     }
 
     #[actix_rt::test]
     #[serial]
-    async fn test_get_item_not_found() {
+    async fn test_parse_timestamp() {
+        let expected = DateTime::parse_from_rfc3339("2024-05-01T03:12:00+02:00")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_timestamp("as_of", "2024-05-01T03:12:00+02:00").unwrap(),
+            expected
+        );
+        // Unencoded `+` decoded to a space
+        assert_eq!(
+            parse_timestamp("as_of", "2024-05-01T03:12:00 02:00").unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_timestamp("as_of", "2024-05-01T01:12:00Z").unwrap(),
+            expected
+        );
+        assert!(parse_timestamp("as_of", "2024-05-01").is_err());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_list_items_with_delimiter() {
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
-                .route("/get/{id}", web::get().to(get_item)),
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/list", web::get().to(list_items)),
         )
         .await;
 
+        let ns = "test_list_delim_ns";
+
+        let update1 = test::TestRequest::get()
+            .uri(&format!("/update/key1/val1?ns={}", ns))
+            .to_request();
+        let _ = test::call_service(&app, update1).await;
+
+        let update2 = test::TestRequest::get()
+            .uri(&format!("/update/key2/val2?ns={}", ns))
+            .to_request();
+        let _ = test::call_service(&app, update2).await;
+
         let req = test::TestRequest::get()
-            .uri("/get/nonexistent_key_xyz?ns=nonexistent_ns")
+            .uri(&format!("/list?ns={}&delim=|", ns))
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
 
         let body = test::read_body(resp).await;
-        assert_eq!(body, "Undefined");
+        let body_str = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body_str.contains("key1|val1"));
+        assert!(body_str.contains("key2|val2"));
     }
 
     #[actix_rt::test]
     #[serial]
-    async fn test_history_item() {
+    async fn test_list_and_script_with_key_filters() {
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
-                .app_data(Data::new(pool))
+                .app_data(Data::new(pool.clone()))
                 .route("/update/{id}/{val}", web::get().to(update_item))
-                .route("/history/{id}", web::get().to(history_item)),
+                .route("/list", web::get().to(list_items))
+                .route("/script", web::get().to(script)),
         )
         .await;
 
-        let ns = "test_history_ns";
-        let key = "test_history_key";
+        let ns = "test_list_key_filter_ns";
 
-        let update1 = test::TestRequest::get()
-            .uri(&format!("/update/{}/value1?ns={}", key, ns))
-            .to_request();
-        let _ = test::call_service(&mut app, update1).await;
+        for key in ["db.primary.host", "db.primary.port", "cache.host"] {
+            purge(&pool, ns, key);
+            let update_req = test::TestRequest::get()
+                .uri(&format!("/update/{}/v?ns={}", key, ns))
+                .to_request();
+            let _ = test::call_service(&app, update_req).await;
+        }
 
-        let update2 = test::TestRequest::get()
-            .uri(&format!("/update/{}/value2?ns={}", key, ns))
+        let req = test::TestRequest::get()
+            .uri(&format!("/list?ns={}&prefix=db.", ns))
             .to_request();
-        let _ = test::call_service(&mut app, update2).await;
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            "<pre>\ndb.primary.host v\ndb.primary.port v\n</pre>"
+        );
 
         let req = test::TestRequest::get()
-            .uri(&format!("/history/{}?ns={}", key, ns))
+            .uri(&format!("/list?ns={}&glob=*.host", ns))
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            "<pre>\ncache.host v\ndb.primary.host v\n</pre>"
+        );
 
-        let body = test::read_body(resp).await;
-        let body_str = String::from_utf8(body.to_vec()).unwrap();
-        assert!(body_str.contains("value1"));
-        assert!(body_str.contains("value2"));
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/script?ns={}&prefix=db.&regex={}&format=json",
+                ns,
+                urlencoding::encode("port$")
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(resp).await, "{\"db.primary.port\":\"v\"}");
+
+        for query in ["glob=*&regex=.", "regex=(unclosed"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/list?ns={}&{}", ns, query))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        }
     }
 
     #[actix_rt::test]
     #[serial]
-    async fn test_list_items_with_delimiter() {
+    async fn test_list_items_with_limit() {
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
-                .app_data(Data::new(pool))
+                .app_data(Data::new(pool.clone()))
                 .route("/update/{id}/{val}", web::get().to(update_item))
                 .route("/list", web::get().to(list_items)),
         )
         .await;
 
-        let ns = "test_list_delim_ns";
+        let ns = "test_list_limit_ns";
 
-        let update1 = test::TestRequest::get()
-            .uri(&format!("/update/key1/val1?ns={}", ns))
-            .to_request();
-        let _ = test::call_service(&mut app, update1).await;
+        for key in ["key1", "key2", "key3"] {
+            purge(&pool, ns, key);
+            let update_req = test::TestRequest::get()
+                .uri(&format!("/update/{}/v?ns={}", key, ns))
+                .to_request();
+            let _ = test::call_service(&app, update_req).await;
+        }
 
-        let update2 = test::TestRequest::get()
-            .uri(&format!("/update/key2/val2?ns={}", ns))
+        let req = test::TestRequest::get()
+            .uri(&format!("/list?ns={}&limit=2", ns))
             .to_request();
-        let _ = test::call_service(&mut app, update2).await;
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get("X-Little-Lookup-Next-Cursor").unwrap(),
+            "key2"
+        );
+        assert_eq!(test::read_body(resp).await, "<pre>\nkey1 v\nkey2 v\n</pre>");
 
         let req = test::TestRequest::get()
-            .uri(&format!("/list?ns={}&delim=|", ns))
+            .uri(&format!("/list?ns={}&limit=2&cursor=key2", ns))
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
-        assert_eq!(resp.status(), http::StatusCode::OK);
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get("X-Little-Lookup-Next-Cursor").is_none());
+        assert_eq!(test::read_body(resp).await, "<pre>\nkey3 v\n</pre>");
 
-        let body = test::read_body(resp).await;
-        let body_str = String::from_utf8(body.to_vec()).unwrap();
-        assert!(body_str.contains("key1|val1"));
-        assert!(body_str.contains("key2|val2"));
+        let req = test::TestRequest::get()
+            .uri(&format!("/list?ns={}&order=desc", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            test::read_body(resp).await,
+            "<pre>\nkey3 v\nkey2 v\nkey1 v\n</pre>"
+        );
     }
 
     #[actix_rt::test]
@@ -488,7 +1625,7 @@ mod tests {
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item))
@@ -503,18 +1640,18 @@ mod tests {
         let update_req = test::TestRequest::get()
             .uri(&format!("/update/{}/test_value?ns={}", key, ns))
             .to_request();
-        let _ = test::call_service(&mut app, update_req).await;
+        let _ = test::call_service(&app, update_req).await;
 
         let delete_req = test::TestRequest::get()
             .uri(&format!("/delete/{}?ns={}", key, ns))
             .to_request();
-        let delete_resp = test::call_service(&mut app, delete_req).await;
+        let delete_resp = test::call_service(&app, delete_req).await;
         assert_eq!(delete_resp.status(), http::StatusCode::OK);
 
         let get_req = test::TestRequest::get()
             .uri(&format!("/get/{}?ns={}", key, ns))
             .to_request();
-        let get_resp = test::call_service(&mut app, get_req).await;
+        let get_resp = test::call_service(&app, get_req).await;
         assert_eq!(get_resp.status(), http::StatusCode::NOT_FOUND);
     }
 
@@ -525,7 +1662,7 @@ mod tests {
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item))
@@ -538,33 +1675,91 @@ mod tests {
         let update_ns1 = test::TestRequest::get()
             .uri(&format!("/update/{}/value_ns1?ns=ns1", key))
             .to_request();
-        let _ = test::call_service(&mut app, update_ns1).await;
+        let _ = test::call_service(&app, update_ns1).await;
 
         let update_ns2 = test::TestRequest::get()
             .uri(&format!("/update/{}/value_ns2?ns=ns2", key))
             .to_request();
-        let _ = test::call_service(&mut app, update_ns2).await;
+        let _ = test::call_service(&app, update_ns2).await;
 
         let get_ns1 = test::TestRequest::get()
             .uri(&format!("/get/{}?ns=ns1", key))
             .to_request();
-        let resp_ns1 = test::call_service(&mut app, get_ns1).await;
+        let resp_ns1 = test::call_service(&app, get_ns1).await;
         let body_ns1 = test::read_body(resp_ns1).await;
         assert_eq!(body_ns1, "value_ns1");
 
         let get_ns2 = test::TestRequest::get()
             .uri(&format!("/get/{}?ns=ns2", key))
             .to_request();
-        let resp_ns2 = test::call_service(&mut app, get_ns2).await;
+        let resp_ns2 = test::call_service(&app, get_ns2).await;
         let body_ns2 = test::read_body(resp_ns2).await;
         assert_eq!(body_ns2, "value_ns2");
     }
 
+    #[actix_rt::test]
+    #[serial]
+    async fn test_get_item_inherited_from_parent() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/get/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let key = "inherited_key_handler";
+        let base = "legacy_inherit_base";
+        let child = "legacy_inherit_child";
+        purge(&pool, base, key);
+        purge(&pool, child, key);
+
+        let mut connection = pool.get().expect("Failed to get connection");
+        Namespace::set_parent(child, base, &mut connection).expect("Failed to set parent");
+
+        let update_req = test::TestRequest::get()
+            .uri(&format!("/update/{}/from_base?ns={}", key, base))
+            .to_request();
+        let _ = test::call_service(&app, update_req).await;
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/get/{}?ns={}", key, child))
+            .to_request();
+        let resp = test::call_service(&app, get_req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(resp.headers().get("X-Little-Lookup-Layer").unwrap(), base);
+        assert!(resp.headers().get(http::header::ETAG).is_none());
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "from_base");
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/get/{}?ns={}&inherit=false", key, child))
+            .to_request();
+        let resp = test::call_service(&app, get_req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        Namespace::remove_parent(child, &mut connection).expect("Failed to remove parent");
+    }
+
+    #[actix_rt::test]
+    async fn test_get_bool_param() {
+        let map = req_query_to_map("a=true&b=0&c=&d=maybe").unwrap();
+        assert!(get_bool_param(&map, "a", false).unwrap());
+        assert!(!get_bool_param(&map, "b", true).unwrap());
+        assert!(get_bool_param(&map, "c", false).unwrap());
+        assert!(!get_bool_param(&map, "missing", false).unwrap());
+        assert!(get_bool_param(&map, "missing", true).unwrap());
+        assert!(get_bool_param(&map, "d", false).is_err());
+    }
+
     #[actix_rt::test]
     #[serial]
     async fn test_req_query_to_map_basic() {
-        let query = String::from("key1=value1&key2=value2");
-        let map = req_query_to_map(query);
+        let map = req_query_to_map("key1=value1&key2=value2").unwrap();
 
         assert_eq!(map.len(), 2);
         assert_eq!(map.get("key1"), Some(&String::from("value1")));
@@ -574,27 +1769,26 @@ mod tests {
     #[actix_rt::test]
     #[serial]
     async fn test_req_query_to_map_empty() {
-        let query = String::from("");
-        let map = req_query_to_map(query);
+        let map = req_query_to_map("").unwrap();
         assert_eq!(map.len(), 0);
     }
 
     #[actix_rt::test]
     #[serial]
     async fn test_req_query_to_map_malformed() {
-        let query = String::from("key1=value1&malformed&key2=value2");
-        let map = req_query_to_map(query);
-
+        let map = req_query_to_map("key1=value1&flag&key2=value2").unwrap();
         assert_eq!(map.get("key1"), Some(&String::from("value1")));
+        assert_eq!(map.get("flag"), Some(&String::new()));
         assert_eq!(map.get("key2"), Some(&String::from("value2")));
-        assert!(!map.contains_key("malformed"));
+
+        let result = req_query_to_map("=value1");
+        assert!(result.is_err());
     }
 
     #[actix_rt::test]
     #[serial]
     async fn test_req_query_to_map_special_chars() {
-        let query = String::from("ns=test&delim=|");
-        let map = req_query_to_map(query);
+        let map = req_query_to_map("ns=test&delim=|").unwrap();
 
         assert_eq!(map.get("ns"), Some(&String::from("test")));
         assert_eq!(map.get("delim"), Some(&String::from("|")));
@@ -603,27 +1797,126 @@ mod tests {
     #[actix_rt::test]
     #[serial]
     async fn test_req_query_to_map_duplicate_keys() {
-        let query = String::from("key=value1&key=value2");
-        let map = req_query_to_map(query);
+        let result = req_query_to_map("key=value1&key=value2");
+        assert!(result.is_err());
 
+        let map = req_query_to_map("key=value1&key=value1").unwrap();
         assert_eq!(map.len(), 1);
-        assert!(map.get("key").is_some());
+        assert_eq!(map.get("key"), Some(&String::from("value1")));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_req_query_to_map_percent_decoding() {
+        let map = req_query_to_map("psk=abc%3D%3D&delim=%20&ns=my+ns&extra=a=b").unwrap();
+
+        assert_eq!(map.get("psk"), Some(&String::from("abc==")));
+        assert_eq!(map.get("delim"), Some(&String::from(" ")));
+        assert_eq!(map.get("ns"), Some(&String::from("my ns")));
+        assert_eq!(map.get("extra"), Some(&String::from("a=b")));
+
+        let map = req_query_to_map("ns=%E4%B8%96%E7%95%8C").unwrap();
+        assert_eq!(map.get("ns"), Some(&String::from("世界")));
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_req_query_to_map_invalid_encoding() {
+        match req_query_to_map("psk=secret%zz") {
+            Err(e) => assert!(!e.to_string().contains("secret")),
+            Ok(_) => panic!("Expected an error"),
+        }
+        assert!(req_query_to_map("psk=abc%2").is_err());
+        assert!(req_query_to_map("psk=%+1").is_err());
+        assert!(req_query_to_map("psk=%ff%fe").is_err());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_req_query_to_map_namespace_aliases() {
+        let map = req_query_to_map("ns=prod&namespace=prod").unwrap();
+        assert_eq!(get_namespace(&map), "prod");
+
+        let result = req_query_to_map("ns=prod&namespace=staging");
+        assert!(result.is_err());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_malformed_query_returns_bad_request() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/get/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/get/somekey?ns=%zz")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers().get("X-Little-Lookup-Error").unwrap(),
+            "validation_failed"
+        );
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_pool_exhausted_returns_service_unavailable() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let exhausted = Pool::builder()
+            .max_size(1)
+            .connection_timeout(std::time::Duration::from_millis(250))
+            .build(diesel::r2d2::ConnectionManager::new(
+                crate::util::get_database(),
+            ))
+            .expect("Failed to build pool");
+        let _only_connection = exhausted.get().expect("Failed to get connection from pool");
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(exhausted.clone()))
+                .route("/get/{id}", web::get().to(get_item))
+                .route("/history/{id}", web::get().to(history_item)),
+        )
+        .await;
+
+        for uri in ["/get/somekey", "/history/somekey"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+            let body = test::read_body(resp).await;
+            assert_eq!(body, "Database connection failed");
+        }
     }
 
     #[actix_rt::test]
     #[serial]
     #[serial]
     async fn test_check_psk_read() {
-        use crate::util::PSKType;
         use std::collections::HashMap;
+        let pool = establish_connection().expect("Failed to establish connection");
 
         std::env::set_var("LITTLE_LOOKUP_PSK_READ", "test_read_psk");
 
         let mut map = HashMap::new();
         map.insert(String::from("psk"), String::from("test_read_psk"));
 
-        let result = check_psk(&map, PSKType::READ);
-        assert_eq!(result, "");
+        let result = authorize(
+            &test::TestRequest::default().to_http_request(),
+            &map,
+            &pool,
+            Scope::Read,
+            "default",
+        );
+        assert!(result.is_ok());
 
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
     }
@@ -632,16 +1925,22 @@ mod tests {
     #[serial]
     #[serial]
     async fn test_check_psk_write() {
-        use crate::util::PSKType;
         use std::collections::HashMap;
+        let pool = establish_connection().expect("Failed to establish connection");
 
         std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "test_write_psk");
 
         let mut map = HashMap::new();
         map.insert(String::from("psk"), String::from("test_write_psk"));
 
-        let result = check_psk(&map, PSKType::WRITE);
-        assert_eq!(result, "");
+        let result = authorize(
+            &test::TestRequest::default().to_http_request(),
+            &map,
+            &pool,
+            Scope::Write,
+            "default",
+        );
+        assert_eq!(result.unwrap(), Some(String::from("psk-write")));
 
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
     }
@@ -650,14 +1949,20 @@ mod tests {
     #[serial]
     #[serial]
     async fn test_check_psk_missing() {
-        use crate::util::PSKType;
         use std::collections::HashMap;
+        let pool = establish_connection().expect("Failed to establish connection");
 
         std::env::set_var("LITTLE_LOOKUP_PSK_READ", "test_psk");
 
         let map = HashMap::new();
-        let result = check_psk(&map, PSKType::READ);
-        assert_eq!(result, "PSK required");
+        let result = authorize(
+            &test::TestRequest::default().to_http_request(),
+            &map,
+            &pool,
+            Scope::Read,
+            "default",
+        );
+        assert_eq!(result.unwrap_err().to_string(), "PSK required");
 
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
     }
@@ -666,16 +1971,22 @@ mod tests {
     #[serial]
     #[serial]
     async fn test_check_psk_incorrect() {
-        use crate::util::PSKType;
         use std::collections::HashMap;
+        let pool = establish_connection().expect("Failed to establish connection");
 
         std::env::set_var("LITTLE_LOOKUP_PSK_READ", "correct_psk");
 
         let mut map = HashMap::new();
         map.insert(String::from("psk"), String::from("wrong_psk"));
 
-        let result = check_psk(&map, PSKType::READ);
-        assert_eq!(result, "Incorrect PSK");
+        let result = authorize(
+            &test::TestRequest::default().to_http_request(),
+            &map,
+            &pool,
+            Scope::Read,
+            "default",
+        );
+        assert_eq!(result.unwrap_err().to_string(), "Incorrect PSK");
 
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
     }
@@ -684,14 +1995,94 @@ mod tests {
     #[serial]
     #[serial]
     async fn test_check_psk_not_required() {
-        use crate::util::PSKType;
         use std::collections::HashMap;
+        let pool = establish_connection().expect("Failed to establish connection");
 
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let map = HashMap::new();
-        let result = check_psk(&map, PSKType::READ);
-        assert_eq!(result, "");
+        let result = authorize(
+            &test::TestRequest::default().to_http_request(),
+            &map,
+            &pool,
+            Scope::Read,
+            "default",
+        );
+        assert!(result.is_ok());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_check_psk_from_headers() {
+        use std::collections::HashMap;
+        let pool = establish_connection().expect("Failed to establish connection");
+
+        std::env::set_var("LITTLE_LOOKUP_PSK_READ", "header_psk");
+        let map = HashMap::new();
+
+        let req = test::TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer header_psk"))
+            .to_http_request();
+        assert_eq!(
+            authorize(&req, &map, &pool, Scope::Read, "default").unwrap(),
+            Some(String::from("psk-read"))
+        );
+
+        let req = test::TestRequest::default()
+            .insert_header((PSK_HEADER, "header_psk"))
+            .to_http_request();
+        assert!(authorize(&req, &map, &pool, Scope::Read, "default").is_ok());
+
+        let req = test::TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "bearer wrong"))
+            .to_http_request();
+        assert_eq!(
+            authorize(&req, &map, &pool, Scope::Read, "default")
+                .unwrap_err()
+                .to_string(),
+            "Incorrect PSK"
+        );
+
+        let req = test::TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Basic aGVhZGVyX3Bzaw=="))
+            .to_http_request();
+        assert!(matches!(
+            authorize(&req, &map, &pool, Scope::Read, "default"),
+            Err(AppError::Unauthorized(_))
+        ));
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_query_psk_can_be_disabled() {
+        std::env::set_var("LITTLE_LOOKUP_PSK_READ", "query_psk");
+        std::env::set_var("LITTLE_LOOKUP_ALLOW_QUERY_PSK", "false");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/get/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/get/query_psk_key?psk=query_psk")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::get()
+            .uri("/get/query_psk_key")
+            .insert_header((header::AUTHORIZATION, "Bearer query_psk"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        std::env::remove_var("LITTLE_LOOKUP_ALLOW_QUERY_PSK");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
     }
 
     #[actix_rt::test]
@@ -702,7 +2093,7 @@ mod tests {
         std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "write_secret");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item)),
@@ -712,7 +2103,7 @@ mod tests {
         let req = test::TestRequest::get()
             .uri("/update/key/value?ns=psk_test_ns_1")
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
 
         let body = test::read_body(resp).await;
@@ -729,7 +2120,7 @@ mod tests {
         std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "correct_write_psk");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item)),
@@ -739,7 +2130,7 @@ mod tests {
         let req = test::TestRequest::get()
             .uri("/update/key/value?psk=wrong_psk&ns=psk_test_ns_2")
             .to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
 
         let body = test::read_body(resp).await;
@@ -755,7 +2146,7 @@ mod tests {
         std::env::set_var("LITTLE_LOOKUP_PSK_READ", "read_secret");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/get/{id}", web::get().to(get_item)),
@@ -763,7 +2154,7 @@ mod tests {
         .await;
 
         let req = test::TestRequest::get().uri("/get/somekey").to_request();
-        let resp = test::call_service(&mut app, req).await;
+        let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
 
         let body = test::read_body(resp).await;
@@ -779,7 +2170,7 @@ mod tests {
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item))
@@ -799,7 +2190,7 @@ mod tests {
                 ns
             ))
             .to_request();
-        let update_resp = test::call_service(&mut app, update_req).await;
+        let update_resp = test::call_service(&app, update_req).await;
         assert_eq!(update_resp.status(), http::StatusCode::OK);
 
         let get_req = test::TestRequest::get()
@@ -809,7 +2200,7 @@ mod tests {
                 ns
             ))
             .to_request();
-        let get_resp = test::call_service(&mut app, get_req).await;
+        let get_resp = test::call_service(&app, get_req).await;
         assert_eq!(get_resp.status(), http::StatusCode::OK);
 
         let body = test::read_body(get_resp).await;
@@ -823,7 +2214,7 @@ mod tests {
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item))
@@ -843,7 +2234,7 @@ mod tests {
                 ns
             ))
             .to_request();
-        let update_resp = test::call_service(&mut app, update_req).await;
+        let update_resp = test::call_service(&app, update_req).await;
         assert_eq!(update_resp.status(), http::StatusCode::OK);
 
         let get_req = test::TestRequest::get()
@@ -853,7 +2244,7 @@ mod tests {
                 ns
             ))
             .to_request();
-        let get_resp = test::call_service(&mut app, get_req).await;
+        let get_resp = test::call_service(&app, get_req).await;
         assert_eq!(get_resp.status(), http::StatusCode::OK);
 
         let body = test::read_body(get_resp).await;
@@ -867,7 +2258,7 @@ mod tests {
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
 
         let pool = establish_connection().expect("Failed to establish connection");
-        let mut app = test::init_service(
+        let app = test::init_service(
             App::new()
                 .app_data(Data::new(pool))
                 .route("/update/{id}/{val}", web::get().to(update_item))
@@ -883,13 +2274,13 @@ mod tests {
         let update1 = test::TestRequest::get()
             .uri(&format!("/update/{}/value1?ns={}", key, ns))
             .to_request();
-        let resp1 = test::call_service(&mut app, update1).await;
+        let resp1 = test::call_service(&app, update1).await;
         assert_eq!(resp1.status(), http::StatusCode::OK);
 
         let get1 = test::TestRequest::get()
             .uri(&format!("/get/{}?ns={}", key, ns))
             .to_request();
-        let resp_get1 = test::call_service(&mut app, get1).await;
+        let resp_get1 = test::call_service(&app, get1).await;
         assert_eq!(resp_get1.status(), http::StatusCode::OK);
         let body1 = test::read_body(resp_get1).await;
         assert_eq!(body1, "value1");
@@ -897,13 +2288,13 @@ mod tests {
         let update2 = test::TestRequest::get()
             .uri(&format!("/update/{}/value2?ns={}", key, ns))
             .to_request();
-        let resp2 = test::call_service(&mut app, update2).await;
+        let resp2 = test::call_service(&app, update2).await;
         assert_eq!(resp2.status(), http::StatusCode::OK);
 
         let get2 = test::TestRequest::get()
             .uri(&format!("/get/{}?ns={}", key, ns))
             .to_request();
-        let resp_get2 = test::call_service(&mut app, get2).await;
+        let resp_get2 = test::call_service(&app, get2).await;
         assert_eq!(resp_get2.status(), http::StatusCode::OK);
         let body2 = test::read_body(resp_get2).await;
         assert_eq!(body2, "value2");
@@ -911,7 +2302,7 @@ mod tests {
         let history = test::TestRequest::get()
             .uri(&format!("/history/{}?ns={}", key, ns))
             .to_request();
-        let resp_history = test::call_service(&mut app, history).await;
+        let resp_history = test::call_service(&app, history).await;
         assert_eq!(resp_history.status(), http::StatusCode::OK);
         let body_history = test::read_body(resp_history).await;
         let history_str = String::from_utf8(body_history.to_vec()).unwrap();
@@ -921,13 +2312,90 @@ mod tests {
         let delete = test::TestRequest::get()
             .uri(&format!("/delete/{}?ns={}", key, ns))
             .to_request();
-        let resp_delete = test::call_service(&mut app, delete).await;
+        let resp_delete = test::call_service(&app, delete).await;
         assert_eq!(resp_delete.status(), http::StatusCode::OK);
 
         let get_deleted = test::TestRequest::get()
             .uri(&format!("/get/{}?ns={}", key, ns))
             .to_request();
-        let resp_deleted = test::call_service(&mut app, get_deleted).await;
+        let resp_deleted = test::call_service(&app, get_deleted).await;
         assert_eq!(resp_deleted.status(), http::StatusCode::NOT_FOUND);
     }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_namespace_psks() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "global_write");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE_PSK_5FPROD_5FNS", "prod_write");
+        std::env::set_var("LITTLE_LOOKUP_PSK_READ_PSK_5FPROD_5FNS", "prod_read");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/get/{id}", web::get().to(get_item))
+                .route("/update/{id}/{val}", web::get().to(update_item)),
+        )
+        .await;
+
+        let request = |uri: &str, psk: Option<&str>| {
+            let mut req = test::TestRequest::get().uri(uri);
+            if let Some(psk) = psk {
+                req = req.insert_header((PSK_HEADER, psk));
+            }
+            req.to_request()
+        };
+
+        for (uri, psk, expected) in [
+            // The namespace PSK replaces the global one in its namespace
+            (
+                "/update/k/v?ns=psk_prod_ns",
+                Some("prod_write"),
+                http::StatusCode::OK,
+            ),
+            (
+                "/update/k/v?ns=psk_prod_ns",
+                Some("global_write"),
+                http::StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/update/k/v?ns=psk_prod_ns",
+                None,
+                http::StatusCode::UNAUTHORIZED,
+            ),
+            // Other namespaces fall back to the global PSK
+            (
+                "/update/k/v?ns=psk_dev_ns",
+                Some("global_write"),
+                http::StatusCode::OK,
+            ),
+            (
+                "/update/k/v?ns=psk_dev_ns",
+                Some("prod_write"),
+                http::StatusCode::UNAUTHORIZED,
+            ),
+            // Reads are open outside the namespace with a read PSK
+            ("/get/k?ns=psk_dev_ns", None, http::StatusCode::OK),
+            (
+                "/get/k?ns=psk_prod_ns",
+                None,
+                http::StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/get/k?ns=psk_prod_ns",
+                Some("prod_read"),
+                http::StatusCode::OK,
+            ),
+        ] {
+            let resp = test::call_service(&app, request(uri, psk)).await;
+            assert_eq!(resp.status(), expected, "{} {:?}", uri, psk);
+        }
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE_PSK_5FPROD_5FNS");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ_PSK_5FPROD_5FNS");
+        purge(&pool, "psk_prod_ns", "k");
+        purge(&pool, "psk_dev_ns", "k");
+    }
 }
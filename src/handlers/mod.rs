@@ -1 +1,2 @@
 pub mod items;
+pub mod v2;
@@ -0,0 +1,3402 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::auth::{authenticate, authorize, authorize_read, clear_token_cache};
+use crate::db_connection::Pool;
+use crate::diff::{diff_maps, diff_values, MapDiff, ValueDiff};
+use crate::error::AppError;
+use crate::handlers::items::{
+    etag, expiry, get_as_of, get_author, get_bool_param, get_expected_version, get_expires_at,
+    get_history_query, get_i64_param, get_increment, get_key_filter, get_list_limit,
+    get_list_query, get_lookup_chain, list_page, parse_timestamp, req_query_to_map,
+    sql_pool_handler, stream_items, write_item,
+};
+use crate::import::{parse_entries, ImportFormat};
+use crate::keys::{key_tree, TreeNode};
+use crate::models::item::{AuditQuery, BatchWrite, Item, ItemList, ListedItem, RollbackSummary};
+use crate::models::namespace::Namespace;
+use crate::models::token::{Scope, Token};
+use crate::util::get_namespace;
+
+const DEFAULT_HISTORY_LIMIT: i64 = 100;
+// Most keys a batch get or write may name
+const MAX_BATCH_SIZE: usize = 1000;
+
+// Request bodies
+
+#[derive(Deserialize)]
+pub struct BatchGetRequest {
+    pub keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Put {
+        key: String,
+        value: String,
+        expected_version: Option<i64>,
+        ttl: Option<i64>,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct BatchWriteRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    // Globs such as `team-a/*`; `*` covers every namespace
+    pub namespaces: Vec<String>,
+    pub ttl: Option<i64>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// Response bodies
+
+#[derive(Serialize)]
+pub struct ItemResponse {
+    pub key: String,
+    pub value: String,
+    pub namespace: String,
+    // Namespace the value came from; differs from `namespace` when it is inherited
+    pub layer: String,
+    pub updated_at: DateTime<Utc>,
+    pub version: i64,
+    pub versions: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct ItemListResponse {
+    pub namespace: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<DateTime<Utc>>,
+    pub items: Vec<ItemResponse>,
+    // Pass as `cursor` to continue; only set when `limit` cut the listing short
+    pub next_cursor: Option<String>,
+}
+
+// Leading fields of an `ItemListResponse` whose items are streamed
+#[derive(Serialize)]
+struct ItemListHead<'a> {
+    namespace: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    as_of: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct BatchGetResponse {
+    pub namespace: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<DateTime<Utc>>,
+    pub items: Vec<ItemResponse>,
+    // Requested keys without a value
+    pub missing: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BatchWriteResponse {
+    pub namespace: String,
+    // One entry per operation, in request order
+    pub results: Vec<DeleteResponse>,
+}
+
+#[derive(Serialize)]
+pub struct TreeResponse {
+    pub namespace: String,
+    pub prefix: String,
+    pub separator: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<DateTime<Utc>>,
+    pub children: Vec<TreeNode>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub version: i64,
+    pub value: String,
+    pub updated_at: DateTime<Utc>,
+    pub deleted: bool,
+    pub written_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub key: String,
+    pub namespace: String,
+    pub versions: i64,
+    pub history: Vec<HistoryEntry>,
+    // Pass as `before` to fetch the next page; None on the last page
+    pub next_before: Option<i64>,
+}
+
+// One stored version in the audit log; values are left out so the log can be shared
+// without exposing them
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub namespace: String,
+    pub key: String,
+    pub version: i64,
+    // `write` or `delete`
+    pub action: &'static str,
+    pub updated_at: DateTime<Utc>,
+    pub written_by: Option<String>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+    // Pass as `offset` to fetch the next page; None on the last page
+    pub next_offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteResponse {
+    pub key: String,
+    pub namespace: String,
+    pub version: i64,
+    pub deleted: bool,
+}
+
+#[derive(Serialize)]
+pub struct PurgeResponse {
+    pub key: String,
+    pub namespace: String,
+    pub purged: usize,
+}
+
+#[derive(Serialize)]
+pub struct RestoreResponse {
+    #[serde(flatten)]
+    pub item: ItemResponse,
+    pub restored_from: i64,
+}
+
+#[derive(Serialize)]
+pub struct RollbackResponse {
+    pub namespace: String,
+    pub as_of: DateTime<Utc>,
+    #[serde(flatten)]
+    pub summary: RollbackSummary,
+}
+
+#[derive(Serialize)]
+pub struct DiffSide {
+    pub version: i64,
+    pub updated_at: DateTime<Utc>,
+    pub deleted: bool,
+}
+
+#[derive(Serialize)]
+pub struct KeyDiffResponse {
+    pub key: String,
+    pub namespace: String,
+    pub from: DiffSide,
+    pub to: DiffSide,
+    #[serde(flatten)]
+    pub diff: ValueDiff,
+}
+
+#[derive(Serialize)]
+pub struct NamespaceDiffResponse {
+    pub namespace: String,
+    pub from: DateTime<Utc>,
+    // None when comparing against the current state
+    pub to: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub diff: MapDiff,
+}
+
+#[derive(Serialize)]
+pub struct NamespaceListResponse {
+    pub namespaces: Vec<Namespace>,
+}
+
+#[derive(Serialize)]
+pub struct RenameNamespaceResponse {
+    pub namespace: String,
+    pub renamed_from: String,
+    pub rows: usize,
+}
+
+#[derive(Serialize)]
+pub struct DeleteNamespaceResponse {
+    pub namespace: String,
+    pub deleted: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PromoteResponse {
+    pub namespace: String,
+    pub to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub diff: MapDiff,
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub namespace: String,
+    pub format: String,
+    // `merge` or `replace`
+    pub mode: String,
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub diff: MapDiff,
+}
+
+#[derive(Serialize)]
+pub struct ParentResponse {
+    pub namespace: String,
+    pub parent: Option<String>,
+    // Namespaces a lookup falls through, nearest first
+    pub chain: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PurgeNamespaceResponse {
+    pub namespace: String,
+    pub purged: usize,
+}
+
+#[derive(Serialize)]
+pub struct TokenListResponse {
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Serialize)]
+pub struct CreateTokenResponse {
+    #[serde(flatten)]
+    pub token: Token,
+    // The only time the secret is returned
+    pub secret: String,
+}
+
+impl DiffSide {
+    fn from_item(item: &Item) -> DiffSide {
+        DiffSide {
+            version: item.version,
+            updated_at: item.updated_at,
+            deleted: item.deleted,
+        }
+    }
+}
+
+impl ItemResponse {
+    fn from_item(item: Item, versions: i64) -> ItemResponse {
+        ItemResponse {
+            key: item.key,
+            value: item.val,
+            namespace: item.namespace.clone(),
+            layer: item.namespace,
+            updated_at: item.updated_at,
+            version: item.version,
+            versions,
+            expires_at: item.expires_at,
+        }
+    }
+}
+
+// Route handler functions
+
+pub async fn get_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let access = authorize_read(&req, &query_options_map, &pool, namespace)?;
+    let as_of = get_as_of(&query_options_map)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let chain = get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    )?;
+    let item = Item::find_layered(id.as_str(), &chain, as_of, &mut sql_pooled_connection)?;
+    let versions = Item::version_count(id.as_str(), &item.namespace, &mut sql_pooled_connection)?;
+
+    let mut response = HttpResponse::Ok();
+    // An inherited value has no version in this namespace to match against
+    if item.namespace == namespace {
+        response.insert_header((header::ETAG, etag(item.version)));
+    }
+    let mut item_response = ItemResponse::from_item(item, versions);
+    item_response.namespace = String::from(namespace);
+
+    Ok(response.json(item_response))
+}
+
+pub async fn list_items(req: HttpRequest, pool: web::Data<Pool>) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let access = authorize_read(&req, &query_options_map, &pool, namespace)?;
+    let list_query = get_list_query(&query_options_map)?;
+    let limit = get_list_limit(&query_options_map)?;
+    let key_filter = get_key_filter(&query_options_map)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool.clone())?;
+
+    let chain = get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    )?;
+
+    let (as_of, sort) = (list_query.as_of, list_query.sort);
+    drop(sql_pooled_connection);
+    let listed = ItemList::iter(pool.get_ref().clone(), &chain, key_filter, list_query);
+
+    let namespace = String::from(namespace);
+    let item_response = {
+        let namespace = namespace.clone();
+        move |listed: ListedItem| {
+            let mut item_response = ItemResponse::from_item(listed.item, listed.versions);
+            item_response.namespace = namespace.clone();
+            item_response
+        }
+    };
+
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            // `{"namespace":...,"as_of":...}` opened up to take the streamed items
+            let head = serde_json::to_string(&ItemListHead {
+                namespace: &namespace,
+                as_of,
+            })
+            .unwrap_or_default();
+            let head = format!("{},\"items\":[", head.trim_end_matches('}'));
+
+            return stream_items(
+                listed,
+                head,
+                "],\"next_cursor\":null}",
+                None,
+                move |listed, first| {
+                    let item = serde_json::to_string(&item_response(listed)).unwrap_or_default();
+                    match first {
+                        true => item,
+                        false => format!(",{}", item),
+                    }
+                },
+            )
+            .map(|mut response| {
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static("application/json"),
+                );
+                response
+            });
+        }
+    };
+
+    let (page, next_cursor) = list_page(listed, sort, limit)?;
+
+    Ok(HttpResponse::Ok().json(ItemListResponse {
+        namespace,
+        as_of,
+        items: page.into_iter().map(item_response).collect(),
+        next_cursor,
+    }))
+}
+
+pub async fn batch_get(
+    req: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let access = authorize_read(&req, &query_options_map, &pool, namespace)?;
+    let as_of = get_as_of(&query_options_map)?;
+
+    let request: BatchGetRequest = parse_body(&body)?;
+    check_batch_size(request.keys.len())?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let chain = get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    )?;
+    let found = ItemList::find_many(&mut sql_pooled_connection, &chain, &request.keys, as_of)?;
+
+    let found_keys: HashSet<&str> = found
+        .iter()
+        .map(|listed| listed.item.key.as_str())
+        .collect();
+    let mut missing: Vec<String> = Vec::new();
+    for key in request.keys.iter() {
+        if !found_keys.contains(key.as_str()) && !missing.contains(key) {
+            missing.push(key.clone());
+        }
+    }
+
+    let items = found
+        .into_iter()
+        .map(|listed| {
+            let mut item_response = ItemResponse::from_item(listed.item, listed.versions);
+            item_response.namespace = String::from(namespace);
+            item_response
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(BatchGetResponse {
+        namespace: String::from(namespace),
+        as_of,
+        items,
+        missing,
+    }))
+}
+
+pub async fn batch_write(
+    req: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let written_by = authorize(&req, &query_options_map, &pool, Scope::Write, namespace)?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let request: BatchWriteRequest = parse_body(&body)?;
+    check_batch_size(request.operations.len())?;
+    if request
+        .operations
+        .iter()
+        .any(|operation| matches!(operation, BatchOperation::Delete { .. }))
+    {
+        authorize(&req, &query_options_map, &pool, Scope::Delete, namespace)?;
+    }
+
+    let writes = request
+        .operations
+        .iter()
+        .map(|operation| match operation {
+            BatchOperation::Put {
+                key,
+                value,
+                expected_version,
+                ttl,
+                expires_at,
+            } => Ok(BatchWrite::Put {
+                key,
+                value,
+                expected_version: *expected_version,
+                expires_at: expiry(*ttl, *expires_at)?,
+            }),
+            BatchOperation::Delete { key } => Ok(BatchWrite::Delete { key }),
+        })
+        .collect::<Result<Vec<BatchWrite>, AppError>>()?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let written = ItemList::write_batch(&mut sql_pooled_connection, namespace, &writes, &author)?;
+
+    Ok(HttpResponse::Ok().json(BatchWriteResponse {
+        namespace: String::from(namespace),
+        results: written
+            .into_iter()
+            .map(|item| DeleteResponse {
+                key: item.key,
+                namespace: item.namespace,
+                version: item.version,
+                deleted: item.deleted,
+            })
+            .collect(),
+    }))
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, AppError> {
+    serde_json::from_slice(body)
+        .map_err(|e| AppError::Validation(format!("Invalid request body: {}", e)))
+}
+
+fn check_batch_size(size: usize) -> Result<(), AppError> {
+    match size {
+        0 => Err(AppError::Validation(String::from("Batch is empty"))),
+        size if size > MAX_BATCH_SIZE => Err(AppError::Validation(format!(
+            "Batch of {} exceeds the limit of {}",
+            size, MAX_BATCH_SIZE
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub async fn key_tree_view(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let access = authorize_read(&req, &query_options_map, &pool, namespace)?;
+    let as_of = get_as_of(&query_options_map)?;
+    let key_filter = get_key_filter(&query_options_map)?;
+
+    let separator = match query_options_map.get("sep") {
+        Some(separator) if separator.is_empty() => {
+            return Err(AppError::Validation(String::from("sep must not be empty")))
+        }
+        Some(separator) => separator.clone(),
+        None => String::from("."),
+    };
+    let prefix = key_filter.prefix.clone().unwrap_or_default();
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let chain = get_lookup_chain(
+        &query_options_map,
+        namespace,
+        &access,
+        &mut sql_pooled_connection,
+    )?;
+    let results = ItemList::list_layered(&mut sql_pooled_connection, &chain, as_of, &key_filter)?;
+
+    let children = key_tree(
+        results.iter().map(|item| item.key.as_str()),
+        &prefix,
+        &separator,
+    );
+
+    Ok(HttpResponse::Ok().json(TreeResponse {
+        namespace: String::from(namespace),
+        prefix,
+        separator,
+        as_of,
+        children,
+    }))
+}
+
+pub async fn history_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    authorize(&req, &query_options_map, &pool, Scope::Read, namespace)?;
+    let mut history_query = get_history_query(&query_options_map, Some(DEFAULT_HISTORY_LIMIT))?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let versions = Item::version_count(id.as_str(), namespace, &mut sql_pooled_connection)?;
+    if versions == 0 {
+        return Err(AppError::NotFound(String::from("Undefined")));
+    }
+
+    // Fetch one extra row to learn whether another page follows
+    let limit = history_query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    history_query.limit = Some(limit + 1);
+    let mut item_list = Item::history_page(
+        id.as_str(),
+        namespace,
+        &history_query,
+        &mut sql_pooled_connection,
+    )?;
+    let has_more = item_list.len() as i64 > limit;
+    item_list.truncate(limit as usize);
+
+    let next_before = if has_more {
+        item_list.last().map(|item| item.version)
+    } else {
+        None
+    };
+    let history: Vec<HistoryEntry> = item_list
+        .into_iter()
+        .map(|item| HistoryEntry {
+            version: item.version,
+            value: item.val,
+            updated_at: item.updated_at,
+            deleted: item.deleted,
+            written_by: item.written_by,
+            expires_at: item.expires_at,
+            comment: item.comment,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(HistoryResponse {
+        key: id.into_inner(),
+        namespace: String::from(namespace),
+        versions,
+        history,
+        next_before,
+    }))
+}
+
+pub async fn diff_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    authorize(&req, &query_options_map, &pool, Scope::Read, namespace)?;
+
+    let from_version = match get_i64_param(&query_options_map, "from")? {
+        Some(from_version) => from_version,
+        None => return Err(AppError::Validation(String::from("from is required"))),
+    };
+    let to_version = get_i64_param(&query_options_map, "to")?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    // The newest version is the default target
+    let to_version = match to_version {
+        Some(to_version) => to_version,
+        None => match Item::latest(id.as_str(), namespace, &mut sql_pooled_connection)? {
+            Some(latest) => latest.version,
+            None => return Err(AppError::NotFound(String::from("Undefined"))),
+        },
+    };
+    let mut find = |version: i64| {
+        Item::find_version(id.as_str(), namespace, version, &mut sql_pooled_connection).map_err(
+            |e| match e {
+                diesel::result::Error::NotFound => {
+                    AppError::NotFound(format!("Version {} of '{}' not found", version, id))
+                }
+                e => AppError::from(e),
+            },
+        )
+    };
+    let from = find(from_version)?;
+    let to = find(to_version)?;
+
+    Ok(HttpResponse::Ok().json(KeyDiffResponse {
+        key: id.to_string(),
+        namespace: String::from(namespace),
+        from: DiffSide::from_item(&from),
+        to: DiffSide::from_item(&to),
+        diff: diff_values(
+            &from.val,
+            &to.val,
+            &format!("{}@{}", id, from.version),
+            &format!("{}@{}", id, to.version),
+        ),
+    }))
+}
+
+pub async fn diff_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Read,
+        namespace.as_str(),
+    )?;
+
+    let from = match query_options_map.get("from") {
+        Some(from) => parse_timestamp("from", from)?,
+        None => return Err(AppError::Validation(String::from("from is required"))),
+    };
+    let to = match query_options_map.get("to") {
+        Some(to) => Some(parse_timestamp("to", to)?),
+        None => None,
+    };
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let from_items = ItemList::list_as_of(&mut sql_pooled_connection, namespace.as_str(), from)?;
+    let to_items = match to {
+        Some(to) => ItemList::list_as_of(&mut sql_pooled_connection, namespace.as_str(), to)?,
+        None => ItemList::list(&mut sql_pooled_connection, namespace.as_str())?,
+    };
+
+    let diff = diff_maps(
+        &from_items
+            .into_iter()
+            .map(|item| (item.key, item.val))
+            .collect(),
+        &to_items
+            .into_iter()
+            .map(|item| (item.key, item.val))
+            .collect(),
+    );
+
+    Ok(HttpResponse::Ok().json(NamespaceDiffResponse {
+        namespace: namespace.into_inner(),
+        from,
+        to,
+        diff,
+    }))
+}
+
+pub async fn put_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let written_by = authorize(&req, &query_options_map, &pool, Scope::Write, namespace)?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let value = std::str::from_utf8(&body)
+        .map_err(|_| AppError::Validation(String::from("Value must be valid UTF-8")))?;
+
+    let expected_version = get_expected_version(&req, &query_options_map)?;
+    let expires_at = get_expires_at(&query_options_map)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let item = write_item(
+        id.as_str(),
+        value,
+        namespace,
+        expected_version,
+        expires_at,
+        &author,
+        &mut sql_pooled_connection,
+    )?;
+    let versions = Item::version_count(id.as_str(), namespace, &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(item.version)))
+        .json(ItemResponse::from_item(item, versions)))
+}
+
+pub async fn increment_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let written_by = authorize(&req, &query_options_map, &pool, Scope::Write, namespace)?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let (step, initial) = get_increment(&query_options_map)?;
+    let expires_at = get_expires_at(&query_options_map)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let item = Item::increment(
+        id.as_str(),
+        namespace,
+        step,
+        initial,
+        expires_at,
+        &author,
+        &mut sql_pooled_connection,
+    )?;
+    let versions = Item::version_count(id.as_str(), namespace, &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(item.version)))
+        .json(ItemResponse::from_item(item, versions)))
+}
+
+pub async fn restore_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let written_by = authorize(&req, &query_options_map, &pool, Scope::Write, namespace)?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let version = match get_i64_param(&query_options_map, "version")? {
+        Some(version) => version,
+        None => return Err(AppError::Validation(String::from("version is required"))),
+    };
+    let expected_version = get_expected_version(&req, &query_options_map)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let historical =
+        match Item::find_version(id.as_str(), namespace, version, &mut sql_pooled_connection) {
+            Ok(historical) => historical,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(AppError::NotFound(format!(
+                    "Version {} of '{}' not found",
+                    version, id
+                )))
+            }
+            Err(e) => return Err(AppError::from(e)),
+        };
+    if historical.deleted {
+        return Err(AppError::Validation(format!(
+            "Version {} of '{}' is a deletion; use undelete instead",
+            version, id
+        )));
+    }
+
+    let item = write_item(
+        id.as_str(),
+        &historical.val,
+        namespace,
+        expected_version,
+        None,
+        &author,
+        &mut sql_pooled_connection,
+    )?;
+    let versions = Item::version_count(id.as_str(), namespace, &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(item.version)))
+        .json(RestoreResponse {
+            item: ItemResponse::from_item(item, versions),
+            restored_from: version,
+        }))
+}
+
+pub async fn rollback_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let written_by = authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Write,
+        namespace.as_str(),
+    )?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let as_of = match get_as_of(&query_options_map)? {
+        Some(as_of) => as_of,
+        None => return Err(AppError::Validation(String::from("as_of is required"))),
+    };
+
+    let keep_new = get_bool_param(&query_options_map, "keep_new", false)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let summary = ItemList::rollback(
+        &mut sql_pooled_connection,
+        namespace.as_str(),
+        as_of,
+        keep_new,
+        &author,
+    )?;
+
+    Ok(HttpResponse::Ok().json(RollbackResponse {
+        namespace: namespace.into_inner(),
+        as_of,
+        summary,
+    }))
+}
+
+pub async fn delete_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let written_by = authorize(&req, &query_options_map, &pool, Scope::Delete, namespace)?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let tombstone =
+        match Item::destroy(id.as_str(), namespace, &author, &mut sql_pooled_connection)? {
+            Some(tombstone) => tombstone,
+            None => return Err(AppError::NotFound(String::from("Undefined"))),
+        };
+
+    Ok(HttpResponse::Ok().json(DeleteResponse {
+        key: id.into_inner(),
+        namespace: String::from(namespace),
+        version: tombstone.version,
+        deleted: true,
+    }))
+}
+
+pub async fn undelete_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    let written_by = authorize(&req, &query_options_map, &pool, Scope::Write, namespace)?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let item = Item::undelete(id.as_str(), namespace, &author, &mut sql_pooled_connection)?;
+    let versions = Item::version_count(id.as_str(), namespace, &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(item.version)))
+        .json(ItemResponse::from_item(item, versions)))
+}
+
+pub async fn purge_item(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace: &str = get_namespace(&query_options_map);
+    authorize(&req, &query_options_map, &pool, Scope::Admin, namespace)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let purged = Item::purge(id.as_str(), namespace, &mut sql_pooled_connection)?;
+    if purged == 0 {
+        return Err(AppError::NotFound(String::from("Undefined")));
+    }
+
+    Ok(HttpResponse::Ok().json(PurgeResponse {
+        key: id.into_inner(),
+        namespace: String::from(namespace),
+        purged,
+    }))
+}
+
+pub async fn list_namespaces(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let access = authenticate(&req, &query_options_map, &pool, Scope::Read)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let namespaces = Namespace::list(&mut sql_pooled_connection)?
+        .into_iter()
+        .filter(|namespace| access.allows(&namespace.namespace))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(NamespaceListResponse { namespaces }))
+}
+
+pub async fn namespace_stats(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Read,
+        namespace.as_str(),
+    )?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let namespace = Namespace::find(namespace.as_str(), &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok().json(namespace))
+}
+
+pub async fn rename_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Delete,
+        namespace.as_str(),
+    )?;
+
+    let new_namespace = match query_options_map.get("to") {
+        Some(new_namespace) if !new_namespace.is_empty() => new_namespace,
+        _ => return Err(AppError::Validation(String::from("to is required"))),
+    };
+    authorize(&req, &query_options_map, &pool, Scope::Write, new_namespace)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let rows = Namespace::rename(
+        namespace.as_str(),
+        new_namespace,
+        &mut sql_pooled_connection,
+    )?;
+
+    Ok(HttpResponse::Ok().json(RenameNamespaceResponse {
+        namespace: new_namespace.clone(),
+        renamed_from: namespace.into_inner(),
+        rows,
+    }))
+}
+
+pub async fn promote_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Read,
+        namespace.as_str(),
+    )?;
+
+    let target_namespace = match query_options_map.get("to") {
+        Some(target_namespace) if !target_namespace.is_empty() => target_namespace,
+        _ => return Err(AppError::Validation(String::from("to is required"))),
+    };
+    let written_by = authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Write,
+        target_namespace,
+    )?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+    let prefix = query_options_map.get("prefix");
+    let dry_run = get_bool_param(&query_options_map, "dry_run", false)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let diff = Namespace::promote(
+        namespace.as_str(),
+        target_namespace,
+        prefix.map(String::as_str),
+        dry_run,
+        &author,
+        &mut sql_pooled_connection,
+    )?;
+
+    Ok(HttpResponse::Ok().json(PromoteResponse {
+        namespace: namespace.into_inner(),
+        to: target_namespace.clone(),
+        prefix: prefix.cloned(),
+        dry_run,
+        diff,
+    }))
+}
+
+pub async fn import_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let written_by = authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Write,
+        namespace.as_str(),
+    )?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let format = match query_options_map.get("format") {
+        Some(format) => ImportFormat::parse(format).ok_or_else(|| {
+            AppError::Validation(format!(
+                "Invalid format '{}', expected dotenv, json or yaml",
+                format
+            ))
+        })?,
+        None => return Err(AppError::Validation(String::from("format is required"))),
+    };
+    let replace = match query_options_map.get("mode").map(String::as_str) {
+        None | Some("merge") => false,
+        Some("replace") => true,
+        Some(mode) => {
+            return Err(AppError::Validation(format!(
+                "Invalid mode '{}', expected merge or replace",
+                mode
+            )))
+        }
+    };
+    if replace {
+        authorize(
+            &req,
+            &query_options_map,
+            &pool,
+            Scope::Delete,
+            namespace.as_str(),
+        )?;
+    }
+    let dry_run = get_bool_param(&query_options_map, "dry_run", false)?;
+
+    let input = std::str::from_utf8(&body)
+        .map_err(|_| AppError::Validation(String::from("Import must be valid UTF-8")))?;
+    let entries = parse_entries(input, format)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let diff = Namespace::import(
+        namespace.as_str(),
+        &entries,
+        replace,
+        dry_run,
+        &author,
+        &mut sql_pooled_connection,
+    )?;
+
+    Ok(HttpResponse::Ok().json(ImportResponse {
+        namespace: namespace.into_inner(),
+        format: String::from(format.name()),
+        mode: String::from(match replace {
+            true => "replace",
+            false => "merge",
+        }),
+        dry_run,
+        diff,
+    }))
+}
+
+pub async fn delete_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let written_by = authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Delete,
+        namespace.as_str(),
+    )?;
+    let author = get_author(&req, &query_options_map, written_by)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let deleted = Namespace::destroy(namespace.as_str(), &author, &mut sql_pooled_connection)?;
+    if deleted.is_empty() {
+        return Err(AppError::NotFound(String::from("Undefined")));
+    }
+
+    Ok(HttpResponse::Ok().json(DeleteNamespaceResponse {
+        namespace: namespace.into_inner(),
+        deleted,
+    }))
+}
+
+pub async fn purge_namespace(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Admin,
+        namespace.as_str(),
+    )?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let purged = Namespace::purge(namespace.as_str(), &mut sql_pooled_connection)?;
+    if purged == 0 {
+        return Err(AppError::NotFound(String::from("Undefined")));
+    }
+
+    Ok(HttpResponse::Ok().json(PurgeNamespaceResponse {
+        namespace: namespace.into_inner(),
+        purged,
+    }))
+}
+
+pub async fn get_parent(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Read,
+        namespace.as_str(),
+    )?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let parent = Namespace::parent(namespace.as_str(), &mut sql_pooled_connection)?;
+    let chain = Namespace::chain(namespace.as_str(), &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok().json(ParentResponse {
+        namespace: namespace.into_inner(),
+        parent,
+        chain,
+    }))
+}
+
+pub async fn set_parent(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Write,
+        namespace.as_str(),
+    )?;
+
+    let parent = match query_options_map.get("parent") {
+        Some(parent) => parent,
+        None => return Err(AppError::Validation(String::from("parent is required"))),
+    };
+    authorize(&req, &query_options_map, &pool, Scope::Read, parent)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let chain = Namespace::set_parent(namespace.as_str(), parent, &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok().json(ParentResponse {
+        namespace: namespace.into_inner(),
+        parent: Some(parent.clone()),
+        chain,
+    }))
+}
+
+pub async fn remove_parent(
+    namespace: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    authorize(
+        &req,
+        &query_options_map,
+        &pool,
+        Scope::Write,
+        namespace.as_str(),
+    )?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    if !Namespace::remove_parent(namespace.as_str(), &mut sql_pooled_connection)? {
+        return Err(AppError::NotFound(format!(
+            "Namespace '{}' has no parent",
+            namespace
+        )));
+    }
+
+    Ok(HttpResponse::Ok().json(ParentResponse {
+        chain: vec![namespace.to_string()],
+        namespace: namespace.into_inner(),
+        parent: None,
+    }))
+}
+
+// Who changed what and from where. Without `ns` the log spans every namespace, which needs
+// an admin credential that covers them all.
+pub async fn audit_log(req: HttpRequest, pool: web::Data<Pool>) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    let namespace = query_options_map
+        .get("ns")
+        .or_else(|| query_options_map.get("namespace"))
+        .filter(|namespace| !namespace.is_empty());
+    match namespace {
+        Some(namespace) => authorize(&req, &query_options_map, &pool, Scope::Admin, namespace)?,
+        None => authenticate(&req, &query_options_map, &pool, Scope::Admin)?.check(None)?,
+    };
+
+    let history_query = get_history_query(&query_options_map, Some(DEFAULT_HISTORY_LIMIT))?;
+    if history_query.before_version.is_some() {
+        return Err(AppError::Validation(String::from(
+            "before is not supported by the audit log, use offset",
+        )));
+    }
+    let limit = history_query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let offset = history_query.offset.unwrap_or(0);
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    // Fetch one extra row to learn whether another page follows
+    let mut records = Item::audit(
+        &AuditQuery {
+            namespace: namespace.cloned(),
+            key: query_options_map.get("key").cloned(),
+            written_by: query_options_map.get("actor").cloned(),
+            since: history_query.since,
+            until: history_query.until,
+            limit: Some(limit + 1),
+            offset: Some(offset),
+        },
+        &mut sql_pooled_connection,
+    )?;
+    let has_more = records.len() as i64 > limit;
+    records.truncate(limit as usize);
+
+    let next_offset = if has_more { Some(offset + limit) } else { None };
+    let entries: Vec<AuditEntry> = records
+        .into_iter()
+        .map(|record| AuditEntry {
+            namespace: record.namespace,
+            key: record.key,
+            version: record.version,
+            action: if record.deleted { "delete" } else { "write" },
+            updated_at: record.updated_at,
+            written_by: record.written_by,
+            source_ip: record.source_ip,
+            user_agent: record.user_agent,
+            comment: record.comment,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(AuditResponse {
+        entries,
+        next_offset,
+    }))
+}
+
+// Token management needs an admin credential that covers every namespace
+fn check_token_admin(
+    req: &HttpRequest,
+    query_options_map: &HashMap<String, String>,
+    pool: &Pool,
+) -> Result<(), AppError> {
+    authenticate(req, query_options_map, pool, Scope::Admin)?.check(None)?;
+    Ok(())
+}
+
+// Parsed here rather than by `web::Path<i64>` so a bad id gets the usual JSON error
+fn token_id(id: &str) -> Result<i64, AppError> {
+    id.parse()
+        .map_err(|_| AppError::Validation(format!("Invalid token id '{}'", id)))
+}
+
+pub async fn list_tokens(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    check_token_admin(&req, &query_options_map, &pool)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let tokens = Token::list(&mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok().json(TokenListResponse { tokens }))
+}
+
+pub async fn create_token(
+    req: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    check_token_admin(&req, &query_options_map, &pool)?;
+
+    let request: CreateTokenRequest = parse_body(&body)?;
+    let expires_at = expiry(request.ttl, request.expires_at)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let (token, secret) = Token::create(
+        &request.name,
+        &request.scopes,
+        &request.namespaces,
+        expires_at,
+        &mut sql_pooled_connection,
+    )?;
+    clear_token_cache();
+
+    Ok(HttpResponse::Created().json(CreateTokenResponse { token, secret }))
+}
+
+pub async fn get_token(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    check_token_admin(&req, &query_options_map, &pool)?;
+    let id = token_id(&id)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let token = Token::find(id, &mut sql_pooled_connection)?;
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
+pub async fn expire_token(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    check_token_admin(&req, &query_options_map, &pool)?;
+    let id = token_id(&id)?;
+
+    // Expires now unless `ttl` or `expires_at` name a later time
+    let expires_at = get_expires_at(&query_options_map)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let token = Token::expire(id, expires_at, &mut sql_pooled_connection)?;
+    clear_token_cache();
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
+pub async fn revoke_token(
+    id: web::Path<String>,
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, AppError> {
+    let query_options_map = req_query_to_map(req.query_string())?;
+    check_token_admin(&req, &query_options_map, &pool)?;
+    let id = token_id(&id)?;
+
+    let mut sql_pooled_connection = sql_pool_handler(pool)?;
+
+    let token = Token::revoke(id, &mut sql_pooled_connection)?;
+    clear_token_cache();
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http, test,
+        web::{self, Data},
+        App,
+    };
+    use serial_test::serial;
+
+    use crate::db_connection::establish_connection;
+    use crate::handlers::items::update_item;
+    use crate::models::item::Author;
+    use crate::util::get_max_value_size;
+
+    use super::*;
+
+    fn purge(pool: &Pool, ns: &str, key: &str) {
+        let mut connection = pool.get().expect("Failed to get connection");
+        Item::purge(key, ns, &mut connection).expect("Failed to purge");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_get_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item)),
+        )
+        .await;
+
+        let ns = "v2_get_ns";
+        let key = "v2_get_key";
+
+        purge(&pool, ns, key);
+
+        for val in ["first", "second"] {
+            let update_req = test::TestRequest::get()
+                .uri(&format!("/update/{}/{}?ns={}", key, val, ns))
+                .to_request();
+            let _ = test::call_service(&app, update_req).await;
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["key"], key);
+        assert_eq!(body["value"], "second");
+        assert_eq!(body["namespace"], ns);
+        assert_eq!(body["versions"], 2);
+        assert!(body["updated_at"].is_string());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_get_item_not_found() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/v2/items/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/v2/items/nonexistent_key_xyz?ns=nonexistent_ns")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "not_found");
+        assert_eq!(body["message"], "Undefined");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_list_and_history() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/update/{id}/{val}", web::get().to(update_item))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}/history", web::get().to(history_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item)),
+        )
+        .await;
+
+        let ns = "v2_list_ns";
+
+        for key in ["key1", "key2"] {
+            purge(&pool, ns, key);
+        }
+
+        for (key, val) in [("key1", "a"), ("key1", "b"), ("key2", "c")] {
+            let update_req = test::TestRequest::get()
+                .uri(&format!("/update/{}/{}?ns={}", key, val, ns))
+                .to_request();
+            let _ = test::call_service(&app, update_req).await;
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["namespace"], ns);
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["key"], "key1");
+        assert_eq!(items[0]["value"], "b");
+        assert_eq!(items[0]["versions"], 2);
+        assert_eq!(items[1]["key"], "key2");
+        assert_eq!(items[1]["versions"], 1);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/key1/history?ns={}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["versions"], 2);
+        let history = body["history"].as_array().unwrap();
+        assert_eq!(history[0]["value"], "b");
+        assert_eq!(history[1]["value"], "a");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_history_paging() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "write_secret");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}/history", web::get().to(history_item)),
+        )
+        .await;
+
+        let ns = "v2_history_paging_ns";
+        let key = "v2_history_paging_key";
+
+        purge(&pool, ns, key);
+
+        for value in ["v1", "v2", "v3", "v4", "v5"] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}&psk=write_secret", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/history?ns={}&limit=2", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["versions"], 5);
+        assert_eq!(body["next_before"], 4);
+        let history = body["history"].as_array().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["version"], 5);
+        assert_eq!(history[0]["value"], "v5");
+        assert_eq!(history[0]["written_by"], "psk-write");
+        assert!(history[0]["updated_at"].is_string());
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/v2/items/{}/history?ns={}&limit=2&before=2",
+                key, ns
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let history = body["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["version"], 1);
+        assert!(body["next_before"].is_null());
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/v2/items/{}/history?ns={}&offset=1&limit=1",
+                key, ns
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["history"][0]["version"], 4);
+
+        for query in ["limit=0", "limit=abc", "offset=-1", "since=yesterday"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/v2/items/{}/history?ns={}&{}", key, ns, query))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+        }
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_diff_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}/diff", web::get().to(diff_item)),
+        )
+        .await;
+
+        let ns = "v2_diff_ns";
+        let key = "v2_diff_key";
+
+        purge(&pool, ns, key);
+
+        for value in ["host=a\nport=1\n", "host=b\nport=1\n"] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/diff?ns={}&from=1", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["from"]["version"], 1);
+        assert_eq!(body["to"]["version"], 2);
+        assert_eq!(body["changed"], true);
+        assert_eq!(body["lines"][0]["op"], "delete");
+        assert_eq!(body["lines"][0]["line"], "host=a");
+        assert_eq!(body["lines"][1]["op"], "insert");
+        assert_eq!(body["lines"][2]["op"], "equal");
+        assert!(body["unified"].as_str().unwrap().contains("+host=b"));
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/diff?ns={}&from=1&to=7", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/diff?ns={}", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_diff_namespace() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item))
+                .route(
+                    "/v2/namespaces/{namespace}/diff",
+                    web::get().to(diff_namespace),
+                ),
+        )
+        .await;
+
+        let ns = "v2_diff_namespace_ns";
+
+        for key in ["kept", "changed", "removed", "added"] {
+            purge(&pool, ns, key);
+        }
+
+        for (key, value) in [("kept", "1"), ("changed", "old"), ("removed", "x")] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let from = Utc::now().to_rfc3339();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        for (key, value) in [("changed", "new"), ("added", "y")] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+        let delete_req = test::TestRequest::delete()
+            .uri(&format!("/v2/items/removed?ns={}", ns))
+            .to_request();
+        let _ = test::call_service(&app, delete_req).await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/v2/namespaces/{}/diff?from={}",
+                ns,
+                urlencoding::encode(&from)
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["to"].is_null());
+        assert_eq!(
+            body["added"],
+            serde_json::json!([{"key": "added", "to": "y"}])
+        );
+        assert_eq!(
+            body["removed"],
+            serde_json::json!([{"key": "removed", "from": "x"}])
+        );
+        assert_eq!(
+            body["changed"],
+            serde_json::json!([{"key": "changed", "from": "old", "to": "new"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/namespaces/{}/diff", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_put_item_from_body() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::post().to(put_item)),
+        )
+        .await;
+
+        let ns = "v2_put_ns";
+        let key = "v2_put_key";
+        let value =
+            "-----BEGIN CERTIFICATE-----\nMIIB/path/with+slashes==\n-----END CERTIFICATE-----\n";
+
+        let put_req = test::TestRequest::put()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .set_payload(value)
+            .to_request();
+        let put_resp = test::call_service(&app, put_req).await;
+        assert_eq!(put_resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(put_resp).await;
+        assert_eq!(body["key"], key);
+        assert_eq!(body["value"], value);
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let get_resp = test::call_service(&app, get_req).await;
+        let body: serde_json::Value = test::read_body_json(get_resp).await;
+        assert_eq!(body["value"], value);
+
+        let post_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .set_payload("posted")
+            .to_request();
+        let post_resp = test::call_service(&app, post_req).await;
+        assert_eq!(post_resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(post_resp).await;
+        assert_eq!(body["value"], "posted");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_put_item_compare_and_swap() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item)),
+        )
+        .await;
+
+        let ns = "v2_cas_ns";
+        let key = "v2_cas_key";
+
+        purge(&pool, ns, key);
+
+        let put_req = test::TestRequest::put()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .set_payload("first")
+            .to_request();
+        let put_resp = test::call_service(&app, put_req).await;
+        assert_eq!(put_resp.headers().get(header::ETAG).unwrap(), "\"1\"");
+        let body: serde_json::Value = test::read_body_json(put_resp).await;
+        assert_eq!(body["version"], 1);
+
+        // Two writers racing from the same read: the second one loses
+        for (value, status) in [
+            ("second", http::StatusCode::OK),
+            ("third", http::StatusCode::PRECONDITION_FAILED),
+        ] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .set_payload(value)
+                .to_request();
+            let put_resp = test::call_service(&app, put_req).await;
+            assert_eq!(put_resp.status(), status);
+        }
+
+        let put_req = test::TestRequest::put()
+            .uri(&format!("/v2/items/{}?ns={}&expected_version=1", key, ns))
+            .set_payload("third")
+            .to_request();
+        let put_resp = test::call_service(&app, put_req).await;
+        assert_eq!(put_resp.status(), http::StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(put_resp).await;
+        assert_eq!(body["error"], "conflict");
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let get_resp = test::call_service(&app, get_req).await;
+        assert_eq!(get_resp.headers().get(header::ETAG).unwrap(), "\"2\"");
+        let body: serde_json::Value = test::read_body_json(get_resp).await;
+        assert_eq!(body["value"], "second");
+        assert_eq!(body["version"], 2);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_restore_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item))
+                .route("/v2/items/{id}/restore", web::post().to(restore_item)),
+        )
+        .await;
+
+        let ns = "v2_restore_ns";
+        let key = "v2_restore_key";
+
+        purge(&pool, ns, key);
+
+        for value in ["good", "bad"] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let restore_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/restore?ns={}&version=1", key, ns))
+            .to_request();
+        let restore_resp = test::call_service(&app, restore_req).await;
+        assert_eq!(restore_resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(restore_resp).await;
+        assert_eq!(body["value"], "good");
+        assert_eq!(body["version"], 3);
+        assert_eq!(body["versions"], 3);
+        assert_eq!(body["restored_from"], 1);
+
+        let restore_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/restore?ns={}&version=9", key, ns))
+            .to_request();
+        let restore_resp = test::call_service(&app, restore_req).await;
+        assert_eq!(restore_resp.status(), http::StatusCode::NOT_FOUND);
+
+        let restore_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/restore?ns={}", key, ns))
+            .to_request();
+        let restore_resp = test::call_service(&app, restore_req).await;
+        assert_eq!(restore_resp.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_rollback_namespace() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item))
+                .route(
+                    "/v2/namespaces/{namespace}/rollback",
+                    web::post().to(rollback_namespace),
+                ),
+        )
+        .await;
+
+        let ns = "v2_rollback_ns";
+
+        for key in ["a", "b", "c"] {
+            purge(&pool, ns, key);
+        }
+
+        for (key, value) in [("a", "a1"), ("b", "b1")] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let as_of = Utc::now().to_rfc3339();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        for (key, value) in [("a", "a2"), ("b", "b2"), ("c", "c2")] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let rollback_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/rollback?as_of={}&keep_new=maybe",
+                ns,
+                urlencoding::encode(&as_of)
+            ))
+            .to_request();
+        let rollback_resp = test::call_service(&app, rollback_req).await;
+        assert_eq!(rollback_resp.status(), http::StatusCode::BAD_REQUEST);
+
+        let rollback_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/rollback?as_of={}",
+                ns,
+                urlencoding::encode(&as_of)
+            ))
+            .to_request();
+        let rollback_resp = test::call_service(&app, rollback_req).await;
+        assert_eq!(rollback_resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(rollback_resp).await;
+        assert_eq!(body["namespace"], ns);
+        assert_eq!(body["restored"], serde_json::json!(["a", "b"]));
+        assert_eq!(body["deleted"], serde_json::json!(["c"]));
+        assert_eq!(body["skipped"], serde_json::json!([]));
+
+        let list_req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}", ns))
+            .to_request();
+        let list_resp = test::call_service(&app, list_req).await;
+        let body: serde_json::Value = test::read_body_json(list_resp).await;
+        assert_eq!(body["items"][0]["value"], "a1");
+        assert_eq!(body["items"][0]["versions"], 3);
+        assert_eq!(body["items"][1]["value"], "b1");
+        assert_eq!(body["items"].as_array().unwrap().len(), 2);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_put_item_large_value() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .app_data(web::PayloadConfig::new(get_max_value_size()))
+                .route("/v2/items/{id}", web::put().to(put_item)),
+        )
+        .await;
+
+        // Larger than actix's default 256kB payload limit
+        let value = "a".repeat(512 * 1024);
+
+        let put_req = test::TestRequest::put()
+            .uri("/v2/items/v2_large_key?ns=v2_put_ns")
+            .set_payload(value.clone())
+            .to_request();
+        let put_resp = test::call_service(&app, put_req).await;
+        assert_eq!(put_resp.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(put_resp).await;
+        assert_eq!(body["value"].as_str().unwrap().len(), value.len());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_put_item_invalid_utf8() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/v2/items/{id}", web::put().to(put_item)),
+        )
+        .await;
+
+        let put_req = test::TestRequest::put()
+            .uri("/v2/items/v2_binary_key?ns=v2_put_ns")
+            .set_payload(vec![0xff, 0xfe, 0xfd])
+            .to_request();
+        let put_resp = test::call_service(&app, put_req).await;
+        assert_eq!(put_resp.status(), http::StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(put_resp).await;
+        assert_eq!(body["error"], "validation_failed");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_delete_requires_write_psk() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "write_secret");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool))
+                .route("/v2/items/{id}", web::delete().to(delete_item)),
+        )
+        .await;
+
+        let req = test::TestRequest::delete()
+            .uri("/v2/items/somekey?ns=v2_delete_psk_ns")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "unauthorized");
+        assert_eq!(body["message"], "PSK required");
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_delete_undelete_and_purge() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_ADMIN");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item))
+                .route("/v2/items/{id}/history", web::get().to(history_item))
+                .route("/v2/items/{id}/undelete", web::post().to(undelete_item))
+                .route("/v2/items/{id}/purge", web::post().to(purge_item)),
+        )
+        .await;
+
+        let ns = "v2_tombstone_ns";
+        let key = "v2_tombstone_key";
+
+        purge(&pool, ns, key);
+
+        let put_req = test::TestRequest::put()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .set_payload("keep me")
+            .to_request();
+        let _ = test::call_service(&app, put_req).await;
+
+        let delete_req = test::TestRequest::delete()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let delete_resp = test::call_service(&app, delete_req).await;
+        assert_eq!(delete_resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(delete_resp).await;
+        assert_eq!(body["version"], 2);
+        assert_eq!(body["deleted"], true);
+
+        let get_req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let get_resp = test::call_service(&app, get_req).await;
+        assert_eq!(get_resp.status(), http::StatusCode::NOT_FOUND);
+
+        // Deleting an already deleted key is a 404
+        let delete_req = test::TestRequest::delete()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let delete_resp = test::call_service(&app, delete_req).await;
+        assert_eq!(delete_resp.status(), http::StatusCode::NOT_FOUND);
+
+        let history_req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/history?ns={}", key, ns))
+            .to_request();
+        let history_resp = test::call_service(&app, history_req).await;
+        let body: serde_json::Value = test::read_body_json(history_resp).await;
+        assert_eq!(body["versions"], 2);
+        assert_eq!(body["history"][0]["deleted"], true);
+        assert_eq!(body["history"][1]["value"], "keep me");
+
+        let undelete_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/undelete?ns={}", key, ns))
+            .to_request();
+        let undelete_resp = test::call_service(&app, undelete_req).await;
+        assert_eq!(undelete_resp.status(), http::StatusCode::OK);
+        assert_eq!(undelete_resp.headers().get(header::ETAG).unwrap(), "\"3\"");
+        let body: serde_json::Value = test::read_body_json(undelete_resp).await;
+        assert_eq!(body["value"], "keep me");
+
+        // Purge is disabled until an admin PSK is configured
+        let purge_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/purge?ns={}", key, ns))
+            .to_request();
+        let purge_resp = test::call_service(&app, purge_req).await;
+        assert_eq!(purge_resp.status(), http::StatusCode::FORBIDDEN);
+
+        std::env::set_var("LITTLE_LOOKUP_PSK_ADMIN", "admin_secret");
+
+        let purge_req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/purge?ns={}", key, ns))
+            .to_request();
+        let purge_resp = test::call_service(&app, purge_req).await;
+        assert_eq!(purge_resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let purge_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/items/{}/purge?ns={}&psk=admin_secret",
+                key, ns
+            ))
+            .to_request();
+        let purge_resp = test::call_service(&app, purge_req).await;
+        assert_eq!(purge_resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(purge_resp).await;
+        assert_eq!(body["purged"], 3);
+
+        let history_req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/history?ns={}", key, ns))
+            .to_request();
+        let history_resp = test::call_service(&app, history_req).await;
+        assert_eq!(history_resp.status(), http::StatusCode::NOT_FOUND);
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_ADMIN");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_namespace_management() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "write_secret");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/namespaces", web::get().to(list_namespaces))
+                .route("/v2/namespaces/{namespace}", web::get().to(namespace_stats))
+                .route(
+                    "/v2/namespaces/{namespace}",
+                    web::delete().to(delete_namespace),
+                )
+                .route(
+                    "/v2/namespaces/{namespace}/rename",
+                    web::post().to(rename_namespace),
+                ),
+        )
+        .await;
+
+        let ns = "v2_namespace_mgmt_ns";
+        let renamed = "v2_namespace_mgmt_renamed";
+
+        for namespace in [ns, renamed] {
+            for key in ["a", "b"] {
+                purge(&pool, namespace, key);
+            }
+        }
+
+        for (key, value) in [("a", "1"), ("a", "2"), ("b", "1")] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}&psk=write_secret", key, ns))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let req = test::TestRequest::get().uri("/v2/namespaces").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["namespaces"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|namespace| namespace["namespace"] == ns));
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/namespaces/{}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["keys"], 2);
+        assert_eq!(body["rows"], 3);
+        assert!(body["last_modified"].is_string());
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/namespaces/{}/rename?to={}", ns, renamed))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/rename?to={}&psk=write_secret",
+                ns, renamed
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["namespace"], renamed);
+        assert_eq!(body["renamed_from"], ns);
+        assert_eq!(body["rows"], 3);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/namespaces/{}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v2/namespaces/{}?psk=write_secret", renamed))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["deleted"], serde_json::json!(["a", "b"]));
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}", renamed))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["items"].as_array().unwrap().is_empty());
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_promote_namespace() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route(
+                    "/v2/namespaces/{namespace}/promote",
+                    web::post().to(promote_namespace),
+                ),
+        )
+        .await;
+
+        let staging = "v2_promote_staging";
+        let production = "v2_promote_production";
+
+        for namespace in [staging, production] {
+            for key in ["app.url", "app.debug", "other"] {
+                purge(&pool, namespace, key);
+            }
+        }
+
+        for (namespace, key, value) in [
+            (staging, "app.url", "https://new"),
+            (staging, "app.debug", "false"),
+            (staging, "other", "x"),
+            (production, "app.url", "https://old"),
+        ] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, namespace))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/promote?to={}&prefix=app.&dry_run=true",
+                staging, production
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["dry_run"], true);
+        assert_eq!(body["prefix"], "app.");
+        assert_eq!(
+            body["added"],
+            serde_json::json!([{"key": "app.debug", "to": "false"}])
+        );
+        assert_eq!(
+            body["changed"],
+            serde_json::json!([{"key": "app.url", "from": "https://old", "to": "https://new"}])
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/app.url?ns={}", production))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "https://old");
+
+        let req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/promote?to={}&prefix=app.",
+                staging, production
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/app.url?ns={}", production))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "https://new");
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/other?ns={}", production))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_namespace_inheritance() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route(
+                    "/v2/namespaces/{namespace}/parent",
+                    web::get().to(get_parent),
+                )
+                .route(
+                    "/v2/namespaces/{namespace}/parent",
+                    web::put().to(set_parent),
+                )
+                .route(
+                    "/v2/namespaces/{namespace}/parent",
+                    web::delete().to(remove_parent),
+                ),
+        )
+        .await;
+
+        let base = "v2_inherit_base";
+        let child = "v2_inherit_child";
+
+        for namespace in [base, child] {
+            for key in ["shared", "override"] {
+                purge(&pool, namespace, key);
+            }
+        }
+
+        for (namespace, key, value) in [
+            (base, "shared", "from-base"),
+            (base, "override", "base"),
+            (child, "override", "child"),
+        ] {
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, namespace))
+                .set_payload(value)
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/v2/namespaces/{}/parent?parent={}", child, child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/v2/namespaces/{}/parent?parent={}", child, base))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["parent"], base);
+        assert_eq!(body["chain"], serde_json::json!([child, base]));
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/shared?ns={}", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert!(resp.headers().get(header::ETAG).is_none());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "from-base");
+        assert_eq!(body["namespace"], child);
+        assert_eq!(body["layer"], base);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/override?ns={}", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(header::ETAG).is_some());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "child");
+        assert_eq!(body["layer"], child);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["key"], "override");
+        assert_eq!(items[0]["layer"], child);
+        assert_eq!(items[1]["key"], "shared");
+        assert_eq!(items[1]["layer"], base);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/shared?ns={}&inherit=false", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v2/namespaces/{}/parent", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v2/namespaces/{}/parent", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/shared?ns={}", child))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_key_tree() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/tree", web::get().to(key_tree_view)),
+        )
+        .await;
+
+        let ns = "v2_key_tree_ns";
+        let keys = ["app/web/port", "app/web/host", "app/api", "db/host"];
+
+        for key in keys {
+            purge(&pool, ns, key);
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", urlencoding::encode(key), ns))
+                .set_payload("v")
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/tree?ns={}&prefix=app/&sep=/", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["prefix"], "app/");
+        assert_eq!(
+            body["children"],
+            serde_json::json!([
+                {"segment": "api", "path": "app/api", "keys": 1, "leaf": true},
+                {"segment": "web", "path": "app/web", "keys": 2, "leaf": false},
+            ])
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}&prefix=app/web/", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let items: Vec<&str> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["key"].as_str().unwrap())
+            .collect();
+        assert_eq!(items, vec!["app/web/host", "app/web/port"]);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/tree?ns={}&sep=", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_list_items_paging_and_sort() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}", web::put().to(put_item)),
+        )
+        .await;
+
+        let ns = "v2_list_paging_ns";
+
+        for key in ["k3", "k1", "k2"] {
+            purge(&pool, ns, key);
+            let put_req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, ns))
+                .set_payload("v")
+                .to_request();
+            let _ = test::call_service(&app, put_req).await;
+        }
+
+        let keys = |body: &serde_json::Value| -> Vec<String> {
+            body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["key"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        // Without a limit the listing is streamed in full
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["namespace"], ns);
+        assert_eq!(keys(&body), vec!["k1", "k2", "k3"]);
+        assert!(body["next_cursor"].is_null());
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}&limit=2", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(keys(&body), vec!["k1", "k2"]);
+        assert_eq!(body["next_cursor"], "k2");
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items?ns={}&limit=2&cursor=k2", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(keys(&body), vec!["k3"]);
+        assert!(body["next_cursor"].is_null());
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/v2/items?ns={}&limit=1&sort=updated_at&order=desc",
+                ns
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(keys(&body), vec!["k2"]);
+        let cursor = body["next_cursor"].as_str().unwrap().to_string();
+        assert!(cursor.ends_with("|k2"));
+
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/v2/items?ns={}&sort=updated_at&order=desc&cursor={}",
+                ns,
+                urlencoding::encode(&cursor)
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(keys(&body), vec!["k1", "k3"]);
+
+        for query in [
+            "limit=0",
+            "limit=1001",
+            "sort=value",
+            "order=up",
+            "sort=updated_at&cursor=k1",
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/v2/items?ns={}&{}", ns, query))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_put_item_with_ttl() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/v2/items/{id}", web::put().to(put_item)),
+        )
+        .await;
+
+        let ns = "v2_ttl_ns";
+        let key = "lease";
+        purge(&pool, ns, key);
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/v2/items/{}?ns={}&ttl=3600", key, ns))
+            .set_payload("holder-1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let expires_at: DateTime<Utc> = serde_json::from_value(body["expires_at"].clone()).unwrap();
+        let remaining = expires_at - Utc::now();
+        assert!(remaining.num_seconds() > 3500 && remaining.num_seconds() <= 3600);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "holder-1");
+        assert!(body["expires_at"].is_string());
+
+        for query in [
+            "ttl=0",
+            "ttl=-5",
+            "ttl=soon",
+            "expires_at=2000-01-01T00:00:00Z",
+            "ttl=60&expires_at=2999-01-01T00:00:00Z",
+        ] {
+            let req = test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}&{}", key, ns, query))
+                .set_payload("holder-2")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+        }
+
+        // A write without a TTL makes the key permanent again
+        let req = test::TestRequest::put()
+            .uri(&format!("/v2/items/{}?ns={}", key, ns))
+            .set_payload("holder-2")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body.get("expires_at").is_none());
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_increment_item() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}/incr", web::post().to(increment_item)),
+        )
+        .await;
+
+        let ns = "v2_incr_ns";
+        let key = "build_number";
+        purge(&pool, ns, key);
+
+        for expected in ["1", "2"] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/v2/items/{}/incr?ns={}", key, ns))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::OK);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["value"], expected);
+        }
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/items/{}/incr?ns={}&step=10", key, ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"3\"");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "12");
+        assert_eq!(body["versions"], 3);
+
+        purge(&pool, ns, key);
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_batch_get_and_write() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/batch/get", web::post().to(batch_get))
+                .route("/v2/batch/write", web::post().to(batch_write)),
+        )
+        .await;
+
+        let ns = "v2_batch_ns";
+        for key in ["db.host", "db.port", "old_flag"] {
+            purge(&pool, ns, key);
+        }
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/batch/write?ns={}", ns))
+            .set_payload(
+                r#"{"operations":[
+                    {"op":"put","key":"db.host","value":"a"},
+                    {"op":"put","key":"db.port","value":"5432","ttl":3600},
+                    {"op":"put","key":"old_flag","value":"on"},
+                    {"op":"delete","key":"old_flag"}
+                ]}"#,
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[3]["key"], "old_flag");
+        assert_eq!(results[3]["version"], 2);
+        assert_eq!(results[3]["deleted"], true);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/batch/get?ns={}", ns))
+            .set_payload(r#"{"keys":["db.port","db.host","old_flag","nope"]}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["key"], "db.host");
+        assert_eq!(items[1]["value"], "5432");
+        assert!(items[1]["expires_at"].is_string());
+        assert_eq!(body["missing"], serde_json::json!(["old_flag", "nope"]));
+
+        // A stale expected_version rejects the whole batch
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/batch/write?ns={}", ns))
+            .set_payload(
+                r#"{"operations":[
+                    {"op":"put","key":"db.host","value":"b"},
+                    {"op":"put","key":"db.port","value":"6432","expected_version":5}
+                ]}"#,
+            )
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::CONFLICT);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/batch/get?ns={}", ns))
+            .set_payload(r#"{"keys":["db.host"]}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["items"][0]["value"], "a");
+
+        for payload in [
+            r#"{"keys":[]}"#,
+            r#"{"operations":[{"op":"rename","key":"a"}]}"#,
+            "not json",
+        ] {
+            let uri = match payload.contains("keys") {
+                true => "/v2/batch/get",
+                false => "/v2/batch/write",
+            };
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_payload(payload)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", payload);
+        }
+
+        for key in ["db.host", "db.port", "old_flag"] {
+            purge(&pool, ns, key);
+        }
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_import_namespace() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route(
+                    "/v2/namespaces/{namespace}/import",
+                    web::post().to(import_namespace),
+                )
+                .route("/v2/items/{id}", web::get().to(get_item)),
+        )
+        .await;
+
+        let ns = "v2_import_ns";
+        {
+            let mut connection = pool.get().expect("Failed to get connection");
+            Namespace::purge(ns, &mut connection).expect("Failed to purge");
+            Item::replace_into("LEGACY", "x", ns, None, &Author::default(), &mut connection)
+                .expect("Failed to write");
+        }
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/namespaces/{}/import?format=dotenv", ns))
+            .set_payload("DB_HOST=db.internal\nDB_PORT=5432\n")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["mode"], "merge");
+        assert_eq!(body["added"].as_array().unwrap().len(), 2);
+        assert!(body["removed"].as_array().unwrap().is_empty());
+
+        let req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/import?format=yaml&mode=replace&dry_run=true",
+                ns
+            ))
+            .set_payload("DB_HOST: db2.internal\nDB_PORT: 5432\n")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["dry_run"], true);
+        assert_eq!(body["changed"][0]["key"], "DB_HOST");
+        assert_eq!(body["removed"][0]["key"], "LEGACY");
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/DB_HOST?ns={}", ns))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["value"], "db.internal");
+
+        let req = test::TestRequest::post()
+            .uri(&format!(
+                "/v2/namespaces/{}/import?format=json&mode=replace",
+                ns
+            ))
+            .set_payload(r#"{"DB_HOST": "db2.internal"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["removed"].as_array().unwrap().len(), 2);
+
+        for query in ["", "format=toml", "format=json&mode=upsert"] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/v2/namespaces/{}/import?{}", ns, query))
+                .set_payload("{}")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST, "{}", query);
+        }
+
+        let mut connection = pool.get().expect("Failed to get connection");
+        Namespace::purge(ns, &mut connection).expect("Failed to purge");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_tokens() {
+        use crate::auth::tests::TokenCleanup;
+        use crate::auth::PSK_HEADER;
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::set_var("LITTLE_LOOKUP_PSK_ADMIN", "token_admin_psk");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/tokens", web::get().to(list_tokens))
+                .route("/v2/tokens", web::post().to(create_token))
+                .route("/v2/tokens/{id}", web::get().to(get_token))
+                .route("/v2/tokens/{id}", web::delete().to(revoke_token))
+                .route("/v2/tokens/{id}/expire", web::post().to(expire_token))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item)),
+        )
+        .await;
+
+        let ns = "v2_tokens_ns";
+        purge(&pool, ns, "somekey");
+        drop(TokenCleanup(pool.clone(), "test-v2-ci"));
+        let _cleanup = TokenCleanup(pool.clone(), "test-v2-ci");
+
+        let create = |psk: &str| {
+            test::TestRequest::post()
+                .uri("/v2/tokens")
+                .insert_header((PSK_HEADER, psk))
+                .set_json(serde_json::json!({
+                    "name": "test-v2-ci",
+                    "scopes": ["write"],
+                    "namespaces": ["v2_tokens_*"],
+                    "ttl": 3600,
+                }))
+                .to_request()
+        };
+        let resp = test::call_service(&app, create("wrong")).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, create("token_admin_psk")).await;
+        assert_eq!(resp.status(), http::StatusCode::CREATED);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let id = body["id"].as_i64().unwrap();
+        let secret = String::from(body["secret"].as_str().unwrap());
+        assert!(body["expires_at"].is_string());
+        assert!(body.get("token_hash").is_none());
+
+        let put = |key: &str, namespace: &str| {
+            test::TestRequest::put()
+                .uri(&format!("/v2/items/{}?ns={}", key, namespace))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+                .set_payload("v")
+                .to_request()
+        };
+        let resp = test::call_service(&app, put("somekey", ns)).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["namespace"], ns);
+
+        let resp = test::call_service(&app, put("somekey", "other_ns")).await;
+        assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v2/items/somekey?ns={}", ns))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
+        // Writes without a credential are refused once a token exists
+        let req = test::TestRequest::put()
+            .uri(&format!("/v2/items/somekey?ns={}", ns))
+            .set_payload("v")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::get()
+            .uri("/v2/tokens")
+            .insert_header((PSK_HEADER, "token_admin_psk"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["tokens"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|token| token["id"] == id && token["revoked_at"].is_null()));
+
+        // A non-numeric id is a validation error in the usual JSON shape
+        for req in [
+            test::TestRequest::get().uri("/v2/tokens/abc"),
+            test::TestRequest::post().uri("/v2/tokens/abc/expire"),
+            test::TestRequest::delete().uri("/v2/tokens/abc"),
+        ] {
+            let req = req
+                .insert_header((PSK_HEADER, "token_admin_psk"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"], "validation_failed");
+            assert_eq!(body["message"], "Invalid token id 'abc'");
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/tokens/{}", id))
+            .insert_header((PSK_HEADER, "token_admin_psk"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["id"], id);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/v2/tokens/{}/expire?ttl=60", id))
+            .insert_header((PSK_HEADER, "token_admin_psk"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v2/tokens/{}", id))
+            .insert_header((PSK_HEADER, "token_admin_psk"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["revoked_at"].is_string());
+
+        let resp = test::call_service(&app, put("somekey", ns)).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_ADMIN");
+        purge(&pool, ns, "somekey");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_audit() {
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "audit_write_psk");
+        std::env::set_var("LITTLE_LOOKUP_PSK_ADMIN", "audit_admin_psk");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items/{id}", web::put().to(put_item))
+                .route("/v2/items/{id}", web::delete().to(delete_item))
+                .route("/v2/items/{id}/history", web::get().to(history_item))
+                .route("/v2/audit", web::get().to(audit_log)),
+        )
+        .await;
+
+        let ns = "v2_audit_ns";
+        let key = "db.password";
+        purge(&pool, ns, key);
+
+        let req = test::TestRequest::put()
+            .uri(&format!(
+                "/v2/items/{}?ns={}&psk=audit_write_psk&comment=rotated%20for%20INC-42",
+                key, ns
+            ))
+            .peer_addr("192.0.2.10:40000".parse().unwrap())
+            .insert_header((header::USER_AGENT, "rotate-cli/1.0"))
+            .set_payload("s3cret")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            http::StatusCode::OK
+        );
+
+        // A forwarded address is only used when the proxy is trusted
+        std::env::set_var("LITTLE_LOOKUP_TRUST_FORWARDED_FOR", "true");
+        let req = test::TestRequest::delete()
+            .uri(&format!("/v2/items/{}?ns={}&psk=audit_write_psk", key, ns))
+            .peer_addr("10.0.0.1:40000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.4, 10.0.0.1"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            http::StatusCode::OK
+        );
+        std::env::remove_var("LITTLE_LOOKUP_TRUST_FORWARDED_FOR");
+
+        let req = test::TestRequest::put()
+            .uri(&format!(
+                "/v2/items/{}?ns={}&psk=audit_write_psk&comment={}",
+                key,
+                ns,
+                "x".repeat(crate::handlers::items::MAX_COMMENT_CHARS + 1)
+            ))
+            .set_payload("v")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            http::StatusCode::BAD_REQUEST
+        );
+
+        let audit = |query: &str, psk: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/v2/audit?{}", query))
+                .insert_header((crate::auth::PSK_HEADER, psk))
+                .to_request()
+        };
+        let resp = test::call_service(&app, audit(&format!("ns={}", ns), "audit_write_psk")).await;
+        assert_eq!(resp.status(), http::StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(
+            &app,
+            audit(&format!("ns={}&key={}", ns, key), "audit_admin_psk"),
+        )
+        .await;
+        assert_eq!(resp.status(), http::StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let entries = body["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(body["next_offset"], serde_json::Value::Null);
+        assert_eq!(entries[0]["action"], "delete");
+        assert_eq!(entries[0]["source_ip"], "198.51.100.4");
+        assert_eq!(entries[1]["action"], "write");
+        assert_eq!(entries[1]["written_by"], "psk-write");
+        assert_eq!(entries[1]["source_ip"], "192.0.2.10");
+        assert_eq!(entries[1]["user_agent"], "rotate-cli/1.0");
+        assert_eq!(entries[1]["comment"], "rotated for INC-42");
+        assert!(entries[1].get("value").is_none());
+
+        let resp = test::call_service(
+            &app,
+            audit(
+                &format!("ns={}&actor=psk-write&limit=1", ns),
+                "audit_admin_psk",
+            ),
+        )
+        .await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(body["next_offset"], 1);
+
+        let resp = test::call_service(
+            &app,
+            audit(&format!("ns={}&actor=token:nobody", ns), "audit_admin_psk"),
+        )
+        .await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["entries"].as_array().unwrap().is_empty());
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/v2/items/{}/history?ns={}", key, ns))
+            .to_request();
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["history"][1]["comment"], "rotated for INC-42");
+
+        let resp = test::call_service(&app, audit("before=3", "audit_admin_psk")).await;
+        assert_eq!(resp.status(), http::StatusCode::BAD_REQUEST);
+
+        purge(&pool, ns, key);
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_ADMIN");
+    }
+
+    #[actix_rt::test]
+    #[serial]
+    async fn test_v2_inheritance_stops_at_unreadable_parent() {
+        use crate::auth::tests::TokenCleanup;
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
+
+        let pool = establish_connection().expect("Failed to establish connection");
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(pool.clone()))
+                .route("/v2/items", web::get().to(list_items))
+                .route("/v2/items/{id}", web::get().to(get_item))
+                .route("/get/{id}", web::get().to(crate::handlers::items::get_item)),
+        )
+        .await;
+
+        let parent = "v2_scoped_parent";
+        let child = "v2_scoped_child";
+        let mut connection = pool.get().expect("Failed to get connection");
+        for namespace in [parent, child] {
+            Namespace::purge(namespace, &mut connection).expect("Failed to purge");
+        }
+        Item::replace_into(
+            "secret",
+            "from-parent",
+            parent,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "own",
+            "from-child",
+            child,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Namespace::set_parent(child, parent, &mut connection).unwrap();
+
+        drop(TokenCleanup(pool.clone(), "test-v2-scoped-child"));
+        let _cleanup = TokenCleanup(pool.clone(), "test-v2-scoped-child");
+        let (_, secret) = Token::create(
+            "test-v2-scoped-child",
+            &[String::from("read")],
+            &[String::from(child)],
+            None,
+            &mut connection,
+        )
+        .unwrap();
+        clear_token_cache();
+
+        let get = |uri: String| {
+            test::TestRequest::get()
+                .uri(&uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", secret)))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, get(format!("/v2/items/secret?ns={}", child))).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+        let resp = test::call_service(&app, get(format!("/get/secret?ns={}", child))).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        let resp = test::call_service(&app, get(format!("/v2/items?ns={}", child))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["key"], "own");
+
+        let resp = test::call_service(&app, get(format!("/v2/items/secret?ns={}", parent))).await;
+        assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
+        for namespace in [parent, child] {
+            Namespace::purge(namespace, &mut connection).expect("Failed to purge");
+        }
+    }
+}
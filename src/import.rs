@@ -0,0 +1,345 @@
+use crate::error::AppError;
+use serde_json::Value as JsonValue;
+use serde_yaml_ng::Value as YamlValue;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+    Dotenv,
+    Json,
+    Yaml,
+}
+
+impl ImportFormat {
+    pub fn parse(name: &str) -> Option<ImportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "dotenv" | "env" => Some(ImportFormat::Dotenv),
+            "json" => Some(ImportFormat::Json),
+            "yaml" | "yml" => Some(ImportFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    // `.json`, `.yaml` and `.yml` files by extension, anything else as dotenv
+    pub fn from_path(path: &str) -> ImportFormat {
+        match path.rsplit_once('.') {
+            Some((_, extension)) => match ImportFormat::parse(extension) {
+                Some(ImportFormat::Dotenv) | None => ImportFormat::Dotenv,
+                Some(format) => format,
+            },
+            None => ImportFormat::Dotenv,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImportFormat::Dotenv => "dotenv",
+            ImportFormat::Json => "json",
+            ImportFormat::Yaml => "yaml",
+        }
+    }
+}
+
+// Reads the key/value pairs of a flat file; when a key repeats, the last value wins
+pub fn parse_entries(
+    input: &str,
+    format: ImportFormat,
+) -> Result<HashMap<String, String>, AppError> {
+    let entries = match format {
+        ImportFormat::Dotenv => parse_dotenv(input)?,
+        ImportFormat::Json => parse_json(input)?,
+        ImportFormat::Yaml => parse_yaml(input)?,
+    };
+
+    if entries.contains_key("") {
+        return Err(AppError::Validation(String::from("Keys must not be empty")));
+    }
+    Ok(entries)
+}
+
+// `KEY=value` lines with optional `export`, `#` comments and single or double quoted
+// values. A quoted value may span lines, as certificates and keys do. Nothing is expanded,
+// so `${VAR}` is stored as written.
+fn parse_dotenv(input: &str) -> Result<HashMap<String, String>, AppError> {
+    let mut entries = HashMap::new();
+
+    let mut lines = input.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let invalid = |reason: &str| {
+            AppError::Validation(format!("Invalid dotenv on line {}: {}", number + 1, reason))
+        };
+
+        // Only the start is trimmed, as the end may lie inside a quoted value
+        let line = line.trim_start();
+        if line.trim_end().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line
+            .strip_prefix("export ")
+            .map(str::trim_start)
+            .unwrap_or(line);
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected KEY=value"))?;
+        let key = key.trim();
+        if key.contains(char::is_whitespace) {
+            return Err(invalid("keys must not contain whitespace"));
+        }
+
+        let mut raw = value.trim_start().to_string();
+        let value = loop {
+            match dotenv_value(raw.trim_end()) {
+                Err(reason) if reason.starts_with("unterminated") => match lines.next() {
+                    Some((_, next)) => {
+                        raw.push('\n');
+                        raw.push_str(next);
+                    }
+                    None => return Err(invalid(reason)),
+                },
+                value => break value.map_err(invalid)?,
+            }
+        };
+        entries.insert(key.to_string(), value);
+    }
+
+    Ok(entries)
+}
+
+fn dotenv_value(raw: &str) -> Result<String, &'static str> {
+    // Single quotes are literal
+    if let Some(rest) = raw.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated single quote")?;
+        check_after_quote(&rest[end + 1..])?;
+        return Ok(rest[..end].to_string());
+    }
+
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next() {
+                None => return Err("unterminated double quote"),
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => value.push(c),
+                    None => return Err("unterminated double quote"),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+        check_after_quote(chars.as_str())?;
+        return Ok(value);
+    }
+
+    // Unquoted values end at an inline comment
+    let value = match raw.find(" #") {
+        Some(comment) => &raw[..comment],
+        None => raw,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+fn check_after_quote(rest: &str) -> Result<(), &'static str> {
+    let rest = rest.trim();
+    match rest.is_empty() || rest.starts_with('#') {
+        true => Ok(()),
+        false => Err("unexpected text after the closing quote"),
+    }
+}
+
+fn parse_json(input: &str) -> Result<HashMap<String, String>, AppError> {
+    let object = match serde_json::from_str(input) {
+        Ok(JsonValue::Object(object)) => object,
+        Ok(_) => {
+            return Err(AppError::Validation(String::from(
+                "JSON import must be an object",
+            )))
+        }
+        Err(e) => return Err(AppError::Validation(format!("Invalid JSON: {}", e))),
+    };
+
+    object
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                JsonValue::String(value) => value,
+                JsonValue::Number(value) => value.to_string(),
+                JsonValue::Bool(value) => value.to_string(),
+                _ => return Err(scalar_required(&key)),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn parse_yaml(input: &str) -> Result<HashMap<String, String>, AppError> {
+    let mapping = match serde_yaml_ng::from_str(input) {
+        Ok(YamlValue::Mapping(mapping)) => mapping,
+        // An empty document
+        Ok(YamlValue::Null) => return Ok(HashMap::new()),
+        Ok(_) => {
+            return Err(AppError::Validation(String::from(
+                "YAML import must be a map",
+            )))
+        }
+        Err(e) => return Err(AppError::Validation(format!("Invalid YAML: {}", e))),
+    };
+
+    mapping
+        .into_iter()
+        .map(|(key, value)| {
+            let key = yaml_scalar(key)
+                .ok_or_else(|| AppError::Validation(String::from("YAML keys must be scalars")))?;
+            let value = yaml_scalar(value).ok_or_else(|| scalar_required(&key))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn yaml_scalar(value: YamlValue) -> Option<String> {
+    match value {
+        YamlValue::String(value) => Some(value),
+        YamlValue::Number(value) => Some(value.to_string()),
+        YamlValue::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn scalar_required(key: &str) -> AppError {
+    AppError::Validation(format!(
+        "Value of '{}' must be a string, number or boolean",
+        key
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_dotenv() {
+        let input = r#"
+# Database
+export DB_HOST=db.internal
+DB_PORT = 5432 # default port
+DB_PASS='p@ss #1'
+GREETING="hello\n\"world\""
+TEMPLATE=${HOME}/app
+EMPTY=
+"#;
+        assert_eq!(
+            parse_entries(input, ImportFormat::Dotenv).unwrap(),
+            entries(&[
+                ("DB_HOST", "db.internal"),
+                ("DB_PORT", "5432"),
+                ("DB_PASS", "p@ss #1"),
+                ("GREETING", "hello\n\"world\""),
+                ("TEMPLATE", "${HOME}/app"),
+                ("EMPTY", ""),
+            ])
+        );
+
+        // Quoted values continue until the closing quote
+        let input = "CERT=\"-----BEGIN CERTIFICATE-----\nMIIBszCCAVmgAwIBAgIU  \n-----END CERTIFICATE-----\n\"\nKEY='line 1\n  line 2'\nAFTER=1\n";
+        assert_eq!(
+            parse_entries(input, ImportFormat::Dotenv).unwrap(),
+            entries(&[
+                (
+                    "CERT",
+                    "-----BEGIN CERTIFICATE-----\nMIIBszCCAVmgAwIBAgIU  \n-----END CERTIFICATE-----\n"
+                ),
+                ("KEY", "line 1\n  line 2"),
+                ("AFTER", "1"),
+            ])
+        );
+
+        for input in [
+            "NO_EQUALS",
+            "A=\"open\nB=1",
+            "A B=c",
+            "A='open",
+            "A=\"open",
+            "A='x' y",
+            "=value",
+        ] {
+            assert!(
+                matches!(
+                    parse_entries(input, ImportFormat::Dotenv),
+                    Err(AppError::Validation(_))
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let input = r#"{"db.host": "db.internal", "db.port": 5432, "debug": false}"#;
+        assert_eq!(
+            parse_entries(input, ImportFormat::Json).unwrap(),
+            entries(&[
+                ("db.host", "db.internal"),
+                ("db.port", "5432"),
+                ("debug", "false")
+            ])
+        );
+
+        for input in [r#"["a"]"#, r#"{"a": {"b": "c"}}"#, r#"{"a": null}"#, "{"] {
+            assert!(
+                parse_entries(input, ImportFormat::Json).is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let input = "db.host: db.internal\ndb.port: 5432\ncert: |\n  line 1\n  line 2\n";
+        assert_eq!(
+            parse_entries(input, ImportFormat::Yaml).unwrap(),
+            entries(&[
+                ("db.host", "db.internal"),
+                ("db.port", "5432"),
+                ("cert", "line 1\nline 2\n")
+            ])
+        );
+        assert!(parse_entries("", ImportFormat::Yaml).unwrap().is_empty());
+
+        for input in ["- a\n- b\n", "a:\n  b: c\n", "a:\n"] {
+            assert!(
+                parse_entries(input, ImportFormat::Yaml).is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_format() {
+        assert_eq!(ImportFormat::parse("YAML"), Some(ImportFormat::Yaml));
+        assert_eq!(ImportFormat::parse("toml"), None);
+        assert_eq!(
+            ImportFormat::from_path("config/app.json"),
+            ImportFormat::Json
+        );
+        assert_eq!(ImportFormat::from_path("values.yml"), ImportFormat::Yaml);
+        assert_eq!(
+            ImportFormat::from_path(".env.production"),
+            ImportFormat::Dotenv
+        );
+        assert_eq!(ImportFormat::from_path("-"), ImportFormat::Dotenv);
+    }
+}
@@ -0,0 +1,187 @@
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Upper bound on the compiled size of client-supplied patterns
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+// Restricts a listing to matching keys; unset fields do not restrict the result
+#[derive(Clone, Default)]
+pub struct KeyFilter {
+    pub prefix: Option<String>,
+    pub pattern: Option<Regex>,
+}
+
+impl KeyFilter {
+    pub fn matches(&self, key: &str) -> bool {
+        self.prefix
+            .as_deref()
+            .is_none_or(|prefix| key.starts_with(prefix))
+            && self
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(key))
+    }
+}
+
+// Unanchored: matches when the pattern is found anywhere in the key
+pub fn compile_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+}
+
+// `*` matches any run of characters, separators included, and `?` a single character;
+// the glob has to match the whole key
+pub fn compile_glob(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    compile_regex(&pattern)
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TreeNode {
+    pub segment: String,
+    pub path: String,
+    // Keys at or below `path`
+    pub keys: usize,
+    // Whether `path` is itself a key
+    pub leaf: bool,
+}
+
+// Groups the keys below `prefix` by their next segment, sorted by segment. A prefix without
+// the trailing separator is read as if it had one, `db` like `db.`, so `dbx.b` is not below it.
+pub fn key_tree<'a, I>(keys: I, prefix: &str, separator: &str) -> Vec<TreeNode>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut nodes: BTreeMap<&str, TreeNode> = BTreeMap::new();
+    let parent = if prefix.is_empty() || prefix.ends_with(separator) {
+        prefix.to_string()
+    } else {
+        format!("{}{}", prefix, separator)
+    };
+
+    for key in keys {
+        let Some(rest) = key.strip_prefix(parent.as_str()) else {
+            continue;
+        };
+        if rest.is_empty() {
+            continue;
+        }
+        let segment = rest.split(separator).next().unwrap_or(rest);
+
+        let node = nodes.entry(segment).or_insert_with(|| TreeNode {
+            segment: segment.to_string(),
+            path: format!("{}{}", parent, segment),
+            keys: 0,
+            leaf: false,
+        });
+        node.keys += 1;
+        if segment == rest {
+            node.leaf = true;
+        }
+    }
+
+    nodes.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_filter() {
+        let filter = KeyFilter {
+            prefix: Some(String::from("db.")),
+            pattern: Some(compile_glob("db.*.host").unwrap()),
+        };
+        assert!(filter.matches("db.primary.host"));
+        assert!(!filter.matches("db.primary.port"));
+        assert!(!filter.matches("cache.db.primary.host"));
+        assert!(KeyFilter::default().matches("anything"));
+    }
+
+    #[test]
+    fn test_compile_glob() {
+        let glob = compile_glob("app/?/*.conf").unwrap();
+        assert!(glob.is_match("app/a/nginx.conf"));
+        assert!(glob.is_match("app/b/sites/default.conf"));
+        assert!(!glob.is_match("app/ab/nginx.conf"));
+        assert!(!glob.is_match("app/a/nginx.conf.bak"));
+
+        // Regex metacharacters in a glob are literal
+        let glob = compile_glob("a+b(1)").unwrap();
+        assert!(glob.is_match("a+b(1)"));
+        assert!(!glob.is_match("aab1"));
+    }
+
+    #[test]
+    fn test_compile_regex() {
+        let regex = compile_regex(r"\.(host|port)$").unwrap();
+        assert!(regex.is_match("db.primary.host"));
+        assert!(!regex.is_match("db.primary.user"));
+        assert!(compile_regex("(unclosed").is_err());
+    }
+
+    #[test]
+    fn test_key_tree() {
+        let keys = [
+            "db",
+            "db.primary.host",
+            "db.primary.port",
+            "db.replica",
+            "db.replica.host",
+            "dbx.other",
+            "cache.host",
+        ];
+
+        let tree = key_tree(keys.iter().copied(), "db.", ".");
+        assert_eq!(
+            tree,
+            vec![
+                TreeNode {
+                    segment: String::from("primary"),
+                    path: String::from("db.primary"),
+                    keys: 2,
+                    leaf: false,
+                },
+                TreeNode {
+                    segment: String::from("replica"),
+                    path: String::from("db.replica"),
+                    keys: 2,
+                    leaf: true,
+                },
+            ]
+        );
+
+        let root = key_tree(keys.iter().copied(), "", ".");
+        let segments: Vec<&str> = root.iter().map(|node| node.segment.as_str()).collect();
+        assert_eq!(segments, vec!["cache", "db", "dbx"]);
+        assert_eq!(root[1].keys, 5);
+        assert!(root[1].leaf);
+
+        let slashed = key_tree(["app/web/port", "app/api/port"], "app/", "/");
+        assert_eq!(slashed[0].path, "app/api");
+
+        // Without the trailing separator the keys below `db` still get named segments, and
+        // `dbx.other` is not below `db`
+        let unterminated = key_tree(keys.iter().copied(), "db", ".");
+        let segments: Vec<(&str, &str)> = unterminated
+            .iter()
+            .map(|node| (node.segment.as_str(), node.path.as_str()))
+            .collect();
+        assert_eq!(
+            segments,
+            vec![("primary", "db.primary"), ("replica", "db.replica")]
+        );
+        assert!(key_tree(["dbx.b"], "db", ".").is_empty());
+    }
+}
@@ -8,9 +8,18 @@ extern crate actix_web;
 extern crate dotenvy;
 extern crate openssl_probe;
 
+pub mod auth;
+pub mod cli;
+pub mod crypto;
 pub mod db_connection;
+pub mod diff;
+pub mod error;
+pub mod export;
 pub mod handlers;
+pub mod import;
+pub mod keys;
 pub mod models;
+pub mod reaper;
 pub mod schema;
 pub mod util;
 
@@ -19,7 +28,8 @@ use actix_web::{
     App, HttpServer,
 };
 use db_connection::{establish_connection, run_sql_schema_migrations};
-use util::get_worker_num;
+use std::time::Duration;
+use util::{get_max_value_size, get_reaper_interval, get_reaper_purge, get_worker_num};
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
@@ -33,6 +43,29 @@ async fn main() -> std::io::Result<()> {
     }
     dotenvy::dotenv().unwrap_or_default();
 
+    // Any arguments select a CLI command instead of the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match args.is_empty() {
+        true => None,
+        false => match cli::parse_args(&args) {
+            Ok(cli::Command::Help) => {
+                println!("{}", cli::USAGE);
+                return Ok(());
+            }
+            Ok(command) => Some(command),
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, cli::USAGE);
+                std::process::exit(2);
+            }
+        },
+    };
+
+    // Values must never be written in plaintext because of a bad key
+    if let Err(e) = crypto::init_keyring() {
+        eprintln!("Invalid encryption keys: {}", e);
+        std::process::exit(1);
+    }
+
     run_sql_schema_migrations();
 
     let pool = match establish_connection() {
@@ -43,6 +76,19 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    if let Some(command) = command {
+        std::process::exit(cli::run(command, &pool));
+    }
+
+    let reaper_interval = get_reaper_interval();
+    if reaper_interval > 0 {
+        actix_rt::spawn(reaper::run(
+            pool.clone(),
+            Duration::from_secs(reaper_interval),
+            get_reaper_purge(),
+        ));
+    }
+
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(pool.clone()))
@@ -58,8 +104,111 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/update/{id}/{val}")
                     .route(web::get().to(handlers::items::update_item)),
             )
+            .service(
+                web::resource("/incr/{id}").route(web::post().to(handlers::items::increment_item)),
+            )
             .service(web::resource("/list").route(web::get().to(handlers::items::list_items)))
             .service(web::resource("/script").route(web::get().to(handlers::items::script)))
+            .service(
+                web::scope("/v2")
+                    .app_data(web::PayloadConfig::new(get_max_value_size()))
+                    .service(web::resource("/items").route(web::get().to(handlers::v2::list_items)))
+                    .service(
+                        web::resource("/batch/get").route(web::post().to(handlers::v2::batch_get)),
+                    )
+                    .service(
+                        web::resource("/batch/write")
+                            .route(web::post().to(handlers::v2::batch_write)),
+                    )
+                    .service(
+                        web::resource("/tree").route(web::get().to(handlers::v2::key_tree_view)),
+                    )
+                    .service(
+                        web::resource("/items/{id}")
+                            .route(web::get().to(handlers::v2::get_item))
+                            .route(web::put().to(handlers::v2::put_item))
+                            .route(web::post().to(handlers::v2::put_item))
+                            .route(web::delete().to(handlers::v2::delete_item)),
+                    )
+                    .service(
+                        web::resource("/items/{id}/history")
+                            .route(web::get().to(handlers::v2::history_item)),
+                    )
+                    .service(
+                        web::resource("/items/{id}/diff")
+                            .route(web::get().to(handlers::v2::diff_item)),
+                    )
+                    .service(
+                        web::resource("/items/{id}/undelete")
+                            .route(web::post().to(handlers::v2::undelete_item)),
+                    )
+                    .service(
+                        web::resource("/items/{id}/purge")
+                            .route(web::post().to(handlers::v2::purge_item)),
+                    )
+                    .service(
+                        web::resource("/items/{id}/incr")
+                            .route(web::post().to(handlers::v2::increment_item)),
+                    )
+                    .service(
+                        web::resource("/items/{id}/restore")
+                            .route(web::post().to(handlers::v2::restore_item)),
+                    )
+                    .service(
+                        web::resource("/namespaces")
+                            .route(web::get().to(handlers::v2::list_namespaces)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}")
+                            .route(web::get().to(handlers::v2::namespace_stats))
+                            .route(web::delete().to(handlers::v2::delete_namespace)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/parent")
+                            .route(web::get().to(handlers::v2::get_parent))
+                            .route(web::put().to(handlers::v2::set_parent))
+                            .route(web::delete().to(handlers::v2::remove_parent)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/rename")
+                            .route(web::post().to(handlers::v2::rename_namespace)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/import")
+                            .route(web::post().to(handlers::v2::import_namespace)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/promote")
+                            .route(web::post().to(handlers::v2::promote_namespace)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/purge")
+                            .route(web::post().to(handlers::v2::purge_namespace)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/diff")
+                            .route(web::get().to(handlers::v2::diff_namespace)),
+                    )
+                    .service(
+                        web::resource("/namespaces/{namespace}/rollback")
+                            .route(web::post().to(handlers::v2::rollback_namespace)),
+                    )
+                    .service(web::resource("/audit").route(web::get().to(handlers::v2::audit_log)))
+                    .service(
+                        web::resource("/tokens")
+                            .route(web::get().to(handlers::v2::list_tokens))
+                            .route(web::post().to(handlers::v2::create_token)),
+                    )
+                    .service(
+                        web::resource("/tokens/{id}")
+                            .route(web::get().to(handlers::v2::get_token))
+                            .route(web::delete().to(handlers::v2::revoke_token)),
+                    )
+                    .service(
+                        web::resource("/tokens/{id}/expire")
+                            .route(web::post().to(handlers::v2::expire_token)),
+                    ),
+            )
     })
     .bind("0.0.0.0:8088")?
     .workers(get_worker_num())
@@ -73,6 +222,8 @@ mod tests {
 
     use super::*;
     #[actix_rt::test]
+    #[serial_test::serial]
+    #[allow(clippy::unnecessary_mut_passed)]
     async fn test_index() {
         let pool = establish_connection().expect("Failed to establish connection");
         let mut app = test::init_service(
@@ -88,6 +239,8 @@ mod tests {
     }
 
     #[actix_rt::test]
+    #[serial_test::serial]
+    #[allow(clippy::unnecessary_mut_passed)]
     async fn test_delete_item() {
         let pool = establish_connection().expect("Failed to establish connection");
         let mut app = test::init_service(App::new().app_data(Data::new(pool)).service(
@@ -103,6 +256,8 @@ mod tests {
     // Add more test cases for other handlers...
 
     #[actix_rt::test]
+    #[serial_test::serial]
+    #[allow(clippy::unnecessary_mut_passed)]
     async fn test_list_items() {
         let pool = establish_connection().expect("Failed to establish connection");
         let mut app = test::init_service(
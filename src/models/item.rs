@@ -1,17 +1,207 @@
-use crate::diesel::{ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl};
+use crate::crypto::{keyring, Keyring};
+use crate::db_connection::Pool;
+use crate::diesel::{
+    ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl, TextExpressionMethods,
+};
+use crate::error::AppError;
+use crate::keys::KeyFilter;
 use crate::schema::items;
 use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Instant;
 
-use diesel::pg::PgConnection;
+use diesel::deserialize::{self, QueryableByName};
+use diesel::dsl::count_star;
+use diesel::pg::{Pg, PgConnection};
+use diesel::result::DatabaseErrorKind;
+use diesel::row::NamedRow;
+use diesel::sql_types::{Array, BigInt, Integer, Nullable, Text, Timestamptz};
+use diesel::Connection;
+
+// Rows fetched per query when walking a listing with `ItemList::iter`
+pub const LIST_BATCH_SIZE: i64 = 1000;
+
+// `written_by` of the tombstones written by `Item::reap_expired`
+pub const REAPER_IDENTITY: &str = "reaper";
+
+// Current versions that are live but expired at $1
+const EXPIRED_SQL: &str = "
+SELECT key, namespace, version, expires_at
+FROM items AS expired
+WHERE expires_at <= $1
+  AND NOT deleted
+  AND NOT EXISTS (
+      SELECT 1 FROM items AS newer
+      WHERE newer.namespace = expired.namespace
+        AND newer.key = expired.key
+        AND newer.version > expired.version
+  )";
+
+// Expiries at or before $1 of versions rewritten before they expired; those never took
+// effect, so clearing them keeps the reaper from scanning the rows again
+const SUPERSEDED_EXPIRY_SQL: &str = "
+UPDATE items AS superseded
+SET expires_at = NULL
+WHERE expires_at <= $1
+  AND NOT deleted
+  AND EXISTS (
+      SELECT 1 FROM items AS newer
+      WHERE newer.namespace = superseded.namespace
+        AND newer.key = superseded.key
+        AND newer.version > superseded.version
+        AND newer.updated_at <= superseded.expires_at
+  )";
+
+// Stored versions after the position $1 namespace, $2 key, $3 version that are not sealed
+// with key $4, optionally only in namespace $6. $5 limit.
+const UNSEALED_SQL: &str = "
+SELECT key, val, updated_at, namespace, version, deleted, written_by, expires_at,
+       source_ip, user_agent, comment, key_version
+FROM items
+WHERE (namespace, key, version) > ($1, $2, $3)
+  AND key_version IS DISTINCT FROM $4
+  AND ($6::TEXT IS NULL OR namespace = $6)
+ORDER BY namespace, key, version
+LIMIT $5";
+
+// Sealed versions of namespace $1 after the position $2 key, $3 version. $4 limit.
+const SEALED_IN_NAMESPACE_SQL: &str = "
+SELECT key, val, updated_at, namespace, version, deleted, written_by, expires_at,
+       source_ip, user_agent, comment, key_version
+FROM items
+WHERE namespace = $1
+  AND (key, version) > ($2, $3)
+  AND key_version IS NOT NULL
+ORDER BY key, version
+LIMIT $4";
 
 pub struct ItemList(pub Vec<Item>);
 
-#[derive(Queryable)]
+#[derive(Serialize)]
+pub struct RollbackSummary {
+    pub restored: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub deleted: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+// Values are decrypted as rows are loaded, so an `Item` always holds the plaintext
 pub struct Item {
     pub key: String,
     pub val: String,
     pub updated_at: DateTime<Utc>,
     pub namespace: String,
+    pub version: i64,
+    pub deleted: bool,
+    pub written_by: Option<String>,
+    // Past this time the version reads as deleted
+    pub expires_at: Option<DateTime<Utc>>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    // Optional note the client sent with the change
+    pub comment: Option<String>,
+}
+
+// A row as stored; `val` is sealed when `key_version` is set
+#[derive(Queryable, QueryableByName)]
+#[diesel(table_name = items)]
+pub struct StoredItem {
+    pub key: String,
+    pub val: String,
+    pub updated_at: DateTime<Utc>,
+    pub namespace: String,
+    pub version: i64,
+    pub deleted: bool,
+    pub written_by: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub comment: Option<String>,
+    pub key_version: Option<i32>,
+}
+
+impl StoredItem {
+    // Decrypts the value; plaintext rows pass through unchanged
+    pub fn open(self, keyring: Option<&Keyring>) -> Result<Item, String> {
+        let val = match (self.key_version, keyring) {
+            (None, _) => self.val,
+            (Some(key_version), Some(keyring)) => keyring
+                .open(
+                    &self.val,
+                    key_version,
+                    &value_aad(&self.namespace, &self.key, self.version),
+                )
+                .map_err(|e| {
+                    format!(
+                        "Cannot decrypt version {} of '{}' in '{}': {}",
+                        self.version, self.key, self.namespace, e
+                    )
+                })?,
+            (Some(key_version), None) => {
+                return Err(format!(
+                    "Version {} of '{}' in '{}' is sealed with key {}, but encryption is off",
+                    self.version, self.key, self.namespace, key_version
+                ))
+            }
+        };
+
+        Ok(Item {
+            key: self.key,
+            val,
+            updated_at: self.updated_at,
+            namespace: self.namespace,
+            version: self.version,
+            deleted: self.deleted,
+            written_by: self.written_by,
+            expires_at: self.expires_at,
+            source_ip: self.source_ip,
+            user_agent: self.user_agent,
+            comment: self.comment,
+        })
+    }
+}
+
+impl<ST> Queryable<ST, Pg> for Item
+where
+    StoredItem: Queryable<ST, Pg>,
+{
+    type Row = <StoredItem as Queryable<ST, Pg>>::Row;
+
+    fn build(row: Self::Row) -> deserialize::Result<Self> {
+        Ok(<StoredItem as Queryable<ST, Pg>>::build(row)?.open(keyring())?)
+    }
+}
+
+impl QueryableByName<Pg> for Item {
+    fn build<'a>(row: &impl NamedRow<'a, Pg>) -> deserialize::Result<Self> {
+        Ok(<StoredItem as QueryableByName<Pg>>::build(row)?.open(keyring())?)
+    }
+}
+
+// Binds a sealed value to its namespace, key and version, so it cannot be moved to another
+// row. Renaming a namespace re-seals its values with `ItemList::reseal`.
+fn value_aad(namespace: &str, key: &str, version: i64) -> Vec<u8> {
+    format!("{}\0{}\0{}", namespace, key, version).into_bytes()
+}
+
+// Who made a change and from where; stored on every version the change writes
+#[derive(Clone, Debug, Default, Insertable)]
+#[diesel(table_name = items)]
+pub struct Author {
+    pub written_by: Option<String>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl Author {
+    pub fn named(written_by: &str) -> Author {
+        Author {
+            written_by: Some(written_by.to_string()),
+            ..Author::default()
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -21,278 +211,3035 @@ pub struct NewItem<'a> {
     pub val: &'a str,
     pub updated_at: &'a DateTime<Utc>,
     pub namespace: &'a str,
+    pub version: i64,
+    pub deleted: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[diesel(embed)]
+    pub author: Author,
+}
+
+// Filters and paging for `Item::history_page`; unset fields do not restrict the result
+#[derive(Default)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub before_version: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+// Filters and paging for `Item::audit`; unset fields do not restrict the result
+#[derive(Default)]
+pub struct AuditQuery {
+    pub namespace: Option<String>,
+    pub key: Option<String>,
+    pub written_by: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// A version as listed by `Item::audit`; the value is not loaded, so nothing is decrypted
+#[derive(Queryable)]
+pub struct AuditRecord {
+    pub namespace: String,
+    pub key: String,
+    pub version: i64,
+    pub deleted: bool,
+    pub updated_at: DateTime<Utc>,
+    pub written_by: Option<String>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ListSort {
+    #[default]
+    Key,
+    UpdatedAt,
+}
+
+// Position of the last item of a page; listings continue strictly after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListCursor {
+    pub key: String,
+    // Only used when sorting by `updated_at`
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl ListCursor {
+    pub fn after(item: &Item, sort: ListSort) -> ListCursor {
+        ListCursor {
+            key: item.key.clone(),
+            updated_at: match sort {
+                ListSort::Key => None,
+                ListSort::UpdatedAt => Some(item.updated_at),
+            },
+        }
+    }
+}
+
+// Order and starting point for `ItemList::iter`
+#[derive(Clone, Default)]
+pub struct ListQuery {
+    pub as_of: Option<DateTime<Utc>>,
+    pub sort: ListSort,
+    pub descending: bool,
+    pub after: Option<ListCursor>,
+}
+
+// A version found by `Item::reap_expired`; its value is not loaded, so nothing is decrypted
+#[derive(QueryableByName)]
+struct ExpiredVersion {
+    #[diesel(sql_type = Text)]
+    key: String,
+    #[diesel(sql_type = Text)]
+    namespace: String,
+    #[diesel(sql_type = BigInt)]
+    version: i64,
+    #[diesel(sql_type = Timestamptz)]
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(QueryableByName)]
+pub struct ListedItem {
+    #[diesel(embed)]
+    pub item: Item,
+    // Stored versions of the key in the namespace the value came from
+    #[diesel(sql_type = BigInt)]
+    pub versions: i64,
+}
+
+// Merges the chain nearest first like `ItemList::list_layered`, then orders by the sort
+// column with the key as tie-breaker. $1 chain, $2 as_of, $3 LIKE prefix, $4 cursor key,
+// $5 cursor updated_at, $6 limit.
+const LIST_PAGE_SQL: &str = "
+SELECT merged.*,
+       (SELECT COUNT(*) FROM items counted
+        WHERE counted.namespace = merged.namespace AND counted.key = merged.key) AS versions
+FROM (
+    SELECT DISTINCT ON (key) *
+    FROM (
+        SELECT DISTINCT ON (namespace, key)
+               key, val, updated_at, namespace, version, deleted, written_by, expires_at,
+               source_ip, user_agent, comment, key_version
+        FROM items
+        WHERE namespace = ANY($1)
+          AND ($2::TIMESTAMPTZ IS NULL OR updated_at <= $2)
+          AND ($3::TEXT IS NULL OR key LIKE $3)
+          {key_cursor}
+        ORDER BY namespace, key, version DESC
+    ) AS latest
+    WHERE NOT deleted AND (expires_at IS NULL OR expires_at > COALESCE($2, NOW()))
+    ORDER BY key, array_position($1, namespace)
+) AS merged
+WHERE $4::TEXT IS NULL OR {cursor}
+ORDER BY {order}
+LIMIT $6";
+
+// Current value of each of the keys $3, merged over the chain $1 like `LIST_PAGE_SQL`.
+// $2 as_of.
+const FIND_MANY_SQL: &str = "
+SELECT merged.*,
+       (SELECT COUNT(*) FROM items counted
+        WHERE counted.namespace = merged.namespace AND counted.key = merged.key) AS versions
+FROM (
+    SELECT DISTINCT ON (key) *
+    FROM (
+        SELECT DISTINCT ON (namespace, key)
+               key, val, updated_at, namespace, version, deleted, written_by, expires_at,
+               source_ip, user_agent, comment, key_version
+        FROM items
+        WHERE namespace = ANY($1)
+          AND key = ANY($3)
+          AND ($2::TIMESTAMPTZ IS NULL OR updated_at <= $2)
+        ORDER BY namespace, key, version DESC
+    ) AS latest
+    WHERE NOT deleted AND (expires_at IS NULL OR expires_at > COALESCE($2, NOW()))
+    ORDER BY key, array_position($1, namespace)
+) AS merged
+ORDER BY merged.key";
+
+// One write of `ItemList::write_batch`
+pub enum BatchWrite<'a> {
+    Put {
+        key: &'a str,
+        value: &'a str,
+        // Like `Item::replace_into_if`; None writes unconditionally
+        expected_version: Option<i64>,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    Delete {
+        key: &'a str,
+    },
+}
+
+// Where `ItemListIter` gets the connection for each batch. A pool lends one per batch, so
+// a listing streamed to a slow client does not hold a connection while it waits.
+pub trait BatchConnection {
+    fn load_batch<T>(
+        &mut self,
+        load: impl FnOnce(&mut PgConnection) -> Result<T, diesel::result::Error>,
+    ) -> Result<T, AppError>;
+}
+
+impl BatchConnection for &mut PgConnection {
+    fn load_batch<T>(
+        &mut self,
+        load: impl FnOnce(&mut PgConnection) -> Result<T, diesel::result::Error>,
+    ) -> Result<T, AppError> {
+        Ok(load(self)?)
+    }
+}
+
+impl BatchConnection for Pool {
+    fn load_batch<T>(
+        &mut self,
+        load: impl FnOnce(&mut PgConnection) -> Result<T, diesel::result::Error>,
+    ) -> Result<T, AppError> {
+        let mut connection = self.get()?;
+        Ok(load(&mut connection)?)
+    }
+}
+
+// Walks a listing in batches of `LIST_BATCH_SIZE`, so memory use does not grow with the
+// size of the namespace. The key pattern of the filter is applied between batches.
+pub struct ItemListIter<C> {
+    connection: C,
+    chain: Vec<String>,
+    filter: KeyFilter,
+    query: ListQuery,
+    batch: std::vec::IntoIter<ListedItem>,
+    done: bool,
+    // No batch is fetched after this time; the listing ends with an error instead
+    deadline: Option<Instant>,
+}
+
+impl<C> ItemListIter<C> {
+    pub fn with_deadline(self, deadline: Instant) -> ItemListIter<C> {
+        ItemListIter {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+}
+
+impl<C: BatchConnection> Iterator for ItemListIter<C> {
+    type Item = Result<ListedItem, AppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(listed) = self.batch.next() {
+                if self.filter.matches(&listed.item.key) {
+                    return Some(Ok(listed));
+                }
+                continue;
+            }
+            if self.done {
+                return None;
+            }
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                self.done = true;
+                return Some(Err(AppError::Timeout(String::from(
+                    "Listing took too long and was cut off",
+                ))));
+            }
+
+            let (chain, prefix, query) = (&self.chain, self.filter.prefix.as_deref(), &self.query);
+            match self.connection.load_batch(|connection| {
+                ItemList::page(connection, chain, prefix, query, LIST_BATCH_SIZE)
+            }) {
+                Ok(batch) => {
+                    self.done = (batch.len() as i64) < LIST_BATCH_SIZE;
+                    self.query.after = batch
+                        .last()
+                        .map(|listed| ListCursor::after(&listed.item, self.query.sort));
+                    self.batch = batch.into_iter();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl ItemList {
+    pub fn list(
+        connection: &mut PgConnection,
+        namespace_id: &str,
+    ) -> Result<std::vec::Vec<Item>, diesel::result::Error> {
+        ItemList::latest(connection, namespace_id, None, None)
+    }
+
+    // Reconstructs the namespace as it was at `as_of`
+    pub fn list_as_of(
+        connection: &mut PgConnection,
+        namespace_id: &str,
+        as_of: DateTime<Utc>,
+    ) -> Result<std::vec::Vec<Item>, diesel::result::Error> {
+        ItemList::latest(connection, namespace_id, Some(as_of), None)
+    }
+
+    // Merges the namespaces of `chain`, nearest first: a key takes its value from the first
+    // namespace that has it. Each item's `namespace` is the layer the value came from.
+    // The prefix of `filter` is applied in the database, its pattern afterwards.
+    pub fn list_layered(
+        connection: &mut PgConnection,
+        chain: &[String],
+        as_of: Option<DateTime<Utc>>,
+        filter: &KeyFilter,
+    ) -> Result<std::vec::Vec<Item>, diesel::result::Error> {
+        let prefix = filter.prefix.as_deref();
+
+        if let [namespace_id] = chain {
+            let mut result = ItemList::latest(connection, namespace_id, as_of, prefix)?;
+            result.retain(|item| filter.matches(&item.key));
+            return Ok(result);
+        }
+
+        let mut merged: HashMap<String, Item> = HashMap::new();
+        for namespace_id in chain.iter() {
+            let layer = ItemList::latest(connection, namespace_id, as_of, prefix)?;
+            for item in layer.into_iter().filter(|item| filter.matches(&item.key)) {
+                merged.entry(item.key.clone()).or_insert(item);
+            }
+        }
+
+        let mut result: Vec<Item> = merged.into_values().collect();
+        result.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(result)
+    }
+
+    // Re-inserts the values and expiries current at `as_of` as new versions, all in one
+    // transaction. Keys deleted since `as_of` are brought back and keys created after it are
+    // deleted, unless `keep_new` leaves them in place and reports them as skipped.
+    pub fn rollback(
+        connection: &mut PgConnection,
+        namespace_id: &str,
+        as_of: DateTime<Utc>,
+        keep_new: bool,
+        author: &Author,
+    ) -> Result<RollbackSummary, diesel::result::Error> {
+        connection.transaction(|connection| {
+            let mut past: HashMap<String, Item> =
+                ItemList::list_as_of(connection, namespace_id, as_of)?
+                    .into_iter()
+                    .map(|item| (item.key.clone(), item))
+                    .collect();
+            let current = ItemList::list(connection, namespace_id)?;
+
+            let mut summary = RollbackSummary {
+                restored: Vec::new(),
+                unchanged: Vec::new(),
+                deleted: Vec::new(),
+                skipped: Vec::new(),
+            };
+
+            for item in current.iter() {
+                match past.remove(&item.key) {
+                    Some(past_item)
+                        if past_item.val == item.val && past_item.expires_at == item.expires_at =>
+                    {
+                        summary.unchanged.push(item.key.clone())
+                    }
+                    Some(past_item) => {
+                        Item::replace_into(
+                            &item.key,
+                            &past_item.val,
+                            namespace_id,
+                            past_item.expires_at,
+                            author,
+                            connection,
+                        )?;
+                        summary.restored.push(item.key.clone());
+                    }
+                    None if keep_new => summary.skipped.push(item.key.clone()),
+                    None => {
+                        Item::destroy(&item.key, namespace_id, author, connection)?;
+                        summary.deleted.push(item.key.clone());
+                    }
+                }
+            }
+
+            let mut deleted_since: Vec<Item> = past.into_values().collect();
+            deleted_since.sort_by(|a, b| a.key.cmp(&b.key));
+            for past_item in deleted_since {
+                Item::replace_into(
+                    &past_item.key,
+                    &past_item.val,
+                    namespace_id,
+                    past_item.expires_at,
+                    author,
+                    connection,
+                )?;
+                summary.restored.push(past_item.key);
+            }
+            summary.restored.sort();
+
+            Ok(summary)
+        })
+    }
+
+    // Newest version of every key that is live at `as_of` (or now), optionally below a prefix
+    fn latest(
+        connection: &mut PgConnection,
+        namespace_id: &str,
+        as_of: Option<DateTime<Utc>>,
+        prefix: Option<&str>,
+    ) -> Result<std::vec::Vec<Item>, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, updated_at, version};
+
+        let mut query = items
+            .filter(namespace.eq(namespace_id))
+            .order_by((key, version.desc()))
+            .distinct_on(key)
+            .into_boxed();
+
+        if let Some(as_of) = as_of {
+            query = query.filter(updated_at.le(as_of));
+        }
+        if let Some(prefix) = prefix {
+            query = query.filter(key.like(format!("{}%", escape_like(prefix))));
+        }
+
+        let at = as_of.unwrap_or_else(Utc::now);
+        Ok(query
+            .load::<Item>(connection)?
+            .into_iter()
+            .filter(|item| item.is_live_at(at))
+            .collect())
+    }
+
+    // Lazily lists `chain` in the order of `query`; the iterator owns its connection source
+    // so the result can be streamed
+    pub fn iter<C: BatchConnection>(
+        connection: C,
+        chain: &[String],
+        filter: KeyFilter,
+        query: ListQuery,
+    ) -> ItemListIter<C> {
+        ItemListIter {
+            connection,
+            chain: chain.to_vec(),
+            filter,
+            query,
+            batch: Vec::new().into_iter(),
+            done: false,
+            deadline: None,
+        }
+    }
+
+    // One keyset page of at most `limit` rows, before the key pattern is applied
+    fn page(
+        connection: &mut PgConnection,
+        chain: &[String],
+        prefix: Option<&str>,
+        query: &ListQuery,
+        limit: i64,
+    ) -> Result<Vec<ListedItem>, diesel::result::Error> {
+        let (direction, operator) = match query.descending {
+            false => ("ASC", ">"),
+            true => ("DESC", "<"),
+        };
+        let (key_cursor, cursor, order) = match query.sort {
+            // Sorting by key lets the cursor narrow the scan itself
+            ListSort::Key => (
+                format!("AND ($4::TEXT IS NULL OR key {} $4)", operator),
+                format!("merged.key {} $4", operator),
+                format!("merged.key {}", direction),
+            ),
+            ListSort::UpdatedAt => (
+                String::new(),
+                format!("(merged.updated_at, merged.key) {} ($5, $4)", operator),
+                format!("merged.updated_at {0}, merged.key {0}", direction),
+            ),
+        };
+        let sql = LIST_PAGE_SQL
+            .replace("{key_cursor}", &key_cursor)
+            .replace("{cursor}", &cursor)
+            .replace("{order}", &order);
+
+        let after = query.after.as_ref();
+        diesel::sql_query(sql)
+            .bind::<Array<Text>, _>(chain)
+            .bind::<Nullable<Timestamptz>, _>(query.as_of)
+            .bind::<Nullable<Text>, _>(prefix.map(|prefix| format!("{}%", escape_like(prefix))))
+            .bind::<Nullable<Text>, _>(after.map(|after| after.key.as_str()))
+            .bind::<Nullable<Timestamptz>, _>(after.and_then(|after| after.updated_at))
+            .bind::<BigInt, _>(limit)
+            .load::<ListedItem>(connection)
+    }
+
+    // Looks up many keys in one query; keys without a live value are left out
+    pub fn find_many(
+        connection: &mut PgConnection,
+        chain: &[String],
+        keys: &[String],
+        as_of: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ListedItem>, diesel::result::Error> {
+        diesel::sql_query(FIND_MANY_SQL)
+            .bind::<Array<Text>, _>(chain)
+            .bind::<Nullable<Timestamptz>, _>(as_of)
+            .bind::<Array<Text>, _>(keys)
+            .load::<ListedItem>(connection)
+    }
+
+    // Applies `writes` in order in one transaction, so either all of them are stored or,
+    // when one fails, none. Returns the written versions; a delete returns its tombstone.
+    pub fn write_batch(
+        connection: &mut PgConnection,
+        namespace_id: &str,
+        writes: &[BatchWrite],
+        author: &Author,
+    ) -> Result<Vec<Item>, AppError> {
+        connection.transaction(|connection| {
+            writes
+                .iter()
+                .map(|write| match *write {
+                    BatchWrite::Put {
+                        key,
+                        value,
+                        expected_version: None,
+                        expires_at,
+                    } => Ok(Item::replace_into(
+                        key,
+                        value,
+                        namespace_id,
+                        expires_at,
+                        author,
+                        connection,
+                    )?),
+                    BatchWrite::Put {
+                        key,
+                        value,
+                        expected_version: Some(expected_version),
+                        expires_at,
+                    } => Item::replace_into_if(
+                        key,
+                        value,
+                        namespace_id,
+                        expected_version,
+                        expires_at,
+                        author,
+                        connection,
+                    ),
+                    BatchWrite::Delete { key } => {
+                        Item::destroy(key, namespace_id, author, connection)?
+                            .ok_or_else(|| AppError::NotFound(format!("'{}' not found", key)))
+                    }
+                })
+                .collect()
+        })
+    }
+
+    // Seals every stored version that is not yet under the active key of `keyring`,
+    // plaintext ones included, one batch per transaction. Stored versions otherwise only
+    // change when a namespace rename re-seals them, so this is safe while servers keep
+    // writing. Returns how many versions were (or with `dry_run`, would be) rewritten.
+    pub fn reencrypt(
+        connection: &mut PgConnection,
+        keyring: &Keyring,
+        namespace_id: Option<&str>,
+        dry_run: bool,
+    ) -> Result<usize, AppError> {
+        use crate::schema::items::dsl::{items, key, key_version, namespace, val, version};
+
+        let mut after = (String::new(), String::new(), i64::MIN);
+        let mut rewritten = 0;
+        loop {
+            let batch = diesel::sql_query(UNSEALED_SQL)
+                .bind::<Text, _>(&after.0)
+                .bind::<Text, _>(&after.1)
+                .bind::<BigInt, _>(after.2)
+                .bind::<Integer, _>(keyring.active_version())
+                .bind::<BigInt, _>(LIST_BATCH_SIZE)
+                .bind::<Nullable<Text>, _>(namespace_id)
+                .load::<StoredItem>(connection)?;
+            let batch_len = batch.len();
+            let Some(last) = batch.last() else {
+                return Ok(rewritten);
+            };
+            after = (last.namespace.clone(), last.key.clone(), last.version);
+
+            connection.transaction(|connection| {
+                for stored in batch {
+                    let item = stored.open(Some(keyring)).map_err(AppError::Conflict)?;
+                    if dry_run {
+                        continue;
+                    }
+
+                    let (sealed, sealed_with) = keyring
+                        .seal(
+                            &item.val,
+                            &value_aad(&item.namespace, &item.key, item.version),
+                        )
+                        .map_err(AppError::Conflict)?;
+                    diesel::update(
+                        items
+                            .filter(namespace.eq(&item.namespace))
+                            .filter(key.eq(&item.key))
+                            .filter(version.eq(item.version)),
+                    )
+                    .set((val.eq(sealed), key_version.eq(sealed_with)))
+                    .execute(connection)?;
+                }
+                Ok::<(), AppError>(())
+            })?;
+            rewritten += batch_len;
+        }
+    }
+
+    // Re-seals every sealed version of `namespace_id` for `new_namespace_id`, under the active
+    // key of `keyring`. Run in the transaction that renames the namespace, before its rows
+    // move. Returns how many versions were re-sealed.
+    pub fn reseal(
+        connection: &mut PgConnection,
+        keyring: Option<&Keyring>,
+        namespace_id: &str,
+        new_namespace_id: &str,
+    ) -> Result<usize, AppError> {
+        use crate::schema::items::dsl::{items, key, key_version, namespace, val, version};
+
+        let mut after = (String::new(), i64::MIN);
+        let mut resealed = 0;
+        loop {
+            let batch = diesel::sql_query(SEALED_IN_NAMESPACE_SQL)
+                .bind::<Text, _>(namespace_id)
+                .bind::<Text, _>(&after.0)
+                .bind::<BigInt, _>(after.1)
+                .bind::<BigInt, _>(LIST_BATCH_SIZE)
+                .load::<StoredItem>(connection)?;
+            let Some(last) = batch.last() else {
+                return Ok(resealed);
+            };
+            after = (last.key.clone(), last.version);
+            let Some(keyring) = keyring else {
+                return Err(AppError::Conflict(format!(
+                    "Namespace '{}' has encrypted values, but encryption is off",
+                    namespace_id
+                )));
+            };
+
+            for stored in batch {
+                let item = stored.open(Some(keyring)).map_err(AppError::Conflict)?;
+                let (sealed, sealed_with) = keyring
+                    .seal(
+                        &item.val,
+                        &value_aad(new_namespace_id, &item.key, item.version),
+                    )
+                    .map_err(AppError::Conflict)?;
+                diesel::update(
+                    items
+                        .filter(namespace.eq(namespace_id))
+                        .filter(key.eq(&item.key))
+                        .filter(version.eq(item.version)),
+                )
+                .set((val.eq(sealed), key_version.eq(sealed_with)))
+                .execute(connection)?;
+                resealed += 1;
+            }
+        }
+    }
+
+    pub fn version_counts(
+        connection: &mut PgConnection,
+        namespace_id: &str,
+    ) -> Result<HashMap<String, i64>, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace};
+
+        let counts = items
+            .filter(namespace.eq(namespace_id))
+            .group_by(key)
+            .select((key, count_star()))
+            .load::<(String, i64)>(connection)?;
+
+        Ok(counts.into_iter().collect())
+    }
 }
 
-impl ItemList {
-    pub fn list(
-        connection: &mut PgConnection,
-        namespace_id: &str,
-    ) -> Result<std::vec::Vec<Item>, diesel::result::Error> {
-        use crate::schema::items::dsl::{items, key, namespace, updated_at};
+impl Item {
+    pub fn find(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, version};
+
+        let item: Item = items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .order_by(version.desc())
+            .first(connection)?;
+
+        live_or_not_found(item, Utc::now())
+    }
+
+    pub fn find_as_of(
+        key_id: &str,
+        namespace_id: &str,
+        as_of: DateTime<Utc>,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, updated_at, version};
+
+        let item: Item = items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .filter(updated_at.le(as_of))
+            .order_by(version.desc())
+            .first(connection)?;
+
+        live_or_not_found(item, as_of)
+    }
+
+    // The first namespace in `chain` that has the key wins; see `ItemList::list_layered`
+    pub fn find_layered(
+        key_id: &str,
+        chain: &[String],
+        as_of: Option<DateTime<Utc>>,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        for namespace_id in chain.iter() {
+            let result = match as_of {
+                Some(as_of) => Item::find_as_of(key_id, namespace_id, as_of, connection),
+                None => Item::find(key_id, namespace_id, connection),
+            };
+            match result {
+                Err(diesel::result::Error::NotFound) => continue,
+                result => return result,
+            }
+        }
+        Err(diesel::result::Error::NotFound)
+    }
+
+    pub fn find_version(
+        key_id: &str,
+        namespace_id: &str,
+        version_id: i64,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, version};
+
+        items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .filter(version.eq(version_id))
+            .first(connection)
+    }
+
+    pub fn history(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Vec<Item>, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, version};
+
+        items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .order_by(version.desc())
+            .get_results(connection)
+    }
+
+    pub fn history_page(
+        key_id: &str,
+        namespace_id: &str,
+        history_query: &HistoryQuery,
+        connection: &mut PgConnection,
+    ) -> Result<Vec<Item>, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, updated_at, version};
+
+        let mut query = items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .order_by(version.desc())
+            .into_boxed();
+
+        if let Some(before_version) = history_query.before_version {
+            query = query.filter(version.lt(before_version));
+        }
+        if let Some(since) = history_query.since {
+            query = query.filter(updated_at.ge(since));
+        }
+        if let Some(until) = history_query.until {
+            query = query.filter(updated_at.le(until));
+        }
+        if let Some(limit) = history_query.limit {
+            query = query.limit(limit);
+        }
+        if let Some(offset) = history_query.offset {
+            query = query.offset(offset);
+        }
+
+        query.load::<Item>(connection)
+    }
+
+    // Versions written across all keys, newest first, for the audit log
+    pub fn audit(
+        audit_query: &AuditQuery,
+        connection: &mut PgConnection,
+    ) -> Result<Vec<AuditRecord>, diesel::result::Error> {
+        use crate::schema::items::dsl::{
+            comment, deleted, items, key, namespace, source_ip, updated_at, user_agent, version,
+            written_by,
+        };
+
+        let mut query = items
+            .select((
+                namespace, key, version, deleted, updated_at, written_by, source_ip, user_agent,
+                comment,
+            ))
+            .order_by((updated_at.desc(), namespace, key, version.desc()))
+            .into_boxed();
+
+        if let Some(namespace_id) = &audit_query.namespace {
+            query = query.filter(namespace.eq(namespace_id));
+        }
+        if let Some(key_id) = &audit_query.key {
+            query = query.filter(key.eq(key_id));
+        }
+        if let Some(actor) = &audit_query.written_by {
+            query = query.filter(written_by.eq(actor));
+        }
+        if let Some(since) = audit_query.since {
+            query = query.filter(updated_at.ge(since));
+        }
+        if let Some(until) = audit_query.until {
+            query = query.filter(updated_at.le(until));
+        }
+        if let Some(limit) = audit_query.limit {
+            query = query.limit(limit);
+        }
+        if let Some(offset) = audit_query.offset {
+            query = query.offset(offset);
+        }
+
+        query.load::<AuditRecord>(connection)
+    }
+
+    pub fn version_count(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<i64, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace};
+
+        items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .count()
+            .get_result(connection)
+    }
+
+    // Writes a tombstone version that hides the key but keeps its history.
+    // Returns None if there is no live key to delete.
+    pub fn destroy(
+        key_id: &str,
+        namespace_id: &str,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Option<Item>, diesel::result::Error> {
+        connection.transaction(
+            |connection| match Item::latest(key_id, namespace_id, connection)? {
+                Some(item) if item.is_live_at(Utc::now()) => {
+                    Item::append(key_id, "", namespace_id, true, None, author, connection).map(Some)
+                }
+                _ => Ok(None),
+            },
+        )
+    }
+
+    // Brings back the last live value of a deleted or expired key as a new version, without
+    // an expiry
+    pub fn undelete(
+        key_id: &str,
+        namespace_id: &str,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Item, AppError> {
+        connection.transaction(
+            |connection| match Item::latest(key_id, namespace_id, connection)? {
+                None => Err(AppError::NotFound(String::from("Undefined"))),
+                Some(item) if item.is_live_at(Utc::now()) => {
+                    Err(AppError::Conflict(format!("'{}' is not deleted", key_id)))
+                }
+                Some(_) => {
+                    let last_live = Item::history(key_id, namespace_id, connection)?
+                        .into_iter()
+                        .find(|item| !item.deleted)
+                        .ok_or_else(|| {
+                            AppError::NotFound(format!("'{}' has no live version", key_id))
+                        })?;
+                    Ok(Item::replace_into(
+                        key_id,
+                        &last_live.val,
+                        namespace_id,
+                        None,
+                        author,
+                        connection,
+                    )?)
+                }
+            },
+        )
+    }
+
+    // Permanently removes every version of a key, tombstones included
+    pub fn purge(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace};
+
+        diesel::delete(
+            items
+                .filter(key.eq(key_id))
+                .filter(namespace.eq(namespace_id)),
+        )
+        .execute(connection)
+    }
+
+    // Neither a tombstone nor expired at `at`
+    pub fn is_live_at(&self, at: DateTime<Utc>) -> bool {
+        !self.deleted && self.expires_at.is_none_or(|expires_at| expires_at > at)
+    }
+
+    // Tombstones (or with `purge`, removes) every key whose current value expired before
+    // `now`. Keys written again since they were selected are left alone. Returns the
+    // number of keys reaped.
+    //
+    // A tombstone is dated at the expiry it stands for, and the expiry of the value it
+    // replaces is then cleared, so reads as of any time see the same as before while each
+    // pass only visits rows that expired since the last one.
+    pub fn reap_expired(
+        now: DateTime<Utc>,
+        purge: bool,
+        connection: &mut PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::items::dsl::{expires_at, items, key, namespace, version};
+
+        diesel::sql_query(SUPERSEDED_EXPIRY_SQL)
+            .bind::<Timestamptz, _>(now)
+            .execute(connection)?;
+        let expired = diesel::sql_query(EXPIRED_SQL)
+            .bind::<Timestamptz, _>(now)
+            .load::<ExpiredVersion>(connection)?;
+
+        let mut reaped = 0;
+        for item in expired {
+            let still_current = connection.transaction(|connection| {
+                let current = Item::current_version(&item.key, &item.namespace, connection)?;
+                if current != item.version {
+                    return Ok(false);
+                }
+                match purge {
+                    true => Item::purge(&item.key, &item.namespace, connection).map(|_| true),
+                    false => match Item::insert_version(
+                        NewItem {
+                            key: &item.key,
+                            val: "",
+                            updated_at: &item.expires_at,
+                            namespace: &item.namespace,
+                            version: item.version + 1,
+                            deleted: true,
+                            expires_at: None,
+                            author: Author::named(REAPER_IDENTITY),
+                        },
+                        connection,
+                    ) {
+                        Ok(_) => diesel::update(
+                            items
+                                .filter(namespace.eq(&item.namespace))
+                                .filter(key.eq(&item.key))
+                                .filter(version.eq(item.version)),
+                        )
+                        .set(expires_at.eq(None::<DateTime<Utc>>))
+                        .execute(connection)
+                        .map(|_| true),
+                        Err(diesel::result::Error::DatabaseError(
+                            DatabaseErrorKind::UniqueViolation,
+                            _,
+                        )) => Ok(false),
+                        Err(e) => Err(e),
+                    },
+                }
+            })?;
+            if still_current {
+                reaped += 1;
+            }
+        }
+
+        Ok(reaped)
+    }
+
+    // Newest version of a key, tombstone or not
+    pub fn latest(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Option<Item>, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, version};
+        use diesel::OptionalExtension;
+
+        items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .order_by(version.desc())
+            .first(connection)
+            .optional()
+    }
+
+    // Version a client can observe: 0 if the key does not exist, is deleted or has expired
+    pub fn live_version(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<i64, diesel::result::Error> {
+        match Item::latest(key_id, namespace_id, connection)? {
+            Some(item) if item.is_live_at(Utc::now()) => Ok(item.version),
+            _ => Ok(0),
+        }
+    }
+
+    pub fn current_version(
+        key_id: &str,
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<i64, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key, namespace, version};
+
+        let current: Option<i64> = items
+            .filter(key.eq(key_id))
+            .filter(namespace.eq(namespace_id))
+            .select(diesel::dsl::max(version))
+            .first(connection)?;
+
+        Ok(current.unwrap_or(0))
+    }
+
+    // `expires_at` makes the value disappear at that time, as if it had been deleted
+    pub fn replace_into(
+        key_id: &str,
+        value: &str,
+        namespace_id: &str,
+        expires_at: Option<DateTime<Utc>>,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        Item::append(
+            key_id,
+            value,
+            namespace_id,
+            false,
+            expires_at,
+            author,
+            connection,
+        )
+    }
+
+    fn append(
+        key_id: &str,
+        value: &str,
+        namespace_id: &str,
+        deleted: bool,
+        expires_at: Option<DateTime<Utc>>,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        // A concurrent writer may claim the next version first; retry on top of it
+        loop {
+            let next_version = Item::current_version(key_id, namespace_id, connection)? + 1;
+            match Item::insert_version(
+                NewItem {
+                    key: key_id,
+                    val: value,
+                    updated_at: &Utc::now(),
+                    namespace: namespace_id,
+                    version: next_version,
+                    deleted,
+                    expires_at,
+                    author: author.clone(),
+                },
+                connection,
+            ) {
+                Err(diesel::result::Error::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    _,
+                )) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // Writes only if the newest version is still `expected_version`
+    // (0 when the key must not exist, be deleted or have expired)
+    pub fn replace_into_if(
+        key_id: &str,
+        value: &str,
+        namespace_id: &str,
+        expected_version: i64,
+        expires_at: Option<DateTime<Utc>>,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Item, AppError> {
+        let (current_version, live_version) = match Item::latest(key_id, namespace_id, connection)?
+        {
+            Some(item) if !item.is_live_at(Utc::now()) => (item.version, 0),
+            Some(item) => (item.version, item.version),
+            None => (0, 0),
+        };
+        if live_version != expected_version {
+            return Err(version_conflict(expected_version, live_version));
+        }
+
+        match Item::insert_version(
+            NewItem {
+                key: key_id,
+                val: value,
+                updated_at: &Utc::now(),
+                namespace: namespace_id,
+                version: current_version + 1,
+                deleted: false,
+                expires_at,
+                author: author.clone(),
+            },
+            connection,
+        ) {
+            Ok(item) => Ok(item),
+            Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                let live_version = Item::live_version(key_id, namespace_id, connection)?;
+                Err(version_conflict(expected_version, live_version))
+            }
+            Err(e) => Err(AppError::from(e)),
+        }
+    }
+
+    // Adds `step` to the integer value of a key as a new version. A key that does not exist,
+    // is deleted or has expired counts as `initial`. Without `expires_at` the new version
+    // keeps the expiry of the current one. Concurrent increments each land on their own
+    // version, so none are lost.
+    pub fn increment(
+        key_id: &str,
+        namespace_id: &str,
+        step: i64,
+        initial: i64,
+        expires_at: Option<DateTime<Utc>>,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Item, AppError> {
+        loop {
+            let (current_version, current_value, current_expiry) =
+                match Item::latest(key_id, namespace_id, connection)? {
+                    Some(item) if item.is_live_at(Utc::now()) => {
+                        let value = item.val.trim().parse::<i64>().map_err(|_| {
+                            AppError::Conflict(format!("Value of '{}' is not an integer", key_id))
+                        })?;
+                        (item.version, value, item.expires_at)
+                    }
+                    Some(item) => (item.version, initial, None),
+                    None => (0, initial, None),
+                };
+            let value = current_value.checked_add(step).ok_or_else(|| {
+                AppError::Conflict(format!("Adding {} to '{}' overflows", step, key_id))
+            })?;
+
+            match Item::insert_version(
+                NewItem {
+                    key: key_id,
+                    val: &value.to_string(),
+                    updated_at: &Utc::now(),
+                    namespace: namespace_id,
+                    version: current_version + 1,
+                    deleted: false,
+                    expires_at: expires_at.or(current_expiry),
+                    author: author.clone(),
+                },
+                connection,
+            ) {
+                Err(diesel::result::Error::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    _,
+                )) => continue,
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    fn insert_version(
+        new_item: NewItem,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        Item::insert_sealed(new_item, keyring(), connection)
+    }
+
+    // Seals the value when a keyring is given
+    fn insert_sealed(
+        new_item: NewItem,
+        keyring: Option<&Keyring>,
+        connection: &mut PgConnection,
+    ) -> Result<Item, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, key_version};
+
+        let sealed = match keyring {
+            Some(keyring) => Some(
+                keyring
+                    .seal(
+                        new_item.val,
+                        &value_aad(new_item.namespace, new_item.key, new_item.version),
+                    )
+                    .map_err(|e| diesel::result::Error::SerializationError(e.into()))?,
+            ),
+            None => None,
+        };
+        let (new_item, sealed_with) = match &sealed {
+            Some((val, version)) => (NewItem { val, ..new_item }, Some(*version)),
+            None => (new_item, None),
+        };
+
+        // Savepoint when nested, so a lost race does not abort the caller's transaction
+        let stored = connection.transaction(|connection| {
+            diesel::insert_into(items)
+                .values((&new_item, key_version.eq(sealed_with)))
+                .get_result::<StoredItem>(connection)
+        })?;
+        stored
+            .open(keyring)
+            .map_err(|e| diesel::result::Error::DeserializationError(e.into()))
+    }
+}
+
+// Escapes the LIKE wildcards so `prefix` only matches literally
+fn escape_like(prefix: &str) -> String {
+    prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn live_or_not_found(item: Item, at: DateTime<Utc>) -> Result<Item, diesel::result::Error> {
+    if item.is_live_at(at) {
+        Ok(item)
+    } else {
+        Err(diesel::result::Error::NotFound)
+    }
+}
+
+fn version_conflict(expected_version: i64, current_version: i64) -> AppError {
+    AppError::Conflict(format!(
+        "Expected version {} but current version is {}",
+        expected_version, current_version
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::get_database;
+    use chrono::TimeDelta;
+
+    use super::*;
+    // Helper function to establish a database connection
+    fn establish_connection() -> PgConnection {
+        let database_url = get_database();
+        PgConnection::establish(&database_url).expect("Failed to connect to database")
+    }
+
+    #[test]
+    fn test_list_items() {
+        let mut connection = establish_connection();
+        let namespace_id = "list_test_namespace";
+
+        let result = ItemList::list(&mut connection, namespace_id);
+        assert!(result.is_ok());
+        // Result can be empty or contain items - both are valid
+        let items = result.unwrap();
+        assert!(items.is_empty() || !items.is_empty());
+    }
+
+    #[test]
+    fn test_list_items_empty_namespace() {
+        let mut connection = establish_connection();
+        let namespace_id = "empty_namespace_12345";
+
+        let result = ItemList::list(&mut connection, namespace_id);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_items_empty() {
+        let mut connection = establish_connection();
+        let key_id = "nonexistent_key_12345";
+        let namespace_id = "my_namespace";
+
+        let result = Item::history(key_id, namespace_id, &mut connection);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_destroy_item_not_found() {
+        let mut connection = establish_connection();
+        let key_id = "nonexistent_delete_key_12345";
+        let namespace_id = "my_namespace";
+
+        let result = Item::destroy(key_id, namespace_id, &Author::default(), &mut connection);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_item_not_found() {
+        let mut connection = establish_connection();
+        let key_id = "nonexistent_find_key_12345";
+        let namespace_id = "my_namespace";
+
+        let result = Item::find(key_id, namespace_id, &mut connection);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_replace_into_item_and_find() {
+        let mut connection = establish_connection();
+        let key_id = "test_key_12345";
+        let value: &str = "test_value_12345";
+        let namespace_id = "test_namespace_12345";
+
+        let replace_result = Item::replace_into(
+            key_id,
+            value,
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        );
+        assert!(replace_result.is_ok());
+
+        let find_result = Item::find(key_id, namespace_id, &mut connection);
+        assert!(find_result.is_ok());
+
+        let found_item = find_result.unwrap();
+        assert_eq!(found_item.key, key_id);
+        assert_eq!(found_item.val, value);
+        assert_eq!(found_item.namespace, namespace_id);
+    }
+
+    #[test]
+    fn test_replace_into_multiple_values_and_history() {
+        let mut connection = establish_connection();
+        let key_id = "history_key_12345";
+        let value1 = "value1";
+        let value2 = "value2";
+        let namespace_id = "history_namespace_12345";
+
+        // Insert first value
+        assert!(Item::replace_into(
+            key_id,
+            value1,
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        // Insert second value (overwrites)
+        assert!(Item::replace_into(
+            key_id,
+            value2,
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        // Get current value
+        let find_result = Item::find(key_id, namespace_id, &mut connection);
+        assert!(find_result.is_ok());
+        assert_eq!(find_result.unwrap().val, value2);
+
+        // Get history - should have 2 entries
+        let history_result = Item::history(key_id, namespace_id, &mut connection);
+        assert!(history_result.is_ok());
+        let history = history_result.unwrap();
+        assert!(history.len() >= 2);
+        // Most recent should be first (order_by updated_at desc)
+        assert_eq!(history[0].val, value2);
+    }
+
+    #[test]
+    fn test_destroy_writes_tombstone() {
+        let mut connection = establish_connection();
+        let key_id = "tombstone_key_12345";
+        let namespace_id = "tombstone_namespace_12345";
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        Item::replace_into(
+            key_id,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            key_id,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+
+        let tombstone = Item::destroy(key_id, namespace_id, &Author::default(), &mut connection)
+            .unwrap()
+            .unwrap();
+        assert!(tombstone.deleted);
+        assert_eq!(tombstone.version, 3);
+
+        // Hidden from find and list, but the history is kept
+        assert!(matches!(
+            Item::find(key_id, namespace_id, &mut connection),
+            Err(diesel::result::Error::NotFound)
+        ));
+        assert!(ItemList::list(&mut connection, namespace_id)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Item::history(key_id, namespace_id, &mut connection)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            Item::live_version(key_id, namespace_id, &mut connection).unwrap(),
+            0
+        );
+
+        // Deleting twice is a no-op
+        assert!(
+            Item::destroy(key_id, namespace_id, &Author::default(), &mut connection)
+                .unwrap()
+                .is_none()
+        );
+
+        // Create-only writes succeed on a deleted key
+        let recreated = Item::replace_into_if(
+            key_id,
+            "v3",
+            namespace_id,
+            0,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(recreated.version, 4);
+    }
+
+    #[test]
+    fn test_undelete_restores_last_live_value() {
+        let mut connection = establish_connection();
+        let key_id = "undelete_key_12345";
+        let namespace_id = "undelete_namespace_12345";
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        assert!(matches!(
+            Item::undelete(key_id, namespace_id, &Author::default(), &mut connection),
+            Err(AppError::NotFound(_))
+        ));
+
+        Item::replace_into(
+            key_id,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert!(matches!(
+            Item::undelete(key_id, namespace_id, &Author::default(), &mut connection),
+            Err(AppError::Conflict(_))
+        ));
+
+        Item::destroy(key_id, namespace_id, &Author::default(), &mut connection).unwrap();
+        let item =
+            Item::undelete(key_id, namespace_id, &Author::default(), &mut connection).unwrap();
+        assert_eq!(item.val, "v1");
+        assert_eq!(item.version, 3);
+        assert!(!item.deleted);
+
+        let found = Item::find(key_id, namespace_id, &mut connection).unwrap();
+        assert_eq!(found.val, "v1");
+
+        Item::replace_into(
+            key_id,
+            "v2",
+            namespace_id,
+            Some(Utc::now() - TimeDelta::seconds(1)),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        let item =
+            Item::undelete(key_id, namespace_id, &Author::default(), &mut connection).unwrap();
+        assert_eq!(item.val, "v2");
+        assert_eq!(item.version, 5);
+        assert_eq!(item.expires_at, None);
+    }
+
+    #[test]
+    fn test_purge_removes_all_versions() {
+        let mut connection = establish_connection();
+        let key_id = "destroy_key_12345";
+        let namespace_id = "destroy_namespace_12345";
+
+        // Insert multiple versions
+        assert!(Item::replace_into(
+            key_id,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key_id,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        // Verify we have history
+        let history_before = Item::history(key_id, namespace_id, &mut connection).unwrap();
+        assert!(history_before.len() >= 2);
+
+        // Purge all
+        let destroy_result = Item::purge(key_id, namespace_id, &mut connection);
+        assert!(destroy_result.is_ok());
+        assert!(destroy_result.unwrap() >= 2);
+
+        // Verify all are deleted
+        let history_after = Item::history(key_id, namespace_id, &mut connection).unwrap();
+        assert!(history_after.is_empty());
+    }
+
+    #[test]
+    fn test_namespace_isolation() {
+        let mut connection = establish_connection();
+        let key_id = "shared_key";
+        let ns1 = "namespace_1_12345";
+        let ns2 = "namespace_2_12345";
+
+        // Insert same key in different namespaces
+        assert!(Item::replace_into(
+            key_id,
+            "value_ns1",
+            ns1,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key_id,
+            "value_ns2",
+            ns2,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        // Verify they are isolated
+        let item_ns1 = Item::find(key_id, ns1, &mut connection).unwrap();
+        let item_ns2 = Item::find(key_id, ns2, &mut connection).unwrap();
+
+        assert_eq!(item_ns1.val, "value_ns1");
+        assert_eq!(item_ns2.val, "value_ns2");
+        assert_eq!(item_ns1.namespace, ns1);
+        assert_eq!(item_ns2.namespace, ns2);
+    }
+
+    #[test]
+    fn test_list_returns_distinct_keys_with_latest_value() {
+        let mut connection = establish_connection();
+        let key1 = "list_key1_12345";
+        let key2 = "list_key2_12345";
+        let namespace_id = "list_distinct_namespace_12345";
+
+        // Insert multiple versions of key1
+        assert!(Item::replace_into(
+            key1,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key1,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        // Insert single version of key2
+        assert!(Item::replace_into(
+            key2,
+            "v3",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        // List should return both keys with latest values
+        let list_result = ItemList::list(&mut connection, namespace_id);
+        assert!(list_result.is_ok());
+
+        let items = list_result.unwrap();
+        let item1 = items.iter().find(|item| item.key == key1);
+        let item2 = items.iter().find(|item| item.key == key2);
+
+        assert!(item1.is_some());
+        assert!(item2.is_some());
+
+        assert_eq!(item1.unwrap().val, "v2");
+        assert_eq!(item2.unwrap().val, "v3");
+    }
+
+    #[test]
+    fn test_replace_into_increments_version() {
+        let mut connection = establish_connection();
+        let key_id = "version_key_12345";
+        let namespace_id = "version_namespace_12345";
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        assert_eq!(
+            Item::current_version(key_id, namespace_id, &mut connection).unwrap(),
+            0
+        );
+
+        let first = Item::replace_into(
+            key_id,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        let second = Item::replace_into(
+            key_id,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(first.version, 1);
+        assert_eq!(second.version, 2);
+
+        let found = Item::find(key_id, namespace_id, &mut connection).unwrap();
+        assert_eq!(found.version, 2);
+        assert_eq!(found.val, "v2");
+    }
+
+    #[test]
+    fn test_increment() {
+        let mut connection = establish_connection();
+        let key_id = "counter_key_12345";
+        let namespace_id = "counter_namespace_12345";
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+
+        let first = Item::increment(
+            key_id,
+            namespace_id,
+            1,
+            0,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(first.val, "1");
+        assert_eq!(first.version, 1);
+
+        let second = Item::increment(
+            key_id,
+            namespace_id,
+            5,
+            100,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(second.val, "6");
+        assert_eq!(second.version, 2);
+
+        let third = Item::increment(
+            key_id,
+            namespace_id,
+            -10,
+            0,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(third.val, "-4");
+        assert_eq!(
+            Item::history(key_id, namespace_id, &mut connection)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // An increment without an expiry keeps the current one
+        let in_an_hour = Utc::now() + TimeDelta::hours(1);
+        Item::increment(
+            key_id,
+            namespace_id,
+            1,
+            0,
+            Some(in_an_hour),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        let kept = Item::increment(
+            key_id,
+            namespace_id,
+            1,
+            0,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(kept.val, "-2");
+        assert_eq!(
+            kept.expires_at.map(|at| at.timestamp_micros()),
+            Some(in_an_hour.timestamp_micros())
+        );
+
+        // A deleted counter starts over from `initial`
+        Item::destroy(key_id, namespace_id, &Author::default(), &mut connection).unwrap();
+        let restarted = Item::increment(
+            key_id,
+            namespace_id,
+            1,
+            41,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(restarted.val, "42");
+        assert_eq!(restarted.version, 7);
+        assert_eq!(restarted.expires_at, None);
+
+        Item::replace_into(
+            key_id,
+            "not a number",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert!(matches!(
+            Item::increment(
+                key_id,
+                namespace_id,
+                1,
+                0,
+                None,
+                &Author::default(),
+                &mut connection
+            ),
+            Err(AppError::Conflict(_))
+        ));
+
+        Item::replace_into(
+            key_id,
+            &i64::MAX.to_string(),
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert!(matches!(
+            Item::increment(
+                key_id,
+                namespace_id,
+                1,
+                0,
+                None,
+                &Author::default(),
+                &mut connection
+            ),
+            Err(AppError::Conflict(_))
+        ));
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+    }
+
+    #[test]
+    fn test_replace_into_if_conflict() {
+        let mut connection = establish_connection();
+        let key_id = "cas_key_12345";
+        let namespace_id = "cas_namespace_12345";
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+
+        let created = Item::replace_into_if(
+            key_id,
+            "v1",
+            namespace_id,
+            0,
+            None,
+            &Author::default(),
+            &mut connection,
+        );
+        assert_eq!(created.unwrap().version, 1);
+
+        // Key already exists, create-only write fails
+        let result = Item::replace_into_if(
+            key_id,
+            "v1",
+            namespace_id,
+            0,
+            None,
+            &Author::default(),
+            &mut connection,
+        );
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+
+        let updated = Item::replace_into_if(
+            key_id,
+            "v2",
+            namespace_id,
+            1,
+            None,
+            &Author::default(),
+            &mut connection,
+        );
+        assert_eq!(updated.unwrap().version, 2);
+
+        let result = Item::replace_into_if(
+            key_id,
+            "v3",
+            namespace_id,
+            1,
+            None,
+            &Author::default(),
+            &mut connection,
+        );
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+
+        let found = Item::find(key_id, namespace_id, &mut connection).unwrap();
+        assert_eq!(found.val, "v2");
+    }
+
+    #[test]
+    fn test_find_and_list_as_of() {
+        let mut connection = establish_connection();
+        let key1 = "as_of_key1_12345";
+        let key2 = "as_of_key2_12345";
+        let namespace_id = "as_of_namespace_12345";
+
+        Item::purge(key1, namespace_id, &mut connection).unwrap();
+        Item::purge(key2, namespace_id, &mut connection).unwrap();
+
+        let before = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(Item::replace_into(
+            key1,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let middle = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(Item::replace_into(
+            key1,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key2,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+
+        let found = Item::find_as_of(key1, namespace_id, middle, &mut connection).unwrap();
+        assert_eq!(found.val, "v1");
+        assert_eq!(found.version, 1);
 
-        let result = items
-            .filter(namespace.eq(namespace_id))
-            .order_by((key, updated_at.desc()))
-            .distinct_on(key)
-            .load::<Item>(connection)?;
+        let result = Item::find_as_of(key1, namespace_id, before, &mut connection);
+        assert!(result.is_err());
 
-        Ok(result)
+        let listed = ItemList::list_as_of(&mut connection, namespace_id, middle).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].key, key1);
+        assert_eq!(listed[0].val, "v1");
+
+        let listed = ItemList::list_as_of(&mut connection, namespace_id, Utc::now()).unwrap();
+        assert_eq!(listed.len(), 2);
     }
-}
 
-impl Item {
-    pub fn find(
-        key_id: &str,
-        namespace_id: &str,
-        connection: &mut PgConnection,
-    ) -> Result<Item, diesel::result::Error> {
-        use crate::schema::items::dsl::{items, key, namespace, updated_at};
+    #[test]
+    fn test_find_version() {
+        let mut connection = establish_connection();
+        let key_id = "find_version_key_12345";
+        let namespace_id = "find_version_namespace_12345";
 
-        items
-            .filter(key.eq(key_id))
-            .filter(namespace.eq(namespace_id))
-            .order_by(updated_at.desc())
-            .first(connection)
-    }
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        assert!(Item::replace_into(
+            key_id,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key_id,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
 
-    pub fn history(
-        key_id: &str,
-        namespace_id: &str,
-        connection: &mut PgConnection,
-    ) -> Result<Vec<Item>, diesel::result::Error> {
-        use crate::schema::items::dsl::{items, key, namespace, updated_at};
+        let found = Item::find_version(key_id, namespace_id, 1, &mut connection).unwrap();
+        assert_eq!(found.val, "v1");
 
-        items
-            .filter(key.eq(key_id))
-            .filter(namespace.eq(namespace_id))
-            .order_by(updated_at.desc())
-            .get_results(connection)
+        let result = Item::find_version(key_id, namespace_id, 3, &mut connection);
+        assert!(result.is_err());
     }
 
-    pub fn destroy(
-        key_id: &str,
-        namespace_id: &str,
-        connection: &mut PgConnection,
-    ) -> Result<usize, diesel::result::Error> {
-        use crate::schema::items::dsl::{items, key, namespace};
+    #[test]
+    fn test_rollback_namespace() {
+        let mut connection = establish_connection();
+        let namespace_id = "rollback_namespace_12345";
 
-        let delete_count = diesel::delete(
-            items
-                .filter(key.eq(key_id))
-                .filter(namespace.eq(namespace_id)),
+        for key_id in [
+            "changed",
+            "same",
+            "created_later",
+            "deleted_later",
+            "expiry_changed",
+        ] {
+            Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        }
+
+        assert!(Item::replace_into(
+            "changed",
+            "old",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
         )
-        .execute(connection)
-        .unwrap_or_default();
-        Ok(delete_count)
-    }
+        .is_ok());
+        assert!(Item::replace_into(
+            "same",
+            "same",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            "deleted_later",
+            "d",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        let expires_at = Utc::now() + TimeDelta::hours(1);
+        assert!(Item::replace_into(
+            "expiry_changed",
+            "e",
+            namespace_id,
+            Some(expires_at),
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let as_of = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(Item::replace_into(
+            "expiry_changed",
+            "e",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            "changed",
+            "new",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            "created_later",
+            "x",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::destroy(
+            "deleted_later",
+            namespace_id,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
 
-    pub fn replace_into(
-        key_id: &str,
-        value: &str,
-        namespace_id: &str,
-        connection: &mut PgConnection,
-    ) -> Result<(), diesel::result::Error> {
-        use crate::schema::items::dsl::items;
+        // Keys created after `as_of` can be kept
+        let summary = ItemList::rollback(
+            &mut connection,
+            namespace_id,
+            as_of,
+            true,
+            &Author::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            summary.restored,
+            vec!["changed", "deleted_later", "expiry_changed"]
+        );
+        assert_eq!(summary.unchanged, vec!["same"]);
+        assert!(summary.deleted.is_empty());
+        assert_eq!(summary.skipped, vec!["created_later"]);
 
-        let now = Utc::now();
-        let new_item = NewItem {
-            key: key_id,
-            val: value,
-            updated_at: &now,
-            namespace: namespace_id,
-        };
+        let found = Item::find("expiry_changed", namespace_id, &mut connection).unwrap();
+        assert_eq!(
+            found.expires_at.map(|at| at.timestamp_micros()),
+            Some(expires_at.timestamp_micros())
+        );
 
-        diesel::insert_into(items)
-            .values(&new_item)
-            .execute(connection)?;
-        Ok(())
+        // By default they are deleted, leaving the namespace as it was at `as_of`
+        let summary = ItemList::rollback(
+            &mut connection,
+            namespace_id,
+            as_of,
+            false,
+            &Author::default(),
+        )
+        .unwrap();
+        assert!(summary.restored.is_empty());
+        assert_eq!(
+            summary.unchanged,
+            vec!["changed", "deleted_later", "expiry_changed", "same"]
+        );
+        assert_eq!(summary.deleted, vec!["created_later"]);
+        assert!(summary.skipped.is_empty());
+        assert!(Item::find("created_later", namespace_id, &mut connection).is_err());
+
+        let current: Vec<(String, String)> = ItemList::list(&mut connection, namespace_id)
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.key, item.val))
+            .collect();
+        let past: Vec<(String, String)> =
+            ItemList::list_as_of(&mut connection, namespace_id, as_of)
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.key, item.val))
+                .collect();
+        assert_eq!(current, past);
+
+        let found = Item::find("changed", namespace_id, &mut connection).unwrap();
+        assert_eq!(found.val, "old");
+        // History is append-only: the restore is a new version
+        assert_eq!(found.version, 3);
+
+        let found = Item::find("deleted_later", namespace_id, &mut connection).unwrap();
+        assert_eq!(found.val, "d");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::util::get_database;
+    #[test]
+    fn test_history_page() {
+        let mut connection = establish_connection();
+        let key_id = "history_page_key_12345";
+        let namespace_id = "history_page_namespace_12345";
 
-    use super::*;
-    use diesel::prelude::*;
-    // Helper function to establish a database connection
-    fn establish_connection() -> PgConnection {
-        let database_url = get_database();
-        PgConnection::establish(&database_url).expect("Failed to connect to database")
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        Item::replace_into(
+            key_id,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let since = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        Item::replace_into(
+            key_id,
+            "v2",
+            namespace_id,
+            None,
+            &Author::named("psk-write"),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            key_id,
+            "v3",
+            namespace_id,
+            None,
+            &Author::named("psk-write"),
+            &mut connection,
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let until = Utc::now();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        Item::replace_into(
+            key_id,
+            "v4",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+
+        let all = Item::history_page(
+            key_id,
+            namespace_id,
+            &HistoryQuery::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[1].written_by.as_deref(), Some("psk-write"));
+        assert_eq!(all[3].written_by, None);
+
+        let window = HistoryQuery {
+            since: Some(since),
+            until: Some(until),
+            ..Default::default()
+        };
+        let versions: Vec<i64> = Item::history_page(key_id, namespace_id, &window, &mut connection)
+            .unwrap()
+            .iter()
+            .map(|item| item.version)
+            .collect();
+        assert_eq!(versions, vec![3, 2]);
+
+        let page = HistoryQuery {
+            limit: Some(2),
+            before_version: Some(4),
+            ..Default::default()
+        };
+        let versions: Vec<i64> = Item::history_page(key_id, namespace_id, &page, &mut connection)
+            .unwrap()
+            .iter()
+            .map(|item| item.version)
+            .collect();
+        assert_eq!(versions, vec![3, 2]);
+
+        let page = HistoryQuery {
+            limit: Some(1),
+            offset: Some(3),
+            ..Default::default()
+        };
+        let versions: Vec<i64> = Item::history_page(key_id, namespace_id, &page, &mut connection)
+            .unwrap()
+            .iter()
+            .map(|item| item.version)
+            .collect();
+        assert_eq!(versions, vec![1]);
     }
 
     #[test]
-    fn test_list_items() {
+    fn test_layered_lookups() {
         let mut connection = establish_connection();
-        let namespace_id = "list_test_namespace";
+        let base = "layered_base_12345";
+        let leaf = "layered_leaf_12345";
+        let chain = vec![String::from(leaf), String::from(base)];
 
-        let result = ItemList::list(&mut connection, namespace_id);
-        assert!(result.is_ok());
-        // Result can be empty or contain items - both are valid
-        let items = result.unwrap();
-        assert!(items.is_empty() || !items.is_empty());
+        for namespace_id in [base, leaf] {
+            for key_id in ["shared", "base_only", "leaf_only", "deleted_in_leaf"] {
+                Item::purge(key_id, namespace_id, &mut connection).unwrap();
+            }
+        }
+        Item::replace_into(
+            "shared",
+            "base",
+            base,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "base_only",
+            "base",
+            base,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "deleted_in_leaf",
+            "base",
+            base,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "shared",
+            "leaf",
+            leaf,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "leaf_only",
+            "leaf",
+            leaf,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "deleted_in_leaf",
+            "leaf",
+            leaf,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::destroy("deleted_in_leaf", leaf, &Author::default(), &mut connection).unwrap();
+
+        let shared = Item::find_layered("shared", &chain, None, &mut connection).unwrap();
+        assert_eq!(
+            (shared.val.as_str(), shared.namespace.as_str()),
+            ("leaf", leaf)
+        );
+        let base_only = Item::find_layered("base_only", &chain, None, &mut connection).unwrap();
+        assert_eq!(base_only.namespace, base);
+        let deleted = Item::find_layered("deleted_in_leaf", &chain, None, &mut connection).unwrap();
+        assert_eq!(deleted.namespace, base);
+        assert!(Item::find_layered("missing", &chain, None, &mut connection).is_err());
+
+        let layered: Vec<(String, String)> =
+            ItemList::list_layered(&mut connection, &chain, None, &KeyFilter::default())
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.key, item.namespace))
+                .collect();
+        assert_eq!(
+            layered,
+            vec![
+                (String::from("base_only"), String::from(base)),
+                (String::from("deleted_in_leaf"), String::from(base)),
+                (String::from("leaf_only"), String::from(leaf)),
+                (String::from("shared"), String::from(leaf)),
+            ]
+        );
     }
 
     #[test]
-    fn test_list_items_empty_namespace() {
+    fn test_list_layered_with_key_filter() {
         let mut connection = establish_connection();
-        let namespace_id = "empty_namespace_12345";
+        let namespace_id = "key_filter_namespace_12345";
+        let chain = vec![String::from(namespace_id)];
 
-        let result = ItemList::list(&mut connection, namespace_id);
-        assert!(result.is_ok());
-        assert!(result.unwrap().is_empty());
+        for key_id in [
+            "db.primary.host",
+            "db.primary.port",
+            "db_x",
+            "dbyx",
+            "cache.host",
+        ] {
+            Item::purge(key_id, namespace_id, &mut connection).unwrap();
+            Item::replace_into(
+                key_id,
+                "v",
+                namespace_id,
+                None,
+                &Author::default(),
+                &mut connection,
+            )
+            .unwrap();
+        }
+
+        let keys = |filter: &KeyFilter, connection: &mut PgConnection| -> Vec<String> {
+            ItemList::list_layered(connection, &chain, None, filter)
+                .unwrap()
+                .into_iter()
+                .map(|item| item.key)
+                .collect()
+        };
+
+        let prefix = KeyFilter {
+            prefix: Some(String::from("db.")),
+            pattern: None,
+        };
+        assert_eq!(
+            keys(&prefix, &mut connection),
+            vec!["db.primary.host", "db.primary.port"]
+        );
+
+        // `_` is not a LIKE wildcard here
+        let underscore = KeyFilter {
+            prefix: Some(String::from("db_")),
+            pattern: None,
+        };
+        assert_eq!(keys(&underscore, &mut connection), vec!["db_x"]);
+
+        let glob = KeyFilter {
+            prefix: None,
+            pattern: Some(crate::keys::compile_glob("*.host").unwrap()),
+        };
+        assert_eq!(
+            keys(&glob, &mut connection),
+            vec!["cache.host", "db.primary.host"]
+        );
     }
 
     #[test]
-    fn test_history_items_empty() {
+    fn test_list_iter_pages_and_sorts() {
         let mut connection = establish_connection();
-        let key_id = "nonexistent_key_12345";
-        let namespace_id = "my_namespace";
+        let base = "list_iter_base_12345";
+        let leaf = "list_iter_leaf_12345";
+        let chain = vec![String::from(leaf), String::from(base)];
 
-        let result = Item::history(key_id, namespace_id, &mut connection);
-        assert!(result.is_ok());
-        assert!(result.unwrap().is_empty());
+        for namespace_id in [base, leaf] {
+            for key_id in ["a", "b", "c", "d"] {
+                Item::purge(key_id, namespace_id, &mut connection).unwrap();
+            }
+        }
+        Item::replace_into("c", "base", base, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("a", "base", base, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("d", "base", base, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("b", "leaf", leaf, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("b", "leaf", leaf, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("d", "leaf", leaf, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("a", "leaf", leaf, None, &Author::default(), &mut connection).unwrap();
+        Item::destroy("a", leaf, &Author::default(), &mut connection).unwrap();
+
+        let keys = |listed: Vec<ListedItem>| -> Vec<(String, String)> {
+            listed
+                .into_iter()
+                .map(|listed| (listed.item.key, listed.item.namespace))
+                .collect()
+        };
+
+        let mut query = ListQuery::default();
+        let first = ItemList::page(&mut connection, &chain, None, &query, 2).unwrap();
+        assert_eq!(first[1].versions, 2);
+        assert_eq!(
+            keys(first),
+            vec![
+                (String::from("a"), String::from(base)),
+                (String::from("b"), String::from(leaf)),
+            ]
+        );
+        query.after = Some(ListCursor {
+            key: String::from("b"),
+            updated_at: None,
+        });
+        let second = ItemList::page(&mut connection, &chain, None, &query, 2).unwrap();
+        assert_eq!(
+            keys(second),
+            vec![
+                (String::from("c"), String::from(base)),
+                (String::from("d"), String::from(leaf)),
+            ]
+        );
+
+        // Newest first: the tombstoned `a` falls back to its older base value
+        let newest_first = ListQuery {
+            sort: ListSort::UpdatedAt,
+            descending: true,
+            ..ListQuery::default()
+        };
+        let listed: Vec<String> =
+            ItemList::iter(&mut connection, &chain, KeyFilter::default(), newest_first)
+                .map(|listed| listed.unwrap().item.key)
+                .collect();
+        assert_eq!(listed, vec!["d", "b", "a", "c"]);
     }
 
     #[test]
-    fn test_destroy_item_not_found() {
+    fn test_item_list_iter_stops_at_deadline() {
         let mut connection = establish_connection();
-        let key_id = "nonexistent_delete_key_12345";
-        let namespace_id = "my_namespace";
+        let chain = vec![String::from("deadline_namespace_12345")];
 
-        let result = Item::destroy(key_id, namespace_id, &mut connection);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        let mut listed = ItemList::iter(
+            &mut connection,
+            &chain,
+            KeyFilter::default(),
+            ListQuery::default(),
+        )
+        .with_deadline(Instant::now());
+        assert!(matches!(listed.next(), Some(Err(AppError::Timeout(_)))));
+        assert!(listed.next().is_none());
     }
 
     #[test]
-    fn test_find_item_not_found() {
+    fn test_expired_items_are_hidden() {
         let mut connection = establish_connection();
-        let key_id = "nonexistent_find_key_12345";
-        let namespace_id = "my_namespace";
+        let namespace_id = "expiry_namespace_12345";
+        let key_id = "expiring_key_12345";
+        let chain = vec![String::from(namespace_id)];
+        let now = Utc::now();
 
-        let result = Item::find(key_id, namespace_id, &mut connection);
-        assert!(result.is_err());
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        Item::replace_into(
+            key_id,
+            "lease",
+            namespace_id,
+            Some(now - TimeDelta::seconds(1)),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Item::find(key_id, namespace_id, &mut connection),
+            Err(diesel::result::Error::NotFound)
+        ));
+        assert!(ItemList::list(&mut connection, namespace_id)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            ItemList::iter(
+                &mut connection,
+                &chain,
+                KeyFilter::default(),
+                ListQuery::default()
+            )
+            .count(),
+            0
+        );
+        assert_eq!(
+            Item::live_version(key_id, namespace_id, &mut connection).unwrap(),
+            0
+        );
+        // A key expiring later is visible now but not at a time past its expiry
+        Item::replace_into(
+            key_id,
+            "lease",
+            namespace_id,
+            Some(now + TimeDelta::hours(1)),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        let later = now + TimeDelta::hours(2);
+        assert!(Item::find(key_id, namespace_id, &mut connection).is_ok());
+        assert!(Item::find_as_of(key_id, namespace_id, later, &mut connection).is_err());
+        assert!(ItemList::list_as_of(&mut connection, namespace_id, later)
+            .unwrap()
+            .is_empty());
+
+        // Writing the key again without an expiry brings it back
+        Item::replace_into(
+            key_id,
+            "kept",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(
+            Item::find(key_id, namespace_id, &mut connection)
+                .unwrap()
+                .val,
+            "kept"
+        );
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
     }
 
     #[test]
-    fn test_replace_into_item_and_find() {
+    fn test_reap_expired() {
         let mut connection = establish_connection();
-        let key_id = "test_key_12345";
-        let value: &str = "test_value_12345";
-        let namespace_id = "test_namespace_12345";
+        let namespace_id = "reaper_namespace_12345";
+        let in_an_hour = Utc::now() + TimeDelta::hours(1);
 
-        let replace_result = Item::replace_into(key_id, value, namespace_id, &mut connection);
-        assert!(replace_result.is_ok());
+        for key_id in ["tombstoned", "purged", "rewritten", "forever"] {
+            Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        }
+        Item::replace_into(
+            "tombstoned",
+            "v",
+            namespace_id,
+            Some(in_an_hour),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "rewritten",
+            "v",
+            namespace_id,
+            Some(in_an_hour),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "rewritten",
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "forever",
+            "v",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
 
-        let find_result = Item::find(key_id, namespace_id, &mut connection);
-        assert!(find_result.is_ok());
+        let written_at = |key_id: &str, connection: &mut PgConnection| {
+            Item::find_version(key_id, namespace_id, 1, connection)
+                .unwrap()
+                .updated_at
+        };
+        let tombstoned_at = written_at("tombstoned", &mut connection);
+        let rewritten_at = written_at("rewritten", &mut connection);
 
-        let found_item = find_result.unwrap();
-        assert_eq!(found_item.key, key_id);
-        assert_eq!(found_item.val, value);
-        assert_eq!(found_item.namespace, namespace_id);
+        let later = in_an_hour + TimeDelta::seconds(1);
+        assert!(Item::reap_expired(later, false, &mut connection).unwrap() >= 1);
+
+        // Clearing expiries keeps the write times, so reads from before the reap still work
+        assert_eq!(written_at("tombstoned", &mut connection), tombstoned_at);
+        assert_eq!(written_at("rewritten", &mut connection), rewritten_at);
+        assert_eq!(
+            Item::find_as_of("tombstoned", namespace_id, tombstoned_at, &mut connection)
+                .unwrap()
+                .val,
+            "v"
+        );
+        assert_eq!(
+            Item::find_as_of("rewritten", namespace_id, rewritten_at, &mut connection)
+                .unwrap()
+                .val,
+            "v"
+        );
+        assert!(matches!(
+            Item::find_as_of("tombstoned", namespace_id, later, &mut connection),
+            Err(diesel::result::Error::NotFound)
+        ));
+        let history = Item::history("rewritten", namespace_id, &mut connection).unwrap();
+        assert!(history[0].updated_at >= history[1].updated_at);
+
+        let tombstone = Item::latest("tombstoned", namespace_id, &mut connection)
+            .unwrap()
+            .unwrap();
+        assert!(tombstone.deleted);
+        assert_eq!(tombstone.version, 2);
+        assert_eq!(tombstone.written_by.as_deref(), Some(REAPER_IDENTITY));
+        // Dated at the expiry, so the expiry of the reaped value is no longer needed
+        assert_eq!(
+            tombstone.updated_at.timestamp_micros(),
+            in_an_hour.timestamp_micros()
+        );
+        for key_id in ["tombstoned", "rewritten"] {
+            let reaped = Item::find_version(key_id, namespace_id, 1, &mut connection).unwrap();
+            assert_eq!(reaped.expires_at, None);
+        }
+        assert_eq!(
+            Item::current_version("rewritten", namespace_id, &mut connection).unwrap(),
+            2
+        );
+        assert_eq!(
+            Item::current_version("forever", namespace_id, &mut connection).unwrap(),
+            1
+        );
+        // Already tombstoned keys are not reaped twice
+        Item::reap_expired(later, false, &mut connection).unwrap();
+        assert_eq!(
+            Item::current_version("tombstoned", namespace_id, &mut connection).unwrap(),
+            2
+        );
+
+        Item::replace_into(
+            "purged",
+            "v",
+            namespace_id,
+            Some(in_an_hour),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::reap_expired(later, true, &mut connection).unwrap();
+        assert!(Item::latest("purged", namespace_id, &mut connection)
+            .unwrap()
+            .is_none());
     }
 
     #[test]
-    fn test_replace_into_multiple_values_and_history() {
+    fn test_find_many_and_write_batch() {
         let mut connection = establish_connection();
-        let key_id = "history_key_12345";
-        let value1 = "value1";
-        let value2 = "value2";
-        let namespace_id = "history_namespace_12345";
+        let namespace_id = "batch_namespace_12345";
+        let parent_id = "batch_parent_12345";
+        let chain = vec![String::from(namespace_id), String::from(parent_id)];
+        for (key_id, ns) in [
+            ("batch_a", namespace_id),
+            ("batch_b", namespace_id),
+            ("batch_c", namespace_id),
+            ("batch_b", parent_id),
+            ("batch_d", parent_id),
+        ] {
+            Item::purge(key_id, ns, &mut connection).unwrap();
+        }
 
-        // Insert first value
-        assert!(Item::replace_into(key_id, value1, namespace_id, &mut connection).is_ok());
+        let written = ItemList::write_batch(
+            &mut connection,
+            namespace_id,
+            &[
+                BatchWrite::Put {
+                    key: "batch_a",
+                    value: "1",
+                    expected_version: Some(0),
+                    expires_at: None,
+                },
+                BatchWrite::Put {
+                    key: "batch_c",
+                    value: "3",
+                    expected_version: None,
+                    expires_at: None,
+                },
+                BatchWrite::Delete { key: "batch_c" },
+            ],
+            &Author::default(),
+        )
+        .unwrap();
+        assert_eq!(written.len(), 3);
+        assert!(written[2].deleted);
+        Item::replace_into(
+            "batch_b",
+            "parent",
+            parent_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "batch_d",
+            "parent",
+            parent_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
 
-        // Insert second value (overwrites)
-        assert!(Item::replace_into(key_id, value2, namespace_id, &mut connection).is_ok());
+        let keys: Vec<String> = ["batch_d", "batch_a", "batch_b", "batch_c", "batch_missing"]
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        let found = ItemList::find_many(&mut connection, &chain, &keys, None).unwrap();
+        let found: Vec<(&str, &str, &str, i64)> = found
+            .iter()
+            .map(|listed| {
+                (
+                    listed.item.key.as_str(),
+                    listed.item.val.as_str(),
+                    listed.item.namespace.as_str(),
+                    listed.versions,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("batch_a", "1", namespace_id, 1),
+                ("batch_b", "parent", parent_id, 1),
+                ("batch_d", "parent", parent_id, 1),
+            ]
+        );
 
-        // Get current value
-        let find_result = Item::find(key_id, namespace_id, &mut connection);
-        assert!(find_result.is_ok());
-        assert_eq!(find_result.unwrap().val, value2);
+        // A failing write rolls back the writes before it
+        let failed = ItemList::write_batch(
+            &mut connection,
+            namespace_id,
+            &[
+                BatchWrite::Put {
+                    key: "batch_b",
+                    value: "2",
+                    expected_version: None,
+                    expires_at: None,
+                },
+                BatchWrite::Put {
+                    key: "batch_a",
+                    value: "stale",
+                    expected_version: Some(7),
+                    expires_at: None,
+                },
+            ],
+            &Author::default(),
+        );
+        assert!(matches!(failed, Err(AppError::Conflict(_))));
+        let failed = ItemList::write_batch(
+            &mut connection,
+            namespace_id,
+            &[
+                BatchWrite::Put {
+                    key: "batch_b",
+                    value: "2",
+                    expected_version: None,
+                    expires_at: None,
+                },
+                BatchWrite::Delete { key: "batch_c" },
+            ],
+            &Author::default(),
+        );
+        assert!(matches!(failed, Err(AppError::NotFound(_))));
+        assert_eq!(
+            Item::current_version("batch_b", namespace_id, &mut connection).unwrap(),
+            0
+        );
+        assert_eq!(
+            Item::find("batch_a", namespace_id, &mut connection)
+                .unwrap()
+                .val,
+            "1"
+        );
 
-        // Get history - should have 2 entries
-        let history_result = Item::history(key_id, namespace_id, &mut connection);
-        assert!(history_result.is_ok());
-        let history = history_result.unwrap();
-        assert!(history.len() >= 2);
-        // Most recent should be first (order_by updated_at desc)
-        assert_eq!(history[0].val, value2);
+        for (key_id, ns) in [
+            ("batch_a", namespace_id),
+            ("batch_c", namespace_id),
+            ("batch_b", parent_id),
+            ("batch_d", parent_id),
+        ] {
+            Item::purge(key_id, ns, &mut connection).unwrap();
+        }
     }
 
     #[test]
-    fn test_destroy_removes_all_versions() {
+    fn test_version_counts() {
         let mut connection = establish_connection();
-        let key_id = "destroy_key_12345";
-        let namespace_id = "destroy_namespace_12345";
+        let key1 = "count_key1_12345";
+        let key2 = "count_key2_12345";
+        let namespace_id = "version_count_namespace_12345";
 
-        // Insert multiple versions
-        assert!(Item::replace_into(key_id, "v1", namespace_id, &mut connection).is_ok());
-        assert!(Item::replace_into(key_id, "v2", namespace_id, &mut connection).is_ok());
+        Item::purge(key1, namespace_id, &mut connection).unwrap();
+        Item::purge(key2, namespace_id, &mut connection).unwrap();
 
-        // Verify we have history
-        let history_before = Item::history(key_id, namespace_id, &mut connection).unwrap();
-        assert!(history_before.len() >= 2);
+        assert!(Item::replace_into(
+            key1,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key1,
+            "v2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
+        assert!(Item::replace_into(
+            key2,
+            "v1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection
+        )
+        .is_ok());
 
-        // Destroy all
-        let destroy_result = Item::destroy(key_id, namespace_id, &mut connection);
-        assert!(destroy_result.is_ok());
-        assert!(destroy_result.unwrap() >= 2);
+        let count = Item::version_count(key1, namespace_id, &mut connection).unwrap();
+        assert_eq!(count, 2);
 
-        // Verify all are deleted
-        let history_after = Item::history(key_id, namespace_id, &mut connection).unwrap();
-        assert!(history_after.is_empty());
+        let counts = ItemList::version_counts(&mut connection, namespace_id).unwrap();
+        assert_eq!(counts.get(key1), Some(&2));
+        assert_eq!(counts.get(key2), Some(&1));
     }
 
     #[test]
-    fn test_namespace_isolation() {
+    fn test_audit() {
         let mut connection = establish_connection();
-        let key_id = "shared_key";
-        let ns1 = "namespace_1_12345";
-        let ns2 = "namespace_2_12345";
+        let namespace_id = "test_audit_ns";
+        let key_id = "test_audit_key";
+        let other_key = "test_audit_other";
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        Item::purge(other_key, namespace_id, &mut connection).unwrap();
 
-        // Insert same key in different namespaces
-        assert!(Item::replace_into(key_id, "value_ns1", ns1, &mut connection).is_ok());
-        assert!(Item::replace_into(key_id, "value_ns2", ns2, &mut connection).is_ok());
+        let author = Author {
+            written_by: Some(String::from("token:rotator")),
+            source_ip: Some(String::from("10.0.0.7")),
+            user_agent: Some(String::from("rotate.sh")),
+            comment: Some(String::from("quarterly rotation")),
+        };
+        Item::replace_into(key_id, "old", namespace_id, None, &author, &mut connection).unwrap();
+        Item::replace_into(
+            other_key,
+            "x",
+            namespace_id,
+            None,
+            &Author::named("psk-write"),
+            &mut connection,
+        )
+        .unwrap();
+        Item::destroy(key_id, namespace_id, &author, &mut connection)
+            .unwrap()
+            .unwrap();
 
-        // Verify they are isolated
-        let item_ns1 = Item::find(key_id, ns1, &mut connection).unwrap();
-        let item_ns2 = Item::find(key_id, ns2, &mut connection).unwrap();
+        let entries = Item::audit(
+            &AuditQuery {
+                namespace: Some(String::from(namespace_id)),
+                written_by: Some(String::from("token:rotator")),
+                ..AuditQuery::default()
+            },
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].deleted);
+        assert_eq!(entries[0].version, 2);
+        assert_eq!(entries[1].version, 1);
+        assert_eq!(entries[1].source_ip.as_deref(), Some("10.0.0.7"));
+        assert_eq!(entries[1].user_agent.as_deref(), Some("rotate.sh"));
+        assert_eq!(entries[1].comment.as_deref(), Some("quarterly rotation"));
 
-        assert_eq!(item_ns1.val, "value_ns1");
-        assert_eq!(item_ns2.val, "value_ns2");
-        assert_eq!(item_ns1.namespace, ns1);
-        assert_eq!(item_ns2.namespace, ns2);
+        let entries = Item::audit(
+            &AuditQuery {
+                namespace: Some(String::from(namespace_id)),
+                key: Some(String::from(other_key)),
+                ..AuditQuery::default()
+            },
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].written_by.as_deref(), Some("psk-write"));
+        assert_eq!(entries[0].comment, None);
+
+        let entries = Item::audit(
+            &AuditQuery {
+                namespace: Some(String::from(namespace_id)),
+                since: Some(Utc::now() + TimeDelta::hours(1)),
+                ..AuditQuery::default()
+            },
+            &mut connection,
+        )
+        .unwrap();
+        assert!(entries.is_empty());
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+        Item::purge(other_key, namespace_id, &mut connection).unwrap();
     }
 
     #[test]
-    fn test_list_returns_distinct_keys_with_latest_value() {
+    fn test_encrypted_values_and_rotation() {
+        use crate::schema::items::dsl::{items, key, namespace, version};
+
         let mut connection = establish_connection();
-        let key1 = "list_key1_12345";
-        let key2 = "list_key2_12345";
-        let namespace_id = "list_distinct_namespace_12345";
+        let namespace_id = "test_encryption_ns";
+        let key_id = "test_encryption_key";
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
 
-        // Insert multiple versions of key1
-        assert!(Item::replace_into(key1, "v1", namespace_id, &mut connection).is_ok());
-        assert!(Item::replace_into(key1, "v2", namespace_id, &mut connection).is_ok());
+        let key_1 = "1:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+        let key_2 = "2:AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=";
+        let old = Keyring::parse(key_1, None).unwrap();
+        let rotated = Keyring::parse(&format!("{},{}", key_1, key_2), None).unwrap();
+        let new = Keyring::parse(key_2, None).unwrap();
 
-        // Insert single version of key2
-        assert!(Item::replace_into(key2, "v3", namespace_id, &mut connection).is_ok());
+        for (value, item_version, keyring) in [("plain", 1, None), ("s3cret", 2, Some(&old))] {
+            let item = Item::insert_sealed(
+                NewItem {
+                    key: key_id,
+                    val: value,
+                    updated_at: &Utc::now(),
+                    namespace: namespace_id,
+                    version: item_version,
+                    deleted: false,
+                    expires_at: None,
+                    author: Author::default(),
+                },
+                keyring,
+                &mut connection,
+            );
+            assert_eq!(item.unwrap().val, value);
+        }
 
-        // List should return both keys with latest values
-        let list_result = ItemList::list(&mut connection, namespace_id);
-        assert!(list_result.is_ok());
+        let stored = |connection: &mut PgConnection| {
+            items
+                .filter(namespace.eq(namespace_id))
+                .filter(key.eq(key_id))
+                .order(version.asc())
+                .load::<StoredItem>(connection)
+                .unwrap()
+        };
+        let rows = stored(&mut connection);
+        assert_eq!(rows[0].key_version, None);
+        assert_eq!(rows[1].key_version, Some(1));
+        assert!(!rows[1].val.contains("s3cret"));
+        let written_at: Vec<DateTime<Utc>> = rows.iter().map(|row| row.updated_at).collect();
 
-        let items = list_result.unwrap();
-        let item1 = items.iter().find(|item| item.key == key1);
-        let item2 = items.iter().find(|item| item.key == key2);
+        assert_eq!(
+            ItemList::reencrypt(&mut connection, &rotated, Some(namespace_id), true).unwrap(),
+            2
+        );
+        assert_eq!(stored(&mut connection)[1].key_version, Some(1));
+        assert_eq!(
+            ItemList::reencrypt(&mut connection, &rotated, Some(namespace_id), false).unwrap(),
+            2
+        );
+        assert_eq!(
+            ItemList::reencrypt(&mut connection, &rotated, Some(namespace_id), false).unwrap(),
+            0
+        );
 
-        assert!(item1.is_some());
-        assert!(item2.is_some());
+        assert!(stored(&mut connection).remove(0).open(None).is_err());
+        let values: Vec<String> = stored(&mut connection)
+            .into_iter()
+            .map(|row| {
+                assert_eq!(row.key_version, Some(2));
+                row.open(Some(&new)).unwrap().val
+            })
+            .collect();
+        assert_eq!(values, vec!["plain", "s3cret"]);
+        // Re-encrypting is not a write, so the versions keep their write times
+        let rotated_at: Vec<DateTime<Utc>> = stored(&mut connection)
+            .iter()
+            .map(|row| row.updated_at)
+            .collect();
+        assert_eq!(rotated_at, written_at);
 
-        assert_eq!(item1.unwrap().val, "v2");
-        assert_eq!(item2.unwrap().val, "v3");
+        // Rows sealed with a key the keyring lacks stop the rotation
+        assert!(matches!(
+            ItemList::reencrypt(&mut connection, &old, Some(namespace_id), false),
+            Err(AppError::Conflict(_))
+        ));
+
+        Item::purge(key_id, namespace_id, &mut connection).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_append_and_find() {
+        use crate::crypto::with_keyring;
+        use crate::schema::items::dsl::{items, key, namespace, val};
+
+        let mut connection = establish_connection();
+        let namespace_id = "test_sealed_ns";
+        let other_namespace = "test_sealed_other_ns";
+        let key_id = "test_sealed_key";
+        for ns in [namespace_id, other_namespace] {
+            Item::purge(key_id, ns, &mut connection).unwrap();
+        }
+        let keyring =
+            Keyring::parse("1:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=", None).unwrap();
+
+        with_keyring(keyring, || {
+            for (value, ns) in [("s3cret", namespace_id), ("other", other_namespace)] {
+                Item::replace_into(key_id, value, ns, None, &Author::default(), &mut connection)
+                    .unwrap();
+            }
+            assert_eq!(
+                Item::find(key_id, namespace_id, &mut connection)
+                    .unwrap()
+                    .val,
+                "s3cret"
+            );
+
+            let stored = items
+                .filter(namespace.eq(namespace_id))
+                .filter(key.eq(key_id))
+                .first::<StoredItem>(&mut connection)
+                .unwrap();
+            assert_eq!(stored.key_version, Some(1));
+            assert!(!stored.val.contains("s3cret"));
+
+            // A sealed value copied into the same key and version of another namespace
+            // does not open there
+            diesel::update(
+                items
+                    .filter(namespace.eq(other_namespace))
+                    .filter(key.eq(key_id)),
+            )
+            .set(val.eq(&stored.val))
+            .execute(&mut connection)
+            .unwrap();
+            assert!(Item::find(key_id, other_namespace, &mut connection).is_err());
+        });
+
+        for ns in [namespace_id, other_namespace] {
+            Item::purge(key_id, ns, &mut connection).unwrap();
+        }
     }
 }
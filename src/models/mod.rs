@@ -1 +1,3 @@
 pub mod item;
+pub mod namespace;
+pub mod token;
@@ -0,0 +1,892 @@
+use crate::crypto::keyring;
+use crate::diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use crate::diff::{diff_maps, MapDiff};
+use crate::error::AppError;
+use crate::models::item::{Author, Item, ItemList};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use diesel::pg::PgConnection;
+use diesel::result::DatabaseErrorKind;
+use diesel::sql_types::{BigInt, Nullable, Text, Timestamptz};
+use diesel::{Connection, OptionalExtension};
+
+// Longest parent chain a lookup follows, counting the namespace itself
+pub const MAX_INHERITANCE_DEPTH: usize = 8;
+
+// Held until the end of the transaction, so renames involving namespace $1 run one at a time
+const LOCK_NAMESPACE_SQL: &str = "SELECT pg_advisory_xact_lock(hashtext($1))";
+
+// Namespaces are implicit: one exists as long as it has at least one row, tombstones included
+#[derive(QueryableByName, Serialize)]
+pub struct Namespace {
+    #[diesel(sql_type = Text)]
+    pub namespace: String,
+    // Live (not deleted or expired) keys
+    #[diesel(sql_type = BigInt)]
+    pub keys: i64,
+    // Stored versions of all keys, tombstones included
+    #[diesel(sql_type = BigInt)]
+    pub rows: i64,
+    #[diesel(sql_type = Timestamptz)]
+    pub last_modified: DateTime<Utc>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub parent: Option<String>,
+}
+
+const NAMESPACE_STATS_SQL: &str = "
+SELECT latest.namespace,
+       COUNT(*) FILTER (WHERE NOT deleted AND (expires_at IS NULL OR expires_at > NOW())) AS keys,
+       SUM(versions)::BIGINT AS rows,
+       MAX(last_modified) AS last_modified,
+       namespace_parents.parent
+FROM (
+    SELECT DISTINCT ON (namespace, key)
+           namespace,
+           deleted,
+           expires_at,
+           COUNT(*) OVER (PARTITION BY namespace, key) AS versions,
+           MAX(updated_at) OVER (PARTITION BY namespace, key) AS last_modified
+    FROM items
+    WHERE $1::TEXT IS NULL OR namespace = $1
+    ORDER BY namespace, key, version DESC
+) AS latest
+LEFT JOIN namespace_parents ON namespace_parents.namespace = latest.namespace
+GROUP BY latest.namespace, namespace_parents.parent
+ORDER BY latest.namespace";
+
+impl Namespace {
+    pub fn list(connection: &mut PgConnection) -> Result<Vec<Namespace>, diesel::result::Error> {
+        diesel::sql_query(NAMESPACE_STATS_SQL)
+            .bind::<Nullable<Text>, _>(None::<String>)
+            .load::<Namespace>(connection)
+    }
+
+    pub fn find(
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Namespace, diesel::result::Error> {
+        diesel::sql_query(NAMESPACE_STATS_SQL)
+            .bind::<Nullable<Text>, _>(Some(namespace_id))
+            .get_result::<Namespace>(connection)
+    }
+
+    pub fn parent(
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Option<String>, diesel::result::Error> {
+        use crate::schema::namespace_parents::dsl::{namespace, namespace_parents, parent};
+
+        namespace_parents
+            .filter(namespace.eq(namespace_id))
+            .select(parent)
+            .first(connection)
+            .optional()
+    }
+
+    // The namespace followed by its ancestors, nearest first
+    pub fn chain(
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Vec<String>, diesel::result::Error> {
+        let mut chain = vec![String::from(namespace_id)];
+        while chain.len() < MAX_INHERITANCE_DEPTH {
+            match Namespace::parent(chain.last().unwrap(), connection)? {
+                // `set_parent` rejects cycles, but never loop on one
+                Some(parent) if !chain.contains(&parent) => chain.push(parent),
+                _ => break,
+            }
+        }
+        Ok(chain)
+    }
+
+    pub fn set_parent(
+        namespace_id: &str,
+        parent_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Vec<String>, AppError> {
+        use crate::schema::namespace_parents::dsl::{
+            namespace, namespace_parents, parent, updated_at,
+        };
+
+        if parent_id.is_empty() {
+            return Err(AppError::Validation(String::from("parent is required")));
+        }
+
+        connection.transaction(|connection| {
+            let parent_chain = Namespace::chain(parent_id, connection)?;
+            if parent_chain.iter().any(|ancestor| ancestor == namespace_id) {
+                return Err(AppError::Validation(format!(
+                    "'{}' cannot inherit from '{}': that would create a cycle",
+                    namespace_id, parent_id
+                )));
+            }
+            if parent_chain.len() >= MAX_INHERITANCE_DEPTH {
+                return Err(AppError::Validation(format!(
+                    "Inheritance chains are limited to {} namespaces",
+                    MAX_INHERITANCE_DEPTH
+                )));
+            }
+
+            diesel::insert_into(namespace_parents)
+                .values((
+                    namespace.eq(namespace_id),
+                    parent.eq(parent_id),
+                    updated_at.eq(Utc::now()),
+                ))
+                .on_conflict(namespace)
+                .do_update()
+                .set((parent.eq(parent_id), updated_at.eq(Utc::now())))
+                .execute(connection)?;
+
+            Ok(Namespace::chain(namespace_id, connection)?)
+        })
+    }
+
+    // Returns false if the namespace had no parent
+    pub fn remove_parent(
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        use crate::schema::namespace_parents::dsl::{namespace, namespace_parents};
+
+        let removed = diesel::delete(namespace_parents.filter(namespace.eq(namespace_id)))
+            .execute(connection)?;
+        Ok(removed > 0)
+    }
+
+    // Moves every row, history included, to a namespace that must not exist yet, either with
+    // rows or with a parent of its own
+    pub fn rename(
+        namespace_id: &str,
+        new_namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<usize, AppError> {
+        use crate::schema::items::dsl::{items, namespace};
+
+        if namespace_id == new_namespace_id {
+            return Err(AppError::Validation(String::from(
+                "New namespace must differ from the current one",
+            )));
+        }
+
+        connection.transaction(|connection| {
+            // Otherwise two renames into the same name could both find it free. Locked in
+            // name order, so renames in opposite directions do not deadlock.
+            let mut locked = [namespace_id, new_namespace_id];
+            locked.sort();
+            for namespace_id in locked {
+                diesel::sql_query(LOCK_NAMESPACE_SQL)
+                    .bind::<Text, _>(namespace_id)
+                    .execute(connection)?;
+            }
+
+            let row_count = |namespace_id: &str, connection: &mut PgConnection| {
+                items
+                    .filter(namespace.eq(namespace_id))
+                    .count()
+                    .get_result::<i64>(connection)
+            };
+
+            if row_count(namespace_id, connection)? == 0 {
+                return Err(AppError::NotFound(format!(
+                    "Namespace '{}' not found",
+                    namespace_id
+                )));
+            }
+            if row_count(new_namespace_id, connection)? > 0 {
+                return Err(AppError::Conflict(format!(
+                    "Namespace '{}' already exists",
+                    new_namespace_id
+                )));
+            }
+            if Namespace::parent(new_namespace_id, connection)?.is_some() {
+                return Err(AppError::Conflict(format!(
+                    "Namespace '{}' already has a parent",
+                    new_namespace_id
+                )));
+            }
+            if Namespace::parent(namespace_id, connection)?.as_deref() == Some(new_namespace_id) {
+                return Err(AppError::Validation(format!(
+                    "'{}' cannot be renamed to its own parent",
+                    namespace_id
+                )));
+            }
+
+            // Sealed values are bound to their namespace
+            ItemList::reseal(connection, keyring(), namespace_id, new_namespace_id)?;
+            // Writers do not take the lock, so one may still have created a key under the
+            // new name
+            let moved = diesel::update(items.filter(namespace.eq(namespace_id)))
+                .set(namespace.eq(new_namespace_id))
+                .execute(connection)
+                .map_err(|e| match e {
+                    diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                        AppError::Conflict(format!(
+                            "Namespace '{}' already exists",
+                            new_namespace_id
+                        ))
+                    }
+                    e => AppError::from(e),
+                })?;
+
+            // Inheritance follows the namespace to its new name
+            {
+                use crate::schema::namespace_parents::dsl::{
+                    namespace as child, namespace_parents, parent,
+                };
+                diesel::update(namespace_parents.filter(child.eq(namespace_id)))
+                    .set(child.eq(new_namespace_id))
+                    .execute(connection)?;
+                diesel::update(namespace_parents.filter(parent.eq(namespace_id)))
+                    .set(parent.eq(new_namespace_id))
+                    .execute(connection)?;
+            }
+
+            Ok(moved)
+        })
+    }
+
+    // Tombstones every live key in one transaction and returns the deleted keys
+    pub fn destroy(
+        namespace_id: &str,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<Vec<String>, diesel::result::Error> {
+        connection.transaction(|connection| {
+            let mut deleted = Vec::new();
+            for item in ItemList::list(connection, namespace_id)? {
+                if Item::destroy(&item.key, namespace_id, author, connection)?.is_some() {
+                    deleted.push(item.key);
+                }
+            }
+            Ok(deleted)
+        })
+    }
+
+    // Copies the current values of `namespace_id` (optionally only keys starting with `prefix`)
+    // into `target_namespace_id` in one transaction, each with its expiry. Keys that exist only
+    // in the target are kept. Returns the changes to the target; with `dry_run` nothing is
+    // written.
+    pub fn promote(
+        namespace_id: &str,
+        target_namespace_id: &str,
+        prefix: Option<&str>,
+        dry_run: bool,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<MapDiff, AppError> {
+        if namespace_id == target_namespace_id {
+            return Err(AppError::Validation(String::from(
+                "Target namespace must differ from the source",
+            )));
+        }
+
+        connection.transaction(|connection| {
+            let matches_prefix = |key: &str| prefix.is_none_or(|prefix| key.starts_with(prefix));
+
+            let source_items: Vec<Item> = ItemList::list(connection, namespace_id)?
+                .into_iter()
+                .filter(|item| matches_prefix(&item.key))
+                .collect();
+            let source: HashMap<String, String> = source_items
+                .iter()
+                .map(|item| (item.key.clone(), item.val.clone()))
+                .collect();
+            let source_expiry: HashMap<&str, DateTime<Utc>> = source_items
+                .iter()
+                .filter_map(|item| Some((item.key.as_str(), item.expires_at?)))
+                .collect();
+            let target: HashMap<String, String> = ItemList::list(connection, target_namespace_id)?
+                .into_iter()
+                .filter(|item| source.contains_key(&item.key))
+                .map(|item| (item.key, item.val))
+                .collect();
+
+            let map_diff = diff_maps(&target, &source);
+            if !dry_run {
+                for key_change in map_diff.added.iter().chain(map_diff.changed.iter()) {
+                    if let Some(value) = &key_change.to {
+                        Item::replace_into(
+                            &key_change.key,
+                            value,
+                            target_namespace_id,
+                            source_expiry.get(key_change.key.as_str()).copied(),
+                            author,
+                            connection,
+                        )?;
+                    }
+                }
+            }
+
+            Ok(map_diff)
+        })
+    }
+
+    // Writes `entries` into the namespace in one transaction, skipping unchanged values. With
+    // `replace`, live keys missing from `entries` are deleted; otherwise they are kept.
+    // `dry_run` only reports what would change.
+    pub fn import(
+        namespace_id: &str,
+        entries: &HashMap<String, String>,
+        replace: bool,
+        dry_run: bool,
+        author: &Author,
+        connection: &mut PgConnection,
+    ) -> Result<MapDiff, AppError> {
+        connection.transaction(|connection| {
+            let current: HashMap<String, String> = ItemList::list(connection, namespace_id)?
+                .into_iter()
+                .filter(|item| replace || entries.contains_key(&item.key))
+                .map(|item| (item.key, item.val))
+                .collect();
+
+            let map_diff = diff_maps(&current, entries);
+            if !dry_run {
+                for key_change in map_diff.added.iter().chain(map_diff.changed.iter()) {
+                    if let Some(value) = &key_change.to {
+                        Item::replace_into(
+                            &key_change.key,
+                            value,
+                            namespace_id,
+                            None,
+                            author,
+                            connection,
+                        )?;
+                    }
+                }
+                for key_change in map_diff.removed.iter() {
+                    Item::destroy(&key_change.key, namespace_id, author, connection)?;
+                }
+            }
+
+            Ok(map_diff)
+        })
+    }
+
+    // Permanently removes every row in the namespace
+    pub fn purge(
+        namespace_id: &str,
+        connection: &mut PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        use crate::schema::items::dsl::{items, namespace};
+
+        connection.transaction(|connection| {
+            Namespace::remove_parent(namespace_id, connection)?;
+            diesel::delete(items.filter(namespace.eq(namespace_id))).execute(connection)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::get_database;
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn establish_connection() -> PgConnection {
+        let database_url = get_database();
+        PgConnection::establish(&database_url).expect("Failed to connect to database")
+    }
+
+    #[test]
+    fn test_list_and_find_namespace() {
+        let mut connection = establish_connection();
+        let namespace_id = "namespace_stats_12345";
+
+        Namespace::purge(namespace_id, &mut connection).unwrap();
+        Item::replace_into(
+            "a",
+            "1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "a",
+            "2",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "b",
+            "1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "c",
+            "1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::destroy("c", namespace_id, &Author::default(), &mut connection).unwrap();
+
+        let namespace = Namespace::find(namespace_id, &mut connection).unwrap();
+        assert_eq!(namespace.namespace, namespace_id);
+        assert_eq!(namespace.keys, 2);
+        assert_eq!(namespace.rows, 5);
+
+        let namespaces = Namespace::list(&mut connection).unwrap();
+        assert!(namespaces
+            .iter()
+            .any(|namespace| namespace.namespace == namespace_id && namespace.keys == 2));
+
+        assert!(matches!(
+            Namespace::find("namespace_missing_12345", &mut connection),
+            Err(diesel::result::Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_rename_namespace() {
+        let mut connection = establish_connection();
+        let from = "namespace_rename_from_12345";
+        let to = "namespace_rename_to_12345";
+        let taken = "namespace_rename_taken_12345";
+        let orphan = "namespace_rename_orphan_12345";
+        let parent = "namespace_rename_parent_12345";
+
+        for namespace_id in [from, to, taken, orphan] {
+            Namespace::purge(namespace_id, &mut connection).unwrap();
+        }
+        Item::replace_into("a", "1", from, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("a", "2", from, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("b", "1", taken, None, &Author::default(), &mut connection).unwrap();
+
+        assert!(matches!(
+            Namespace::rename(from, taken, &mut connection),
+            Err(AppError::Conflict(_))
+        ));
+
+        let written_at: Vec<DateTime<Utc>> = Item::history("a", from, &mut connection)
+            .unwrap()
+            .iter()
+            .map(|item| item.updated_at)
+            .collect();
+        assert_eq!(Namespace::rename(from, to, &mut connection).unwrap(), 2);
+        assert_eq!(Item::find("a", to, &mut connection).unwrap().val, "2");
+        // Moving rows is not a write, so history and reads as of a time are unchanged
+        let history = Item::history("a", to, &mut connection).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|item| item.updated_at)
+                .collect::<Vec<_>>(),
+            written_at
+        );
+        assert_eq!(
+            Item::find_as_of("a", to, written_at[1], &mut connection)
+                .unwrap()
+                .val,
+            "1"
+        );
+        assert!(Item::find("a", from, &mut connection).is_err());
+
+        assert!(matches!(
+            Namespace::rename(from, to, &mut connection),
+            Err(AppError::NotFound(_))
+        ));
+
+        // A namespace without rows but with a parent of its own is taken too
+        Item::replace_into("a", "1", from, None, &Author::default(), &mut connection).unwrap();
+        Namespace::set_parent(orphan, taken, &mut connection).unwrap();
+        assert!(matches!(
+            Namespace::rename(from, orphan, &mut connection),
+            Err(AppError::Conflict(_))
+        ));
+
+        Namespace::set_parent(from, parent, &mut connection).unwrap();
+        assert!(matches!(
+            Namespace::rename(from, parent, &mut connection),
+            Err(AppError::Validation(_))
+        ));
+
+        for namespace_id in [from, orphan] {
+            Namespace::purge(namespace_id, &mut connection).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rename_namespace_reseals_values() {
+        use crate::crypto::{with_keyring, Keyring};
+
+        let mut connection = establish_connection();
+        let from = "namespace_reseal_from_12345";
+        let to = "namespace_reseal_to_12345";
+        for namespace_id in [from, to] {
+            Namespace::purge(namespace_id, &mut connection).unwrap();
+        }
+        let keyring =
+            Keyring::parse("1:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=", None).unwrap();
+
+        with_keyring(keyring, || {
+            Item::replace_into("a", "1", from, None, &Author::default(), &mut connection).unwrap();
+            Item::replace_into("a", "2", from, None, &Author::default(), &mut connection).unwrap();
+
+            assert_eq!(Namespace::rename(from, to, &mut connection).unwrap(), 2);
+            let history = Item::history("a", to, &mut connection).unwrap();
+            assert_eq!(history.len(), 2);
+            assert_eq!(Item::find("a", to, &mut connection).unwrap().val, "2");
+        });
+
+        // Without the keys the values could not be re-sealed, so the rename is refused
+        assert!(matches!(
+            Namespace::rename(to, from, &mut connection),
+            Err(AppError::Conflict(_))
+        ));
+
+        Namespace::purge(to, &mut connection).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_renames_into_one_namespace() {
+        let mut connection = establish_connection();
+        let first = "namespace_race_first_12345";
+        let second = "namespace_race_second_12345";
+        let target = "namespace_race_target_12345";
+        for namespace_id in [first, second, target] {
+            Namespace::purge(namespace_id, &mut connection).unwrap();
+        }
+        Item::replace_into("a", "1", first, None, &Author::default(), &mut connection).unwrap();
+        Item::replace_into("b", "1", second, None, &Author::default(), &mut connection).unwrap();
+
+        // The first rename holds its transaction open while the second one starts
+        let (started, wait_for_start) = std::sync::mpsc::channel();
+        let slow_rename = std::thread::spawn(move || {
+            let mut connection = establish_connection();
+            connection.transaction(|connection| {
+                Namespace::rename(first, target, connection)?;
+                started.send(()).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                Ok::<(), AppError>(())
+            })
+        });
+        wait_for_start.recv().unwrap();
+        let second_rename = Namespace::rename(second, target, &mut connection);
+        slow_rename.join().unwrap().unwrap();
+
+        assert!(matches!(second_rename, Err(AppError::Conflict(_))));
+        assert!(Item::find("b", second, &mut connection).is_ok());
+
+        for namespace_id in [second, target] {
+            Namespace::purge(namespace_id, &mut connection).unwrap();
+        }
+    }
+    #[test]
+    fn test_promote_namespace() {
+        let mut connection = establish_connection();
+        let source = "namespace_promote_source_12345";
+        let target = "namespace_promote_target_12345";
+        let in_an_hour = Utc::now() + TimeDelta::hours(1);
+
+        for namespace_id in [source, target] {
+            Namespace::purge(namespace_id, &mut connection).unwrap();
+        }
+        Item::replace_into(
+            "db.host",
+            "new-host",
+            source,
+            Some(in_an_hour),
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "db.port",
+            "5432",
+            source,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "feature",
+            "on",
+            source,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "db.host",
+            "old-host",
+            target,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "db.port",
+            "5432",
+            target,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "target_only",
+            "x",
+            target,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+
+        let dry_run = Namespace::promote(
+            source,
+            target,
+            None,
+            true,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(dry_run.added.len(), 1);
+        assert_eq!(dry_run.added[0].key, "feature");
+        assert_eq!(dry_run.changed.len(), 1);
+        assert_eq!(dry_run.changed[0].key, "db.host");
+        assert!(dry_run.removed.is_empty());
+        assert_eq!(
+            Item::find("db.host", target, &mut connection).unwrap().val,
+            "old-host"
+        );
+
+        let promoted = Namespace::promote(
+            source,
+            target,
+            Some("db."),
+            false,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert!(promoted.added.is_empty());
+        assert_eq!(promoted.changed.len(), 1);
+        let promoted_host = Item::find("db.host", target, &mut connection).unwrap();
+        assert_eq!(promoted_host.val, "new-host");
+        assert_eq!(
+            promoted_host.expires_at.map(|at| at.timestamp_micros()),
+            Some(in_an_hour.timestamp_micros())
+        );
+        // Unchanged values do not get a new version, and keys outside the prefix are not copied
+        assert_eq!(
+            Item::version_count("db.port", target, &mut connection).unwrap(),
+            1
+        );
+        assert!(Item::find("feature", target, &mut connection).is_err());
+        assert_eq!(
+            Item::find("target_only", target, &mut connection)
+                .unwrap()
+                .val,
+            "x"
+        );
+
+        assert!(matches!(
+            Namespace::promote(
+                source,
+                source,
+                None,
+                false,
+                &Author::default(),
+                &mut connection
+            ),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_import_namespace() {
+        let mut connection = establish_connection();
+        let namespace_id = "namespace_import_12345";
+
+        Namespace::purge(namespace_id, &mut connection).unwrap();
+        Item::replace_into(
+            "DB_HOST",
+            "old-host",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "DB_PORT",
+            "5432",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "LEGACY",
+            "x",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+
+        let entries: HashMap<String, String> = [
+            ("DB_HOST", "new-host"),
+            ("DB_PORT", "5432"),
+            ("API_KEY", "secret"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let merged = Namespace::import(
+            namespace_id,
+            &entries,
+            false,
+            false,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(merged.added.len(), 1);
+        assert_eq!(merged.changed.len(), 1);
+        assert!(merged.removed.is_empty());
+        assert_eq!(
+            Item::find("DB_HOST", namespace_id, &mut connection)
+                .unwrap()
+                .val,
+            "new-host"
+        );
+        assert_eq!(
+            Item::version_count("DB_PORT", namespace_id, &mut connection).unwrap(),
+            1
+        );
+        assert!(Item::find("LEGACY", namespace_id, &mut connection).is_ok());
+
+        let dry_run = Namespace::import(
+            namespace_id,
+            &entries,
+            true,
+            true,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert_eq!(dry_run.removed.len(), 1);
+        assert_eq!(dry_run.removed[0].key, "LEGACY");
+        assert!(Item::find("LEGACY", namespace_id, &mut connection).is_ok());
+
+        let replaced = Namespace::import(
+            namespace_id,
+            &entries,
+            true,
+            false,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        assert!(replaced.added.is_empty() && replaced.changed.is_empty());
+        assert_eq!(replaced.removed.len(), 1);
+        assert!(Item::find("LEGACY", namespace_id, &mut connection).is_err());
+
+        Namespace::purge(namespace_id, &mut connection).unwrap();
+    }
+
+    #[test]
+    fn test_namespace_parent_chain() {
+        let mut connection = establish_connection();
+        let base = "namespace_chain_base_12345";
+        let middle = "namespace_chain_middle_12345";
+        let leaf = "namespace_chain_leaf_12345";
+
+        for namespace_id in [base, middle, leaf] {
+            Namespace::remove_parent(namespace_id, &mut connection).unwrap();
+        }
+
+        assert_eq!(Namespace::chain(leaf, &mut connection).unwrap(), vec![leaf]);
+
+        Namespace::set_parent(middle, base, &mut connection).unwrap();
+        let chain = Namespace::set_parent(leaf, middle, &mut connection).unwrap();
+        assert_eq!(chain, vec![leaf, middle, base]);
+        assert_eq!(
+            Namespace::parent(leaf, &mut connection).unwrap().as_deref(),
+            Some(middle)
+        );
+
+        assert!(matches!(
+            Namespace::set_parent(base, leaf, &mut connection),
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            Namespace::set_parent(base, base, &mut connection),
+            Err(AppError::Validation(_))
+        ));
+
+        assert!(Namespace::remove_parent(middle, &mut connection).unwrap());
+        assert!(!Namespace::remove_parent(middle, &mut connection).unwrap());
+        assert_eq!(
+            Namespace::chain(leaf, &mut connection).unwrap(),
+            vec![leaf, middle]
+        );
+
+        Namespace::remove_parent(leaf, &mut connection).unwrap();
+    }
+
+    #[test]
+    fn test_destroy_namespace() {
+        let mut connection = establish_connection();
+        let namespace_id = "namespace_destroy_12345";
+
+        Namespace::purge(namespace_id, &mut connection).unwrap();
+        Item::replace_into(
+            "a",
+            "1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+        Item::replace_into(
+            "b",
+            "1",
+            namespace_id,
+            None,
+            &Author::default(),
+            &mut connection,
+        )
+        .unwrap();
+
+        let deleted =
+            Namespace::destroy(namespace_id, &Author::default(), &mut connection).unwrap();
+        assert_eq!(deleted, vec!["a", "b"]);
+        assert!(ItemList::list(&mut connection, namespace_id)
+            .unwrap()
+            .is_empty());
+
+        // History is kept until the namespace is purged
+        let namespace = Namespace::find(namespace_id, &mut connection).unwrap();
+        assert_eq!(namespace.keys, 0);
+        assert_eq!(namespace.rows, 4);
+
+        assert_eq!(Namespace::purge(namespace_id, &mut connection).unwrap(), 4);
+        assert!(Namespace::find(namespace_id, &mut connection).is_err());
+    }
+}
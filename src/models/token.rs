@@ -0,0 +1,305 @@
+use crate::diesel::{ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl};
+use crate::error::AppError;
+use crate::keys::compile_glob;
+use crate::schema::tokens;
+use crate::util::PSKType;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use diesel::dsl::exists;
+use diesel::pg::PgConnection;
+use diesel::result::DatabaseErrorKind;
+use diesel::OptionalExtension;
+
+// Prefix of every issued secret, so leaked tokens are easy to spot
+pub const TOKEN_PREFIX: &str = "llt_";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    Read,
+    Write,
+    Delete,
+    Admin,
+}
+
+impl Scope {
+    pub fn parse(name: &str) -> Option<Scope> {
+        match name {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "delete" => Some(Scope::Delete),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Delete => "delete",
+            Scope::Admin => "admin",
+        }
+    }
+
+    // The environment PSK that also grants this scope
+    pub fn psk_type(&self) -> PSKType {
+        match self {
+            Scope::Read => PSKType::READ,
+            Scope::Write | Scope::Delete => PSKType::WRITE,
+            Scope::Admin => PSKType::ADMIN,
+        }
+    }
+}
+
+// Only the SHA-256 of the secret is stored; the secret itself is shown once, on creation
+#[derive(Clone, Queryable, Serialize)]
+#[diesel(table_name = tokens)]
+pub struct Token {
+    pub id: i64,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    // Globs of the namespaces the scopes apply to
+    pub namespaces: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = tokens)]
+struct NewToken<'a> {
+    name: &'a str,
+    token_hash: &'a str,
+    scopes: &'a [String],
+    namespaces: &'a [String],
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl Token {
+    pub fn hash_secret(secret: &str) -> String {
+        hex::encode(Sha256::digest(secret.as_bytes()))
+    }
+
+    // Not revoked and not expired at `at`
+    pub fn is_active(&self, at: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > at)
+    }
+
+    // `admin` grants every other scope
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes
+            .iter()
+            .any(|name| name == scope.name() || name == Scope::Admin.name())
+    }
+
+    // Issues a token and returns it with its secret
+    pub fn create(
+        name: &str,
+        scopes: &[String],
+        namespaces: &[String],
+        expires_at: Option<DateTime<Utc>>,
+        connection: &mut PgConnection,
+    ) -> Result<(Token, String), AppError> {
+        if name.is_empty() {
+            return Err(AppError::Validation(String::from("name is required")));
+        }
+        if scopes.is_empty() {
+            return Err(AppError::Validation(String::from(
+                "At least one scope is required",
+            )));
+        }
+        if let Some(scope) = scopes.iter().find(|scope| Scope::parse(scope).is_none()) {
+            return Err(AppError::Validation(format!(
+                "Invalid scope '{}', expected read, write, delete or admin",
+                scope
+            )));
+        }
+        if namespaces.is_empty() {
+            return Err(AppError::Validation(String::from(
+                "At least one namespace pattern is required",
+            )));
+        }
+        if let Some(pattern) = namespaces
+            .iter()
+            .find(|pattern| pattern.is_empty() || compile_glob(pattern).is_err())
+        {
+            return Err(AppError::Validation(format!(
+                "Invalid namespace pattern '{}'",
+                pattern
+            )));
+        }
+
+        let secret = format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            hex::encode(rand::random::<[u8; 32]>())
+        );
+        let token = diesel::insert_into(tokens::table)
+            .values(&NewToken {
+                name,
+                token_hash: &Token::hash_secret(&secret),
+                scopes,
+                namespaces,
+                expires_at,
+            })
+            .get_result::<Token>(connection)
+            .map_err(|e| match e {
+                diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    AppError::Conflict(format!("Token '{}' already exists", name))
+                }
+                e => AppError::from(e),
+            })?;
+
+        Ok((token, secret))
+    }
+
+    pub fn find(token_id: i64, connection: &mut PgConnection) -> Result<Token, AppError> {
+        tokens::table
+            .find(token_id)
+            .first::<Token>(connection)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("Token {} not found", token_id)))
+    }
+
+    // Revoked and expired tokens are returned too
+    pub fn find_by_hash(
+        token_hash: &str,
+        connection: &mut PgConnection,
+    ) -> Result<Option<Token>, diesel::result::Error> {
+        tokens::table
+            .filter(tokens::token_hash.eq(token_hash))
+            .first::<Token>(connection)
+            .optional()
+    }
+
+    pub fn list(connection: &mut PgConnection) -> Result<Vec<Token>, diesel::result::Error> {
+        tokens::table
+            .order(tokens::id.asc())
+            .load::<Token>(connection)
+    }
+
+    // Whether a token was ever issued; revoking or expiring them all keeps that true
+    pub fn any_issued(connection: &mut PgConnection) -> Result<bool, diesel::result::Error> {
+        diesel::select(exists(tokens::table.select(tokens::id))).get_result(connection)
+    }
+
+    // Sets when the token stops working; None expires it now
+    pub fn expire(
+        token_id: i64,
+        expires_at: Option<DateTime<Utc>>,
+        connection: &mut PgConnection,
+    ) -> Result<Token, AppError> {
+        diesel::update(tokens::table.find(token_id))
+            .set(tokens::expires_at.eq(expires_at.unwrap_or_else(Utc::now)))
+            .get_result::<Token>(connection)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("Token {} not found", token_id)))
+    }
+
+    // Revoking keeps the row, and the first revocation time, for the record
+    pub fn revoke(token_id: i64, connection: &mut PgConnection) -> Result<Token, AppError> {
+        diesel::update(
+            tokens::table
+                .find(token_id)
+                .filter(tokens::revoked_at.is_null()),
+        )
+        .set(tokens::revoked_at.eq(Utc::now()))
+        .execute(connection)?;
+        Token::find(token_id, connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_connection::establish_connection;
+    use serial_test::serial;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    #[serial]
+    fn test_token_lifecycle() {
+        let pool = establish_connection().expect("Failed to establish connection");
+        let mut connection = pool.get().expect("Failed to get connection");
+        diesel::delete(tokens::table.filter(tokens::name.eq("test-lifecycle")))
+            .execute(&mut connection)
+            .unwrap();
+
+        let (token, secret) = Token::create(
+            "test-lifecycle",
+            &strings(&["read", "write"]),
+            &strings(&["team-a/*"]),
+            None,
+            &mut connection,
+        )
+        .unwrap();
+        assert!(secret.starts_with(TOKEN_PREFIX));
+        assert_ne!(token.token_hash, secret);
+        assert!(token.is_active(Utc::now()));
+        assert!(token.has_scope(Scope::Write));
+        assert!(!token.has_scope(Scope::Delete));
+
+        let found = Token::find_by_hash(&Token::hash_secret(&secret), &mut connection)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, token.id);
+        assert!(
+            Token::find_by_hash(&Token::hash_secret("llt_wrong"), &mut connection)
+                .unwrap()
+                .is_none()
+        );
+
+        assert!(matches!(
+            Token::create(
+                "test-lifecycle",
+                &strings(&["read"]),
+                &strings(&["*"]),
+                None,
+                &mut connection
+            ),
+            Err(AppError::Conflict(_))
+        ));
+        for (scopes, namespaces) in [
+            (vec![], vec!["*"]),
+            (vec!["owner"], vec!["*"]),
+            (vec!["read"], vec![]),
+            (vec!["read"], vec![""]),
+        ] {
+            assert!(matches!(
+                Token::create(
+                    "test-invalid",
+                    &strings(&scopes),
+                    &strings(&namespaces),
+                    None,
+                    &mut connection
+                ),
+                Err(AppError::Validation(_))
+            ));
+        }
+
+        let expired = Token::expire(token.id, None, &mut connection).unwrap();
+        assert!(!expired.is_active(Utc::now()));
+
+        let revoked = Token::revoke(token.id, &mut connection).unwrap();
+        let revoked_at = revoked.revoked_at.unwrap();
+        let revoked = Token::revoke(token.id, &mut connection).unwrap();
+        assert_eq!(revoked.revoked_at, Some(revoked_at));
+
+        Token::expire(token.id, None, &mut connection).unwrap();
+        assert!(matches!(
+            Token::revoke(-1, &mut connection),
+            Err(AppError::NotFound(_))
+        ));
+
+        diesel::delete(tokens::table.find(token.id))
+            .execute(&mut connection)
+            .unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+use chrono::Utc;
+use log::{error, info};
+use std::time::Duration;
+
+use crate::db_connection::Pool;
+use crate::error::AppError;
+use crate::models::item::Item;
+
+// Reaps expired keys every `interval` for as long as the server runs
+pub async fn run(pool: Pool, interval: Duration, purge: bool) {
+    let mut ticker = actix_rt::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let pool = pool.clone();
+        match actix_rt::task::spawn_blocking(move || reap(&pool, purge)).await {
+            Ok(Ok(0)) => (),
+            Ok(Ok(reaped)) => info!("Reaped {} expired keys", reaped),
+            Ok(Err(e)) => error!("Failed to reap expired keys: {}", e),
+            Err(e) => error!("Reaper task failed: {}", e),
+        }
+    }
+}
+
+pub fn reap(pool: &Pool, purge: bool) -> Result<usize, AppError> {
+    let mut connection = pool.get()?;
+    Ok(Item::reap_expired(Utc::now(), purge, &mut connection)?)
+}
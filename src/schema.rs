@@ -4,5 +4,34 @@ table! {
         val -> Text,
         updated_at -> Timestamptz,
         namespace -> Text,
+        version -> Int8,
+        deleted -> Bool,
+        written_by -> Nullable<Text>,
+        expires_at -> Nullable<Timestamptz>,
+        source_ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        comment -> Nullable<Text>,
+        key_version -> Nullable<Int4>,
+    }
+}
+
+table! {
+    namespace_parents (namespace) {
+        namespace -> Text,
+        parent -> Text,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    tokens (id) {
+        id -> Int8,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Array<Text>,
+        namespaces -> Array<Text>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
     }
 }
@@ -9,10 +9,9 @@ pub fn get_database() -> String {
 }
 
 pub fn get_namespace(query_options_map: &HashMap<String, String>) -> &str {
-    match query_options_map.get("ns") {
-        Some(namespace) => return namespace.as_str(),
-        None => (),
-    };
+    if let Some(namespace) = query_options_map.get("ns") {
+        return namespace.as_str();
+    }
 
     match query_options_map.get("namespace") {
         Some(namespace) => namespace.as_str(),
@@ -31,21 +30,148 @@ pub fn get_pool_size_per_worker() -> u32 {
     }
 }
 
+pub fn get_max_value_size() -> usize {
+    let key = "LITTLE_LOOKUP_MAX_VALUE_BYTES";
+    match std::env::var(key) {
+        Ok(val) => val.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!(
+                "Warning: {} is not a valid usize, using default value 1048576",
+                key
+            );
+            1_048_576
+        }),
+        Err(_) => 1_048_576,
+    }
+}
+
+// Seconds between runs of the expired-key reaper; 0 disables it
+pub fn get_reaper_interval() -> u64 {
+    let key = "LITTLE_LOOKUP_REAPER_INTERVAL_SECS";
+    match std::env::var(key) {
+        Ok(val) => val.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!(
+                "Warning: {} is not a valid u64, using default value 60",
+                key
+            );
+            60
+        }),
+        Err(_) => 60,
+    }
+}
+
+// Whether the reaper purges expired keys instead of tombstoning them
+pub fn get_reaper_purge() -> bool {
+    let key = "LITTLE_LOOKUP_REAPER_MODE";
+    match std::env::var(key).as_deref() {
+        Ok("purge") => true,
+        Ok("tombstone") | Err(_) => false,
+        Ok(_) => {
+            eprintln!(
+                "Warning: {} must be tombstone or purge, using default value tombstone",
+                key
+            );
+            false
+        }
+    }
+}
+
+// Whether a PSK may be sent as the `psk` query parameter, where it ends up in access logs
+pub fn get_allow_query_psk() -> bool {
+    let key = "LITTLE_LOOKUP_ALLOW_QUERY_PSK";
+    match std::env::var(key).as_deref() {
+        Ok("true") | Ok("1") | Err(_) => true,
+        Ok("false") | Ok("0") => false,
+        Ok(_) => {
+            eprintln!(
+                "Warning: {} must be true or false, using default value true",
+                key
+            );
+            true
+        }
+    }
+}
+
+// Seconds a token lookup is reused before the tokens table is asked again
+pub fn get_token_cache_secs() -> u64 {
+    let key = "LITTLE_LOOKUP_TOKEN_CACHE_SECS";
+    match std::env::var(key) {
+        Ok(val) => val.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!(
+                "Warning: {} is not a valid u64, using default value 30",
+                key
+            );
+            30
+        }),
+        Err(_) => 30,
+    }
+}
+
+// Seconds a streamed listing may run before it is cut off; 0 lets it run as long as the
+// client keeps reading
+pub fn get_stream_timeout() -> u64 {
+    let key = "LITTLE_LOOKUP_STREAM_TIMEOUT_SECS";
+    match std::env::var(key) {
+        Ok(val) => val.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!(
+                "Warning: {} is not a valid u64, using default value 300",
+                key
+            );
+            300
+        }),
+        Err(_) => 300,
+    }
+}
+
+// Whether the audit log takes the client address from X-Forwarded-For; only safe behind a
+// proxy that overwrites that header
+pub fn get_trust_forwarded_for() -> bool {
+    let key = "LITTLE_LOOKUP_TRUST_FORWARDED_FOR";
+    match std::env::var(key).as_deref() {
+        Ok("true") | Ok("1") => true,
+        Ok("false") | Ok("0") | Err(_) => false,
+        Ok(_) => {
+            eprintln!(
+                "Warning: {} must be true or false, using default value false",
+                key
+            );
+            false
+        }
+    }
+}
+
 pub enum PSKType {
     READ,
     WRITE,
+    ADMIN,
 }
 
-pub fn get_psk(psk_type: PSKType) -> String {
+// The PSK for `namespace` from LITTLE_LOOKUP_PSK_<TYPE>_<NAMESPACE>, falling back to the
+// global LITTLE_LOOKUP_PSK_<TYPE> when that is unset or empty. None asks for the global PSK.
+pub fn get_psk(psk_type: PSKType, namespace: Option<&str>) -> String {
     let key = match psk_type {
         PSKType::READ => "LITTLE_LOOKUP_PSK_READ",
         PSKType::WRITE => "LITTLE_LOOKUP_PSK_WRITE",
+        PSKType::ADMIN => "LITTLE_LOOKUP_PSK_ADMIN",
     };
 
-    match std::env::var(key) {
-        Ok(val) => val,
-        Err(_) => String::from(""),
-    }
+    let namespace_psk = namespace
+        .and_then(|namespace| std::env::var(psk_env_var(key, namespace)).ok())
+        .filter(|psk| !psk.is_empty());
+    namespace_psk.unwrap_or_else(|| std::env::var(key).unwrap_or_default())
+}
+
+// Lowercase ASCII letters are uppercased and digits kept; every other byte becomes `_` and
+// two hex digits, so `team-a/api` reads LITTLE_LOOKUP_PSK_READ_TEAM_2DA_2FAPI. Escaping
+// uppercase letters and `_` too keeps distinct namespaces on distinct variables.
+pub fn psk_env_var(key: &str, namespace: &str) -> String {
+    let suffix: String = namespace
+        .bytes()
+        .map(|b| match b.is_ascii_lowercase() || b.is_ascii_digit() {
+            true => String::from(b.to_ascii_uppercase() as char),
+            false => format!("_{:02X}", b),
+        })
+        .collect();
+    format!("{}_{}", key, suffix)
 }
 
 pub fn get_worker_num() -> usize {
@@ -138,20 +264,130 @@ mod tests {
     }
 
     #[test]
+    fn test_get_max_value_size() {
+        // Test when the environment variable is set
+        std::env::set_var("LITTLE_LOOKUP_MAX_VALUE_BYTES", "2048");
+        assert_eq!(get_max_value_size(), 2048);
+
+        // Test with invalid value - should use default
+        std::env::set_var("LITTLE_LOOKUP_MAX_VALUE_BYTES", "lots");
+        assert_eq!(get_max_value_size(), 1_048_576);
+
+        // Test when the environment variable is not set
+        std::env::remove_var("LITTLE_LOOKUP_MAX_VALUE_BYTES");
+        assert_eq!(get_max_value_size(), 1_048_576);
+    }
+
+    #[test]
+    fn test_get_reaper_settings() {
+        std::env::set_var("LITTLE_LOOKUP_REAPER_INTERVAL_SECS", "5");
+        assert_eq!(get_reaper_interval(), 5);
+        std::env::set_var("LITTLE_LOOKUP_REAPER_INTERVAL_SECS", "soon");
+        assert_eq!(get_reaper_interval(), 60);
+        std::env::remove_var("LITTLE_LOOKUP_REAPER_INTERVAL_SECS");
+        assert_eq!(get_reaper_interval(), 60);
+
+        std::env::set_var("LITTLE_LOOKUP_REAPER_MODE", "purge");
+        assert!(get_reaper_purge());
+        std::env::set_var("LITTLE_LOOKUP_REAPER_MODE", "shred");
+        assert!(!get_reaper_purge());
+        std::env::remove_var("LITTLE_LOOKUP_REAPER_MODE");
+        assert!(!get_reaper_purge());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_get_allow_query_psk() {
+        std::env::set_var("LITTLE_LOOKUP_ALLOW_QUERY_PSK", "false");
+        assert!(!get_allow_query_psk());
+
+        std::env::set_var("LITTLE_LOOKUP_ALLOW_QUERY_PSK", "sometimes");
+        assert!(get_allow_query_psk());
+
+        std::env::remove_var("LITTLE_LOOKUP_ALLOW_QUERY_PSK");
+        assert!(get_allow_query_psk());
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_get_token_cache_secs() {
+        std::env::set_var("LITTLE_LOOKUP_TOKEN_CACHE_SECS", "5");
+        assert_eq!(get_token_cache_secs(), 5);
+
+        std::env::set_var("LITTLE_LOOKUP_TOKEN_CACHE_SECS", "soon");
+        assert_eq!(get_token_cache_secs(), 30);
+
+        std::env::remove_var("LITTLE_LOOKUP_TOKEN_CACHE_SECS");
+        assert_eq!(get_token_cache_secs(), 30);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_get_trust_forwarded_for() {
+        std::env::set_var("LITTLE_LOOKUP_TRUST_FORWARDED_FOR", "true");
+        assert!(get_trust_forwarded_for());
+
+        std::env::set_var("LITTLE_LOOKUP_TRUST_FORWARDED_FOR", "maybe");
+        assert!(!get_trust_forwarded_for());
+
+        std::env::remove_var("LITTLE_LOOKUP_TRUST_FORWARDED_FOR");
+        assert!(!get_trust_forwarded_for());
+    }
+
+    #[test]
+    #[serial_test::serial]
     fn test_get_psk() {
         // Test when the environment variable is set
         std::env::set_var("LITTLE_LOOKUP_PSK_READ", "test_read_psk");
-        assert_eq!(get_psk(PSKType::READ), "test_read_psk");
+        assert_eq!(get_psk(PSKType::READ, None), "test_read_psk");
 
         std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "test_write_psk");
-        assert_eq!(get_psk(PSKType::WRITE), "test_write_psk");
+        assert_eq!(get_psk(PSKType::WRITE, None), "test_write_psk");
 
         // Test when the environment variable is not set
         std::env::remove_var("LITTLE_LOOKUP_PSK_READ");
-        assert_eq!(get_psk(PSKType::READ), "");
+        assert_eq!(get_psk(PSKType::READ, None), "");
 
         std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
-        assert_eq!(get_psk(PSKType::WRITE), "");
+        assert_eq!(get_psk(PSKType::WRITE, None), "");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_get_namespace_psk() {
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE", "global_write");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE_TEAM_2DA_2FAPI", "team_a_write");
+        std::env::set_var("LITTLE_LOOKUP_PSK_WRITE_DEV", "");
+
+        assert_eq!(get_psk(PSKType::WRITE, Some("team-a/api")), "team_a_write");
+        // Unconfigured and empty namespace PSKs fall back to the global one
+        assert_eq!(get_psk(PSKType::WRITE, Some("staging")), "global_write");
+        assert_eq!(get_psk(PSKType::WRITE, Some("dev")), "global_write");
+        assert_eq!(get_psk(PSKType::WRITE, None), "global_write");
+        assert_eq!(get_psk(PSKType::READ, Some("team-a/api")), "");
+
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE_TEAM_2DA_2FAPI");
+        std::env::remove_var("LITTLE_LOOKUP_PSK_WRITE_DEV");
+    }
+
+    #[test]
+    fn test_psk_env_var() {
+        assert_eq!(
+            psk_env_var("LITTLE_LOOKUP_PSK_READ", "production"),
+            "LITTLE_LOOKUP_PSK_READ_PRODUCTION"
+        );
+        assert_eq!(
+            psk_env_var("LITTLE_LOOKUP_PSK_READ", "prod.eu-1/api"),
+            "LITTLE_LOOKUP_PSK_READ_PROD_2EEU_2D1_2FAPI"
+        );
+        // Namespaces that differ only in punctuation or case get their own variables
+        let variables: std::collections::HashSet<String> =
+            ["prod-eu", "prod.eu", "prod_eu", "PROD_EU", "Prod-EU"]
+                .iter()
+                .map(|namespace| psk_env_var("LITTLE_LOOKUP_PSK_READ", namespace))
+                .collect();
+        assert_eq!(variables.len(), 5);
     }
 
     #[test]